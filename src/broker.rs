@@ -0,0 +1,335 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::catalog::{self, Catalog};
+use crate::cluster_metadata::{ClusterMetadata, CLUSTER_METADATA_DIR};
+use crate::config::{self, Config};
+use crate::fetch_session::FetchSessionCache;
+use crate::log::{self, checkpoint, partition_metadata};
+use crate::purgatory::Purgatory;
+use crate::record::compression::CompressionType;
+
+pub type SharedPartitionLog = Arc<Mutex<log::PartitionLog>>;
+
+// State shared by every connection
+#[derive(Debug)]
+pub struct Broker {
+    pub node_id: i32,
+    // address advertised to clients in Metadata responses, from advertised.listeners
+    pub host: String,
+    pub port: i32,
+    pub cluster_id: String,
+    // requests over it close the connection, see `Request::read_frame`
+    pub socket_request_max_bytes: usize,
+    // used for the topics created automatically
+    pub default_partition_count: i32,
+    // topics can override it with their own compression.type
+    pub compression_type: CompressionType,
+    // levels of the features set in the metadata log, and the offset they were read up to
+    pub finalized_features: BTreeMap<String, i16>,
+    pub finalized_features_epoch: i64,
+    pub catalog: Mutex<Catalog>,
+    pub fetch_sessions: Mutex<FetchSessionCache>,
+    // fetches waiting for records
+    pub fetch_purgatory: Purgatory,
+    log_dir: PathBuf,
+    log_config: log::LogConfig,
+    metadata_log_dir: PathBuf,
+    logs: Mutex<HashMap<(String, i32), SharedPartitionLog>>,
+}
+
+impl Broker {
+    pub fn new(config: &Config) -> Broker {
+        Broker {
+            node_id: config.node_id,
+            host: config.advertised_listener.host.clone(),
+            port: config.advertised_listener.port as i32,
+            // replaced by the one of meta.properties, see `load_cluster_metadata`
+            cluster_id: "kafka-rust-cluster".to_string(),
+            socket_request_max_bytes: config.socket_request_max_bytes,
+            default_partition_count: config.num_partitions,
+            compression_type: config.compression_type,
+            finalized_features: BTreeMap::new(),
+            finalized_features_epoch: -1,
+            catalog: Mutex::new(Catalog::default()),
+            fetch_sessions: Mutex::new(FetchSessionCache::default()),
+            fetch_purgatory: Purgatory::default(),
+            log_dir: config.log_dir().to_path_buf(),
+            log_config: config.log_config.clone(),
+            metadata_log_dir: config.metadata_log_dir.clone(),
+            logs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Registers the topics and features of the KRaft metadata log, and takes the cluster id from
+    // the meta.properties written by `kafka-storage.sh format`
+    pub fn load_cluster_metadata(&mut self) -> Result<ClusterMetadata, Box<dyn Error>> {
+        let meta_properties = self.metadata_log_dir.join("meta.properties");
+        if meta_properties.exists() {
+            let properties = config::parse_properties(&fs::read_to_string(meta_properties)?);
+            if let Some(cluster_id) = properties.get("cluster.id") {
+                self.cluster_id = cluster_id.clone();
+            }
+        }
+
+        let cluster_metadata = ClusterMetadata::load(&self.metadata_log_dir)?;
+        let catalog = self.catalog.get_mut().unwrap();
+        for topic in cluster_metadata.topics() {
+            catalog.add_topic(topic);
+        }
+        self.finalized_features = cluster_metadata.features.clone();
+        self.finalized_features_epoch = cluster_metadata.last_offset;
+        Ok(cluster_metadata)
+    }
+
+    // Opens the partition logs of the log dir, after adding their topics to the catalog. Unless
+    // the previous run shut down cleanly they are recovered from the checkpointed recovery points,
+    // and the marker is removed so that the next crash is noticed too.
+    pub fn load_logs(&self) -> log::Result<usize> {
+        let clean_shutdown_file = self.log_dir.join(log::CLEAN_SHUTDOWN_FILE);
+        let had_clean_shutdown = clean_shutdown_file.exists();
+        let recovery_points =
+            checkpoint::read(&self.log_dir.join(log::RECOVERY_POINT_CHECKPOINT_FILE))?;
+
+        let entries = match fs::read_dir(&self.log_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        let mut partitions = vec![];
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() || entry.file_name() == CLUSTER_METADATA_DIR {
+                continue;
+            }
+            if let Some((topic, partition)) = entry.file_name().to_str().and_then(|name| {
+                let (topic, partition) = name.rsplit_once('-')?;
+                Some((topic.to_string(), partition.parse().ok()?))
+            }) {
+                partitions.push((topic, partition));
+            }
+        }
+        self.register_local_topics(&partitions)?;
+
+        for (topic, partition) in &partitions {
+            let Some(topic) = self.catalog.lock().unwrap().topic(topic).cloned() else {
+                continue;
+            };
+            let recovery_point = (!had_clean_shutdown).then(|| {
+                recovery_points
+                    .get(&(topic.name.clone(), *partition))
+                    .copied()
+                    .unwrap_or(0)
+            });
+            self.open_log(&topic, *partition, recovery_point)?;
+        }
+
+        self.checkpoint_recovery_points()?;
+        if had_clean_shutdown {
+            fs::remove_file(clean_shutdown_file)?;
+        }
+        Ok(partitions.len())
+    }
+
+    // The topics created by the broker are not in the metadata log, they are added back from
+    // their partition dirs with the id kept in them
+    fn register_local_topics(&self, partitions: &[(String, i32)]) -> log::Result<()> {
+        let mut catalog = self.catalog.lock().unwrap();
+        let mut local_topics: BTreeMap<&str, Vec<i32>> = BTreeMap::new();
+        for (topic, partition) in partitions {
+            if catalog.topic(topic).is_none() {
+                local_topics.entry(topic).or_default().push(*partition);
+            }
+        }
+        for (topic, partition_indexes) in local_topics {
+            let mut topic_id = None;
+            for &partition in &partition_indexes {
+                let dir = log::PartitionLog::dir(&self.log_dir, topic, partition);
+                topic_id = topic_id.or(partition_metadata::read(&dir)?);
+            }
+            // logs written before the ids were kept get a new one
+            let topic_id = match topic_id {
+                Some(topic_id) => topic_id,
+                None => catalog::random_uuid()?,
+            };
+            catalog.add_local_topic(topic, topic_id, partition_indexes, self.node_id);
+        }
+        Ok(())
+    }
+
+    // Flushes the logs and writes the clean shutdown marker, so that the logs are not recovered
+    // on the next start
+    pub fn shutdown(&self) -> log::Result<()> {
+        for log in self.logs.lock().unwrap().values() {
+            log.lock().unwrap().flush()?;
+        }
+        self.checkpoint_recovery_points()?;
+        File::create(self.log_dir.join(log::CLEAN_SHUTDOWN_FILE))?.sync_all()?;
+        Ok(())
+    }
+
+    pub fn partition_logs(&self) -> Vec<((String, i32), SharedPartitionLog)> {
+        self.logs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, log)| (key.clone(), log.clone()))
+            .collect()
+    }
+
+    pub fn log_dir(&self) -> &Path {
+        &self.log_dir
+    }
+
+    fn checkpoint_recovery_points(&self) -> log::Result<()> {
+        let recovery_points = self
+            .logs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, log)| (key.clone(), log.lock().unwrap().recovery_point()))
+            .collect();
+        fs::create_dir_all(&self.log_dir)?;
+        checkpoint::write(
+            &self.log_dir.join(log::RECOVERY_POINT_CHECKPOINT_FILE),
+            &recovery_points,
+        )?;
+        Ok(())
+    }
+
+    // Opens the log of the partition the first time it is used, with the topic configs
+    // overriding the broker ones
+    pub fn partition_log(
+        &self,
+        topic: &catalog::Topic,
+        partition: i32,
+    ) -> log::Result<SharedPartitionLog> {
+        self.open_log(topic, partition, None)
+    }
+
+    fn open_log(
+        &self,
+        topic: &catalog::Topic,
+        partition: i32,
+        recovery_point: Option<i64>,
+    ) -> log::Result<SharedPartitionLog> {
+        let mut logs = self.logs.lock().unwrap();
+        if let Some(log) = logs.get(&(topic.name.clone(), partition)) {
+            return Ok(log.clone());
+        }
+        let config = self.log_config.with_overrides(&topic.configs);
+        let log = match recovery_point {
+            Some(recovery_point) => log::PartitionLog::recover(
+                &self.log_dir,
+                &topic.name,
+                partition,
+                config,
+                recovery_point,
+            )?,
+            None => log::PartitionLog::open(&self.log_dir, &topic.name, partition, config)?,
+        };
+        // so that the topic keeps its id when it is added back on restart
+        let dir = log::PartitionLog::dir(&self.log_dir, &topic.name, partition);
+        if !dir.join(partition_metadata::FILE).exists() {
+            partition_metadata::write(&dir, topic.id)?;
+        }
+        let log = Arc::new(Mutex::new(log));
+        logs.insert((topic.name.clone(), partition), log.clone());
+        Ok(log)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    use crate::cluster_metadata::tests::*;
+    use crate::record::{self, tests::record_batch};
+    use std::io::Write;
+
+    // Broker storing its logs in a new temporary directory
+    pub fn test_broker() -> Broker {
+        Broker::new(&Config::new(log::tests::temp_log_dir()))
+    }
+
+    #[test]
+    fn test_load_cluster_metadata() {
+        let log_dir = log::tests::temp_log_dir();
+        write_metadata_log(
+            &log_dir,
+            &[metadata_batch(
+                0,
+                &[
+                    feature_level_record("metadata.version", 20),
+                    topic_record("foo", 17),
+                    partition_record(17, 0, 1),
+                ],
+            )],
+        );
+        fs::write(
+            log_dir.join("meta.properties"),
+            "version=1\ncluster.id=MkU3OEVBNTcwNTJENDM2Qk\nnode.id=1\n",
+        )
+        .unwrap();
+
+        let mut broker = Broker::new(&Config::new(&log_dir));
+        broker.load_cluster_metadata().unwrap();
+
+        assert_eq!(broker.cluster_id, "MkU3OEVBNTcwNTJENDM2Qk");
+        assert_eq!(broker.finalized_features["metadata.version"], 20);
+        assert_eq!(broker.finalized_features_epoch, 2);
+        let catalog = broker.catalog.lock().unwrap();
+        assert_eq!(catalog.topic_by_id(17).unwrap().name, "foo");
+        let topic = catalog.topic("foo").unwrap();
+        assert_eq!(topic.partition(0).unwrap().leader_id, 1);
+    }
+
+    #[test]
+    fn test_load_logs_should_recover_after_a_crash() {
+        let log_dir = log::tests::temp_log_dir();
+        let config = Config::new(&log_dir);
+        let broker = Broker::new(&config);
+        let topic_id = broker
+            .catalog
+            .lock()
+            .unwrap()
+            .create_topic("foo", 1, 1)
+            .unwrap()
+            .id;
+        // the topics created by the broker are found in the log dir
+        let topic = |broker: &Broker| broker.catalog.lock().unwrap().topic("foo").unwrap().clone();
+        let log = broker.partition_log(&topic(&broker), 0).unwrap();
+        log.lock().unwrap().append(&record_batch(2)).unwrap();
+        broker.shutdown().unwrap();
+        assert!(log_dir.join(log::CLEAN_SHUTDOWN_FILE).exists());
+
+        let broker = Broker::new(&config);
+        assert_eq!(broker.load_logs().unwrap(), 1);
+        assert!(!log_dir.join(log::CLEAN_SHUTDOWN_FILE).exists());
+        assert_eq!(topic(&broker).id, topic_id);
+        let log = broker.partition_log(&topic(&broker), 0).unwrap();
+        assert_eq!(log.lock().unwrap().log_end_offset(), 2);
+        log.lock().unwrap().append(&record_batch(1)).unwrap();
+
+        // the broker is killed before the last batch is flushed, which reaches the disk torn
+        let mut segment = fs::OpenOptions::new()
+            .append(true)
+            .open(log::PartitionLog::dir(&log_dir, "foo", 0).join("00000000000000000000.log"))
+            .unwrap();
+        let mut batch = record_batch(1);
+        batch[record::BASE_OFFSET + 7] = 3;
+        let last = batch.len() - 1;
+        batch[last] ^= 1;
+        segment.write_all(&batch).unwrap();
+        drop(broker);
+
+        let broker = Broker::new(&config);
+        assert_eq!(broker.load_logs().unwrap(), 1);
+        let log = broker.partition_log(&topic(&broker), 0).unwrap();
+        assert_eq!(log.lock().unwrap().log_end_offset(), 3);
+    }
+}
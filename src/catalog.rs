@@ -0,0 +1,208 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, Read};
+
+use crate::server::model::Uuid;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Topic {
+    pub name: String,
+    pub id: Uuid,
+    pub is_internal: bool,
+    pub replication_factor: i16,
+    // overrides of the broker defaults, e.g. retention.ms
+    pub configs: BTreeMap<String, String>,
+    pub partitions: Vec<Partition>,
+}
+
+impl Topic {
+    pub fn partition(&self, index: i32) -> Option<&Partition> {
+        self.partitions
+            .binary_search_by_key(&index, |partition| partition.index)
+            .ok()
+            .map(|position| &self.partitions[position])
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Partition {
+    pub index: i32,
+    pub leader_id: i32,
+    pub leader_epoch: i32,
+    pub replicas: Vec<i32>,
+    pub isr: Vec<i32>,
+    // replicas that can be elected without losing committed data, see KIP-966
+    pub eligible_leader_replicas: Vec<i32>,
+    pub last_known_elr: Vec<i32>,
+    pub offline_replicas: Vec<i32>,
+}
+
+// Registry of the topics hosted by the broker, ordered by name
+#[derive(Debug, Default)]
+pub struct Catalog {
+    topics: BTreeMap<String, Topic>,
+    names_by_id: HashMap<Uuid, String>,
+}
+
+impl Catalog {
+    pub fn topics(&self) -> impl Iterator<Item = &Topic> {
+        self.topics.values()
+    }
+
+    pub fn topic(&self, name: &str) -> Option<&Topic> {
+        self.topics.get(name)
+    }
+
+    pub fn topic_by_id(&self, id: Uuid) -> Option<&Topic> {
+        self.names_by_id
+            .get(&id)
+            .and_then(|name| self.topics.get(name))
+    }
+
+    // Adds or replaces a topic, its partitions must be sorted by index
+    pub fn add_topic(&mut self, topic: Topic) -> &Topic {
+        if let Some(previous) = self.topics.get(&topic.name) {
+            self.names_by_id.remove(&previous.id);
+        }
+        self.names_by_id.insert(topic.id, topic.name.clone());
+        let name = topic.name.clone();
+        self.topics.insert(name.clone(), topic);
+        &self.topics[&name]
+    }
+
+    // Creates a topic whose partitions are all led by `node_id`, the only replica
+    pub fn create_topic(
+        &mut self,
+        name: &str,
+        partition_count: i32,
+        node_id: i32,
+    ) -> io::Result<&Topic> {
+        if self.topics.contains_key(name) {
+            return Ok(&self.topics[name]);
+        }
+        let id = random_uuid()?;
+        Ok(self.add_local_topic(name, id, 0..partition_count, node_id))
+    }
+
+    // Adds a topic found in the log dir, whose partitions are all led by `node_id`
+    pub fn add_local_topic(
+        &mut self,
+        name: &str,
+        id: Uuid,
+        partition_indexes: impl IntoIterator<Item = i32>,
+        node_id: i32,
+    ) -> &Topic {
+        let mut partition_indexes: Vec<_> = partition_indexes.into_iter().collect();
+        partition_indexes.sort();
+        self.add_topic(Topic {
+            name: name.to_string(),
+            id,
+            is_internal: name.starts_with("__"),
+            replication_factor: 1,
+            configs: BTreeMap::new(),
+            partitions: partition_indexes
+                .into_iter()
+                .map(|index| Partition {
+                    index,
+                    leader_id: node_id,
+                    leader_epoch: 0,
+                    replicas: vec![node_id],
+                    isr: vec![node_id],
+                    eligible_leader_replicas: vec![],
+                    last_known_elr: vec![],
+                    offline_replicas: vec![],
+                })
+                .collect(),
+        })
+    }
+}
+
+// https://github.com/apache/kafka/blob/3.8/clients/src/main/java/org/apache/kafka/common/internals/Topic.java
+pub fn is_valid_topic_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 249
+        && name != "."
+        && name != ".."
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+}
+
+// Version 4 uuid, from the random bytes of the kernel
+// https://www.rfc-editor.org/rfc/rfc9562#name-uuid-version-4
+pub fn random_uuid() -> io::Result<Uuid> {
+    let mut bytes = [0; 16];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    let bits = Uuid::from_be_bytes(bytes);
+    let bits = (bits & !(0xf << 76)) | (0x4 << 76); // version
+    Ok((bits & !(0x3 << 62)) | (0x2 << 62)) // variant
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_topic() {
+        let mut catalog = Catalog::default();
+        let id = catalog.create_topic("foo", 2, 1).unwrap().id;
+        catalog.create_topic("__consumer_offsets", 1, 1).unwrap();
+
+        let topic = catalog.topic("foo").unwrap();
+        assert_eq!(topic.partitions.len(), 2);
+        assert_eq!(topic.partitions[1].replicas, vec![1]);
+        assert!(!topic.is_internal);
+        assert_eq!(catalog.topic_by_id(id), Some(topic));
+        assert!(catalog.topic("__consumer_offsets").unwrap().is_internal);
+        // the existing topic is kept
+        assert_eq!(catalog.create_topic("foo", 3, 1).unwrap().id, id);
+        let topic = catalog.topic("foo").unwrap();
+        assert!(topic.partition(1).is_some());
+        assert!(topic.partition(2).is_none());
+        assert!(topic.partition(-1).is_none());
+    }
+
+    #[test]
+    fn test_partition_should_look_partitions_up_by_index() {
+        let mut catalog = Catalog::default();
+        let mut topic = catalog.create_topic("foo", 3, 1).unwrap().clone();
+        // the metadata log can leave gaps, e.g. when a partition record is missing
+        topic.partitions.remove(1);
+
+        assert_eq!(topic.partition(2).unwrap().index, 2);
+        assert!(topic.partition(1).is_none());
+    }
+
+    #[test]
+    fn test_add_topic_should_replace_the_topic_with_the_same_name() {
+        let mut catalog = Catalog::default();
+        let previous_id = catalog.create_topic("foo", 1, 1).unwrap().id;
+        let mut topic = catalog.topic("foo").unwrap().clone();
+        topic.id = 42;
+        topic
+            .configs
+            .insert("cleanup.policy".to_string(), "compact".to_string());
+        catalog.add_topic(topic);
+
+        assert!(catalog.topic_by_id(previous_id).is_none());
+        let topic = catalog.topic_by_id(42).unwrap();
+        assert_eq!(topic.configs["cleanup.policy"], "compact");
+    }
+
+    #[test]
+    fn test_random_uuids_are_version_4() {
+        let id = random_uuid().unwrap();
+        assert_ne!(id, random_uuid().unwrap());
+        assert_eq!((id >> 76) & 0xf, 4);
+        assert_eq!((id >> 62) & 0x3, 2);
+    }
+
+    #[test]
+    fn test_is_valid_topic_name() {
+        assert!(is_valid_topic_name("foo.bar_baz-1"));
+        assert!(!is_valid_topic_name(""));
+        assert!(!is_valid_topic_name(".."));
+        assert!(!is_valid_topic_name("foo bar"));
+        assert!(!is_valid_topic_name(&"a".repeat(250)));
+    }
+}
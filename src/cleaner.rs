@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::{task, time};
+
+use crate::broker::{Broker, SharedPartitionLog};
+use crate::log::{self, checkpoint};
+use crate::retention;
+
+// Compacts the logs of the topics with cleanup.policy=compact, every `backoff`. Where the dirty
+// segments of each log start is kept in the cleaner checkpoint of the log dir.
+pub async fn run(broker: Arc<Broker>, backoff: Duration) {
+    let path = broker.log_dir().join(log::CLEANER_CHECKPOINT_FILE);
+    // the logs are cleaned from their start again without it
+    let mut first_dirty_offsets = checkpoint::read(&path).unwrap_or_else(|e| {
+        eprintln!("Error reading the cleaner checkpoint: {}", e);
+        checkpoint::Offsets::new()
+    });
+
+    let mut interval = time::interval(backoff);
+    loop {
+        interval.tick().await;
+        let pass_broker = broker.clone();
+        let offsets = first_dirty_offsets.clone();
+        // the segments are read and written, and the logs locked, with blocking calls
+        let cleaned =
+            task::spawn_blocking(move || clean_logs(&pass_broker, offsets, retention::now_ms()))
+                .await;
+        match cleaned {
+            Ok((offsets, deleted)) => {
+                let cleaned_any = !deleted.is_empty();
+                first_dirty_offsets = offsets;
+                for (files, delay) in deleted {
+                    tokio::spawn(retention::remove_later(files, delay));
+                }
+                if cleaned_any {
+                    if let Err(e) = checkpoint::write(&path, &first_dirty_offsets) {
+                        eprintln!("Error writing the cleaner checkpoint: {}", e);
+                    }
+                }
+            }
+            Err(e) => eprintln!("Error cleaning the logs: {}", e),
+        }
+    }
+}
+
+// Cleans each log, which is only locked to take its segments and then to swap in the compacted
+// ones. Returns the next first dirty offsets, and the replaced files of each cleaned log with
+// the delay after which they can be removed.
+fn clean_logs(
+    broker: &Broker,
+    mut first_dirty_offsets: checkpoint::Offsets,
+    now_ms: i64,
+) -> (checkpoint::Offsets, Vec<(Vec<PathBuf>, Duration)>) {
+    let mut deleted = vec![];
+    for (key, log) in broker.partition_logs() {
+        let first_dirty_offset = first_dirty_offsets.get(&key).copied();
+        match clean_log(&log, first_dirty_offset, now_ms) {
+            Ok(Some((next_dirty_offset, files, delay))) => {
+                first_dirty_offsets.insert(key, next_dirty_offset);
+                deleted.push((files, delay));
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("Error cleaning the log of {}-{}: {}", key.0, key.1, e),
+        }
+    }
+    (first_dirty_offsets, deleted)
+}
+
+fn clean_log(
+    log: &SharedPartitionLog,
+    first_dirty_offset: Option<i64>,
+    now_ms: i64,
+) -> log::Result<Option<(i64, Vec<PathBuf>, Duration)>> {
+    let cleaning = {
+        let log = log.lock().unwrap();
+        // the logs are cleaned from their start the first time
+        let first_dirty_offset =
+            first_dirty_offset.map_or(0, |offset| offset.max(log.log_start_offset()));
+        log.start_cleaning(first_dirty_offset)?
+    };
+    let Some(cleaning) = cleaning else {
+        return Ok(None);
+    };
+    let next_dirty_offset = cleaning.next_dirty_offset();
+    let cleaned = cleaning.clean(now_ms)?;
+    let mut log = log.lock().unwrap();
+    let files = log.replace_segments(cleaned)?;
+    Ok(Some((next_dirty_offset, files, log.file_delete_delay())))
+}
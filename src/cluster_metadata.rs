@@ -0,0 +1,547 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use bytes::Buf;
+
+use crate::catalog;
+use crate::record::{self, RecordBatch};
+use crate::server::codec;
+use crate::server::codec::Decoder;
+use crate::server::model::{TaggedFields, Uuid};
+
+// KRaft stores the cluster metadata as records in the log of this partition
+pub const CLUSTER_METADATA_DIR: &str = "__cluster_metadata-0";
+
+// https://github.com/apache/kafka/tree/3.8/metadata/src/main/resources/common/metadata
+#[derive(Debug, PartialEq)]
+pub enum MetadataRecord {
+    Topic(TopicRecord),
+    Partition(PartitionRecord),
+    Config(ConfigRecord),
+    PartitionChange(PartitionChangeRecord),
+    RemoveTopic(RemoveTopicRecord),
+    FeatureLevel(FeatureLevelRecord),
+    // records the broker does not need, like RegisterBrokerRecord
+    Other { record_type: u32 },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TopicRecord {
+    pub name: String,
+    pub topic_id: Uuid,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PartitionRecord {
+    pub partition_id: i32,
+    pub topic_id: Uuid,
+    pub replicas: Vec<i32>,
+    pub isr: Vec<i32>,
+    pub removing_replicas: Vec<i32>,
+    pub adding_replicas: Vec<i32>,
+    pub leader: i32,
+    pub leader_epoch: i32,
+    pub partition_epoch: i32,
+    pub directories: Vec<Uuid>,
+    pub eligible_leader_replicas: Vec<i32>,
+    pub last_known_elr: Vec<i32>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ConfigRecord {
+    pub resource_type: i8,
+    pub resource_name: String,
+    pub name: String,
+    // `None` removes the config
+    pub value: Option<String>,
+}
+
+// Only the fields that changed are set
+#[derive(Debug, PartialEq)]
+pub struct PartitionChangeRecord {
+    pub partition_id: i32,
+    pub topic_id: Uuid,
+    pub isr: Option<Vec<i32>>,
+    pub leader: Option<i32>,
+    pub replicas: Option<Vec<i32>>,
+    pub eligible_leader_replicas: Option<Vec<i32>>,
+    pub last_known_elr: Option<Vec<i32>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RemoveTopicRecord {
+    pub topic_id: Uuid,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FeatureLevelRecord {
+    pub name: String,
+    pub feature_level: i16,
+}
+
+const TOPIC_RECORD: u32 = 2;
+const PARTITION_RECORD: u32 = 3;
+const CONFIG_RECORD: u32 = 4;
+const PARTITION_CHANGE_RECORD: u32 = 5;
+const REMOVE_TOPIC_RECORD: u32 = 9;
+const FEATURE_LEVEL_RECORD: u32 = 12;
+
+const TOPIC_RESOURCE_TYPE: i8 = 2;
+
+// PartitionRecord tags
+const PARTITION_ELR_TAG: u32 = 1;
+const PARTITION_LAST_KNOWN_ELR_TAG: u32 = 2;
+
+// PartitionChangeRecord tags
+const ISR_TAG: u32 = 0;
+const LEADER_TAG: u32 = 1;
+const REPLICAS_TAG: u32 = 2;
+const ELR_TAG: u32 = 6;
+const LAST_KNOWN_ELR_TAG: u32 = 7;
+// leader of a PartitionChangeRecord when it does not change
+const NO_LEADER_CHANGE: i32 = -2;
+
+impl MetadataRecord {
+    // Parses the value of a record of the metadata log, every metadata record is flexible
+    pub fn parse<B: Buf>(buffer: &mut B) -> codec::Result<MetadataRecord> {
+        let _frame_version = buffer.read_unsigned_varint()?;
+        let record_type = buffer.read_unsigned_varint()?;
+        let version = buffer.read_unsigned_varint()?;
+
+        let record = match record_type {
+            TOPIC_RECORD => MetadataRecord::Topic(TopicRecord {
+                name: buffer.read_compact_string()?,
+                topic_id: buffer.read_uuid()?,
+            }),
+            PARTITION_RECORD => {
+                let partition_id = buffer.read_int32()?;
+                let topic_id = buffer.read_uuid()?;
+                let replicas = read_nodes(buffer)?;
+                let isr = read_nodes(buffer)?;
+                let removing_replicas = read_nodes(buffer)?;
+                let adding_replicas = read_nodes(buffer)?;
+                let leader = buffer.read_int32()?;
+                let leader_epoch = buffer.read_int32()?;
+                let partition_epoch = buffer.read_int32()?;
+                let directories = if version >= 1 {
+                    buffer.read_compact_array(|buffer| buffer.read_uuid())?
+                } else {
+                    vec![]
+                };
+                let mut tagged_fields = TaggedFields::read(buffer)?;
+                return Ok(MetadataRecord::Partition(PartitionRecord {
+                    partition_id,
+                    topic_id,
+                    replicas,
+                    isr,
+                    removing_replicas,
+                    adding_replicas,
+                    leader,
+                    leader_epoch,
+                    partition_epoch,
+                    directories,
+                    eligible_leader_replicas: tagged_fields
+                        .take(PARTITION_ELR_TAG, read_nodes)?
+                        .unwrap_or_default(),
+                    last_known_elr: tagged_fields
+                        .take(PARTITION_LAST_KNOWN_ELR_TAG, read_nodes)?
+                        .unwrap_or_default(),
+                }));
+            }
+            CONFIG_RECORD => MetadataRecord::Config(ConfigRecord {
+                resource_type: buffer.read_int8()?,
+                resource_name: buffer.read_compact_string()?,
+                name: buffer.read_compact_string()?,
+                value: buffer.read_compact_nullable_string()?,
+            }),
+            PARTITION_CHANGE_RECORD => {
+                let partition_id = buffer.read_int32()?;
+                let topic_id = buffer.read_uuid()?;
+                let mut tagged_fields = TaggedFields::read(buffer)?;
+                return Ok(MetadataRecord::PartitionChange(PartitionChangeRecord {
+                    partition_id,
+                    topic_id,
+                    isr: tagged_fields.take(ISR_TAG, read_nodes)?,
+                    leader: tagged_fields
+                        .take(LEADER_TAG, |buffer| buffer.read_int32())?
+                        .filter(|&leader| leader != NO_LEADER_CHANGE),
+                    replicas: tagged_fields.take(REPLICAS_TAG, read_nodes)?,
+                    eligible_leader_replicas: tagged_fields.take(ELR_TAG, read_nodes)?,
+                    last_known_elr: tagged_fields.take(LAST_KNOWN_ELR_TAG, read_nodes)?,
+                }));
+            }
+            REMOVE_TOPIC_RECORD => MetadataRecord::RemoveTopic(RemoveTopicRecord {
+                topic_id: buffer.read_uuid()?,
+            }),
+            FEATURE_LEVEL_RECORD => MetadataRecord::FeatureLevel(FeatureLevelRecord {
+                name: buffer.read_compact_string()?,
+                feature_level: buffer.read_int16()?,
+            }),
+            record_type => return Ok(MetadataRecord::Other { record_type }),
+        };
+        TaggedFields::read(buffer)?;
+        Ok(record)
+    }
+}
+
+fn read_nodes<B: Buf>(buffer: &mut B) -> codec::Result<Vec<i32>> {
+    buffer.read_compact_array(|buffer| buffer.read_int32())
+}
+
+#[derive(Debug, Default)]
+struct TopicState {
+    name: String,
+    partitions: BTreeMap<i32, PartitionRecord>,
+    configs: BTreeMap<String, String>,
+}
+
+// State of the cluster, rebuilt by replaying the metadata log
+#[derive(Debug, Default)]
+pub struct ClusterMetadata {
+    topics: BTreeMap<Uuid, TopicState>,
+    pub features: BTreeMap<String, i16>,
+    // offset of the last record replayed, -1 if there is none
+    pub last_offset: i64,
+}
+
+impl ClusterMetadata {
+    // Replays the segments of the metadata log found in `log_dir`, if any
+    pub fn load(log_dir: &Path) -> Result<ClusterMetadata, Box<dyn Error>> {
+        let mut cluster_metadata = ClusterMetadata {
+            last_offset: -1,
+            ..ClusterMetadata::default()
+        };
+        let dir = log_dir.join(CLUSTER_METADATA_DIR);
+        if !dir.is_dir() {
+            return Ok(cluster_metadata);
+        }
+
+        // segments are named by base offset, padded so that they sort by name
+        let mut segments = fs::read_dir(&dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        segments.retain(|path| path.extension().is_some_and(|extension| extension == "log"));
+        segments.sort();
+
+        for segment in segments {
+            let data = fs::read(&segment)?;
+            for RecordValue { offset, value } in read_record_values(&data)
+                .map_err(|e| format!("invalid metadata log {}: {}", segment.display(), e))?
+            {
+                let record = MetadataRecord::parse(&mut value.as_slice())
+                    .map_err(|e| format!("invalid metadata record at offset {}: {}", offset, e))?;
+                cluster_metadata.replay(record);
+                cluster_metadata.last_offset = offset;
+            }
+        }
+        Ok(cluster_metadata)
+    }
+
+    pub fn replay(&mut self, record: MetadataRecord) {
+        match record {
+            MetadataRecord::Topic(topic) => {
+                self.topics.entry(topic.topic_id).or_default().name = topic.name;
+            }
+            MetadataRecord::Partition(partition) => {
+                self.topics
+                    .entry(partition.topic_id)
+                    .or_default()
+                    .partitions
+                    .insert(partition.partition_id, partition);
+            }
+            MetadataRecord::Config(config) if config.resource_type == TOPIC_RESOURCE_TYPE => {
+                let Some(topic) = self
+                    .topics
+                    .values_mut()
+                    .find(|topic| topic.name == config.resource_name)
+                else {
+                    return;
+                };
+                match config.value {
+                    Some(value) => topic.configs.insert(config.name, value),
+                    None => topic.configs.remove(&config.name),
+                };
+            }
+            MetadataRecord::PartitionChange(change) => {
+                let Some(partition) = self
+                    .topics
+                    .get_mut(&change.topic_id)
+                    .and_then(|topic| topic.partitions.get_mut(&change.partition_id))
+                else {
+                    return;
+                };
+                if let Some(isr) = change.isr {
+                    partition.isr = isr;
+                }
+                if let Some(replicas) = change.replicas {
+                    partition.replicas = replicas;
+                }
+                if let Some(eligible_leader_replicas) = change.eligible_leader_replicas {
+                    partition.eligible_leader_replicas = eligible_leader_replicas;
+                }
+                if let Some(last_known_elr) = change.last_known_elr {
+                    partition.last_known_elr = last_known_elr;
+                }
+                // the controller bumps the leader epoch on every leader change
+                if let Some(leader) = change.leader {
+                    partition.leader = leader;
+                    partition.leader_epoch += 1;
+                }
+                partition.partition_epoch += 1;
+            }
+            MetadataRecord::RemoveTopic(remove_topic) => {
+                self.topics.remove(&remove_topic.topic_id);
+            }
+            MetadataRecord::FeatureLevel(feature_level) => {
+                self.features
+                    .insert(feature_level.name, feature_level.feature_level);
+            }
+            MetadataRecord::Config(_) | MetadataRecord::Other { .. } => {}
+        }
+    }
+
+    pub fn topics(&self) -> Vec<catalog::Topic> {
+        self.topics
+            .iter()
+            .filter(|(_, topic)| !topic.name.is_empty())
+            .map(|(&id, topic)| catalog::Topic {
+                name: topic.name.clone(),
+                id,
+                is_internal: topic.name.starts_with("__"),
+                replication_factor: topic
+                    .partitions
+                    .values()
+                    .next()
+                    .map_or(0, |partition| partition.replicas.len() as i16),
+                configs: topic.configs.clone(),
+                partitions: topic
+                    .partitions
+                    .values()
+                    .map(|partition| catalog::Partition {
+                        index: partition.partition_id,
+                        leader_id: partition.leader,
+                        leader_epoch: partition.leader_epoch,
+                        replicas: partition.replicas.clone(),
+                        isr: partition.isr.clone(),
+                        eligible_leader_replicas: partition.eligible_leader_replicas.clone(),
+                        last_known_elr: partition.last_known_elr.clone(),
+                        offline_replicas: vec![],
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+struct RecordValue {
+    offset: i64,
+    value: Vec<u8>,
+}
+
+// Returns the offset and value of every record of the data batches, a batch cut at the end of
+// the segment is ignored
+fn read_record_values(mut data: &[u8]) -> record::Result<Vec<RecordValue>> {
+    let mut values = vec![];
+    while record::batch_size(data).is_some() {
+        let batch = RecordBatch::read(&mut data)?;
+        if batch.is_control() {
+            continue;
+        }
+        for record in batch.records {
+            if let Some(value) = record.value {
+                values.push(RecordValue {
+                    offset: batch.base_offset + record.offset_delta as i64,
+                    value,
+                });
+            }
+        }
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    use bytes::BufMut;
+
+    use crate::server::codec::Encoder;
+
+    pub fn metadata_batch(base_offset: i64, values: &[Vec<u8>]) -> Vec<u8> {
+        let records = values
+            .iter()
+            .enumerate()
+            .map(|(offset_delta, value)| record::tests::record(offset_delta as i32, value))
+            .collect();
+        let mut buffer = vec![];
+        record::tests::batch(base_offset, records).write(&mut buffer);
+        buffer
+    }
+
+    fn record_header(buffer: &mut Vec<u8>, record_type: u32, version: u32) {
+        buffer.write_unsigned_varint(1); // frame version
+        buffer.write_unsigned_varint(record_type);
+        buffer.write_unsigned_varint(version);
+    }
+
+    pub fn topic_record(name: &str, topic_id: Uuid) -> Vec<u8> {
+        let mut buffer = vec![];
+        record_header(&mut buffer, TOPIC_RECORD, 0);
+        buffer.write_compact_string(name);
+        buffer.write_uuid(topic_id);
+        buffer.write_unsigned_varint(0);
+        buffer
+    }
+
+    pub fn partition_record(topic_id: Uuid, partition_id: i32, leader: i32) -> Vec<u8> {
+        let mut buffer = vec![];
+        record_header(&mut buffer, PARTITION_RECORD, 1);
+        buffer.put_i32(partition_id);
+        buffer.write_uuid(topic_id);
+        for nodes in [vec![leader], vec![leader], vec![], vec![]] {
+            buffer.write_compact_array(&nodes, |buffer, node| buffer.put_i32(*node));
+        }
+        buffer.put_i32(leader);
+        buffer.put_i32(0); // leader epoch
+        buffer.put_i32(0); // partition epoch
+        buffer.write_compact_array(&[7u128], |buffer, directory| buffer.write_uuid(*directory));
+        buffer.write_unsigned_varint(0);
+        buffer
+    }
+
+    pub fn feature_level_record(name: &str, feature_level: i16) -> Vec<u8> {
+        let mut buffer = vec![];
+        record_header(&mut buffer, FEATURE_LEVEL_RECORD, 0);
+        buffer.write_compact_string(name);
+        buffer.put_i16(feature_level);
+        buffer.write_unsigned_varint(0);
+        buffer
+    }
+
+    fn config_record(topic: &str, name: &str, value: Option<&str>) -> Vec<u8> {
+        let mut buffer = vec![];
+        record_header(&mut buffer, CONFIG_RECORD, 0);
+        buffer.put_i8(TOPIC_RESOURCE_TYPE);
+        buffer.write_compact_string(topic);
+        buffer.write_compact_string(name);
+        buffer.write_compact_nullable_string(value);
+        buffer.write_unsigned_varint(0);
+        buffer
+    }
+
+    fn partition_change_record(topic_id: Uuid, partition_id: i32, leader: i32) -> Vec<u8> {
+        let mut buffer = vec![];
+        record_header(&mut buffer, PARTITION_CHANGE_RECORD, 0);
+        buffer.put_i32(partition_id);
+        buffer.write_uuid(topic_id);
+        let mut tagged_fields = TaggedFields::default();
+        tagged_fields.insert(LEADER_TAG, |buffer| buffer.put_i32(leader));
+        tagged_fields.insert(ISR_TAG, |buffer| {
+            buffer.write_compact_array(&[leader], |buffer, node| buffer.put_i32(*node))
+        });
+        tagged_fields.insert(ELR_TAG, |buffer| {
+            buffer.write_compact_array(&[1], |buffer, node| buffer.put_i32(*node))
+        });
+        tagged_fields.write(&mut buffer);
+        buffer
+    }
+
+    fn remove_topic_record(topic_id: Uuid) -> Vec<u8> {
+        let mut buffer = vec![];
+        record_header(&mut buffer, REMOVE_TOPIC_RECORD, 0);
+        buffer.write_uuid(topic_id);
+        buffer.write_unsigned_varint(0);
+        buffer
+    }
+
+    pub fn write_metadata_log(log_dir: &Path, batches: &[Vec<u8>]) {
+        let dir = log_dir.join(CLUSTER_METADATA_DIR);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("00000000000000000000.log"), batches.concat()).unwrap();
+    }
+
+    #[test]
+    fn test_parse_partition_record() {
+        let record = MetadataRecord::parse(&mut partition_record(17, 2, 1).as_slice());
+        assert_eq!(
+            record,
+            Ok(MetadataRecord::Partition(PartitionRecord {
+                partition_id: 2,
+                topic_id: 17,
+                replicas: vec![1],
+                isr: vec![1],
+                removing_replicas: vec![],
+                adding_replicas: vec![],
+                leader: 1,
+                leader_epoch: 0,
+                partition_epoch: 0,
+                directories: vec![7],
+                eligible_leader_replicas: vec![],
+                last_known_elr: vec![],
+            }))
+        );
+    }
+
+    #[test]
+    fn test_load_should_replay_the_metadata_log() {
+        let log_dir = crate::log::tests::temp_log_dir();
+        write_metadata_log(
+            &log_dir,
+            &[
+                metadata_batch(0, &[feature_level_record("metadata.version", 20)]),
+                metadata_batch(
+                    1,
+                    &[
+                        topic_record("foo", 17),
+                        partition_record(17, 0, 1),
+                        partition_record(17, 1, 1),
+                        topic_record("bar", 18),
+                        partition_record(18, 0, 1),
+                        config_record("foo", "cleanup.policy", Some("compact")),
+                    ],
+                ),
+                metadata_batch(
+                    7,
+                    &[partition_change_record(17, 1, 2), remove_topic_record(18)],
+                ),
+            ],
+        );
+
+        let cluster_metadata = ClusterMetadata::load(&log_dir).unwrap();
+        assert_eq!(cluster_metadata.features["metadata.version"], 20);
+        assert_eq!(cluster_metadata.last_offset, 8);
+
+        let topics = cluster_metadata.topics();
+        assert_eq!(topics.len(), 1);
+        assert_eq!(topics[0].name, "foo");
+        assert_eq!(topics[0].id, 17);
+        assert_eq!(topics[0].replication_factor, 1);
+        assert_eq!(topics[0].configs["cleanup.policy"], "compact");
+        assert_eq!(topics[0].partitions[1].leader_id, 2);
+        assert_eq!(topics[0].partitions[1].leader_epoch, 1);
+        assert_eq!(topics[0].partitions[1].isr, vec![2]);
+        assert_eq!(topics[0].partitions[1].eligible_leader_replicas, vec![1]);
+    }
+
+    #[test]
+    fn test_load_without_metadata_log() {
+        let cluster_metadata = ClusterMetadata::load(&crate::log::tests::temp_log_dir()).unwrap();
+        assert_eq!(cluster_metadata.last_offset, -1);
+        assert!(cluster_metadata.topics().is_empty());
+    }
+
+    #[test]
+    fn test_load_should_ignore_a_truncated_batch() {
+        let log_dir = crate::log::tests::temp_log_dir();
+        let batch = metadata_batch(0, &[topic_record("foo", 17)]);
+        write_metadata_log(
+            &log_dir,
+            &[batch.clone(), batch[..batch.len() - 1].to_vec()],
+        );
+
+        let cluster_metadata = ClusterMetadata::load(&log_dir).unwrap();
+        assert_eq!(cluster_metadata.last_offset, 0);
+    }
+}
@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::log::LogConfig;
+use crate::record::compression::CompressionType;
+
+// Broker settings, read from a Kafka server.properties file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub node_id: i32,
+    // the listener clients connect to, from listeners, and the address Metadata advertises for
+    // it, from advertised.listeners
+    pub listener: Listener,
+    pub advertised_listener: Listener,
+    pub log_dirs: Vec<PathBuf>,
+    // where the KRaft `__cluster_metadata` log is, the first log dir by default
+    pub metadata_log_dir: PathBuf,
+    pub num_partitions: i32,
+    // largest request accepted, larger ones close the connection
+    pub socket_request_max_bytes: usize,
+    // codec of the appended batches, unless a topic overrides it
+    pub compression_type: CompressionType,
+    // defaults of the partition logs, e.g. log.segment.bytes
+    pub log_config: LogConfig,
+    // how often the segments past the retention of their topic are deleted
+    pub log_retention_check_interval_ms: u64,
+    // whether the topics with cleanup.policy=compact are compacted, and how often
+    pub log_cleaner_enable: bool,
+    pub log_cleaner_backoff_ms: u64,
+}
+
+impl Config {
+    pub fn new(log_dir: impl Into<PathBuf>) -> Config {
+        let log_dir = log_dir.into();
+        Config {
+            node_id: 1,
+            listener: Listener::new("PLAINTEXT", "127.0.0.1", 9092),
+            advertised_listener: Listener::new("PLAINTEXT", "localhost", 9092),
+            log_dirs: vec![log_dir.clone()],
+            metadata_log_dir: log_dir,
+            num_partitions: 1,
+            socket_request_max_bytes: 100 * 1024 * 1024,
+            compression_type: CompressionType::Producer,
+            log_config: LogConfig::default(),
+            log_retention_check_interval_ms: 5 * 60 * 1000,
+            log_cleaner_enable: true,
+            log_cleaner_backoff_ms: 15_000,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Config, Box<dyn Error>> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(content: &str) -> Result<Config, Box<dyn Error>> {
+        let properties = parse_properties(content);
+        let mut config = Config::default();
+
+        // log.dirs takes precedence over log.dir
+        if let Some(log_dirs) = properties
+            .get("log.dirs")
+            .or_else(|| properties.get("log.dir"))
+        {
+            config.log_dirs = log_dirs
+                .split(',')
+                .map(str::trim)
+                .filter(|log_dir| !log_dir.is_empty())
+                .map(PathBuf::from)
+                .collect();
+            if config.log_dirs.is_empty() {
+                return Err("log.dirs is empty".into());
+            }
+        }
+        config.metadata_log_dir = match properties.get("metadata.log.dir") {
+            Some(metadata_log_dir) => PathBuf::from(metadata_log_dir),
+            None => config.log_dirs[0].clone(),
+        };
+        if let Some(node_id) = properties.get("node.id") {
+            config.node_id = node_id.parse()?;
+        }
+        // the KRaft controller listeners are not served
+        let controller_listeners: Vec<_> = properties
+            .get("controller.listener.names")
+            .map_or(vec![], |names| names.split(',').map(str::trim).collect());
+        if let Some(listeners) = properties.get("listeners") {
+            config.listener = parse_listeners(listeners)?
+                .into_iter()
+                .find(|listener| !controller_listeners.contains(&listener.name.as_str()))
+                .ok_or("listeners has no listener for clients")?;
+            // like Kafka, the listeners are advertised as they are by default
+            config.advertised_listener = config.listener.clone();
+            if config.advertised_listener.host.is_empty() {
+                config.advertised_listener.host = "localhost".to_string();
+            }
+        }
+        if let Some(advertised_listeners) = properties.get("advertised.listeners") {
+            config.advertised_listener = parse_listeners(advertised_listeners)?
+                .into_iter()
+                .find(|listener| listener.name == config.listener.name)
+                .ok_or_else(|| {
+                    format!(
+                        "advertised.listeners has no {} listener",
+                        config.listener.name
+                    )
+                })?;
+        }
+        if let Some(num_partitions) = properties.get("num.partitions") {
+            config.num_partitions = num_partitions.parse()?;
+        }
+        if let Some(max_bytes) = properties.get("socket.request.max.bytes") {
+            config.socket_request_max_bytes = max_bytes.parse()?;
+        }
+        if let Some(compression_type) = properties.get("compression.type") {
+            config.compression_type = compression_type.parse()?;
+        }
+        if let Some(segment_bytes) = properties.get("log.segment.bytes") {
+            config.log_config.segment_bytes = segment_bytes.parse()?;
+        }
+        if let Some(roll_ms) = properties.get("log.roll.ms") {
+            config.log_config.segment_ms = roll_ms.parse()?;
+        } else if let Some(roll_hours) = properties.get("log.roll.hours") {
+            config.log_config.segment_ms = roll_hours.parse::<i64>()? * 60 * 60 * 1000;
+        }
+        // log.retention.ms takes precedence over log.retention.minutes and log.retention.hours
+        if let Some(retention_ms) = properties.get("log.retention.ms") {
+            config.log_config.retention_ms = retention_ms.parse()?;
+        } else if let Some(retention_minutes) = properties.get("log.retention.minutes") {
+            config.log_config.retention_ms = retention_minutes.parse::<i64>()? * 60 * 1000;
+        } else if let Some(retention_hours) = properties.get("log.retention.hours") {
+            config.log_config.retention_ms = retention_hours.parse::<i64>()? * 60 * 60 * 1000;
+        }
+        if let Some(retention_bytes) = properties.get("log.retention.bytes") {
+            config.log_config.retention_bytes = retention_bytes.parse()?;
+        }
+        if let Some(delete_delay_ms) = properties.get("log.segment.delete.delay.ms") {
+            config.log_config.file_delete_delay_ms = delete_delay_ms.parse()?;
+        }
+        if let Some(check_interval_ms) = properties.get("log.retention.check.interval.ms") {
+            config.log_retention_check_interval_ms = check_interval_ms.parse()?;
+        }
+        if let Some(cleanup_policy) = properties.get("log.cleanup.policy") {
+            config.log_config.cleanup_policy = cleanup_policy.parse()?;
+        }
+        if let Some(delete_retention_ms) = properties.get("log.cleaner.delete.retention.ms") {
+            config.log_config.delete_retention_ms = delete_retention_ms.parse()?;
+        }
+        if let Some(min_cleanable_ratio) = properties.get("log.cleaner.min.cleanable.ratio") {
+            config.log_config.min_cleanable_dirty_ratio = min_cleanable_ratio.parse()?;
+        }
+        if let Some(cleaner_enable) = properties.get("log.cleaner.enable") {
+            config.log_cleaner_enable = cleaner_enable.parse()?;
+        }
+        if let Some(cleaner_backoff_ms) = properties.get("log.cleaner.backoff.ms") {
+            config.log_cleaner_backoff_ms = cleaner_backoff_ms.parse()?;
+        }
+        if let Some(index_interval_bytes) = properties.get("log.index.interval.bytes") {
+            config.log_config.index_interval_bytes = index_interval_bytes.parse()?;
+        }
+        if let Some(max_index_bytes) = properties.get("log.index.size.max.bytes") {
+            config.log_config.max_index_bytes = max_index_bytes.parse()?;
+        }
+        Ok(config)
+    }
+
+    // Partitions are stored in the first log dir
+    pub fn log_dir(&self) -> &Path {
+        &self.log_dirs[0]
+    }
+
+    // A listener without host binds every interface, like in Kafka
+    pub fn bind_address(&self) -> String {
+        let host = match self.listener.host.as_str() {
+            "" => "0.0.0.0",
+            host => host,
+        };
+        format!("{}:{}", host, self.listener.port)
+    }
+}
+
+// A listener of the listeners and advertised.listeners properties, like PLAINTEXT://:9092
+#[derive(Clone, Debug, PartialEq)]
+pub struct Listener {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl Listener {
+    pub fn new(name: &str, host: &str, port: u16) -> Listener {
+        Listener {
+            name: name.to_string(),
+            host: host.to_string(),
+            port,
+        }
+    }
+}
+
+fn parse_listeners(value: &str) -> Result<Vec<Listener>, Box<dyn Error>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|listener| !listener.is_empty())
+        .map(|listener| {
+            let invalid = || format!("invalid listener {}", listener);
+            let (name, address) = listener.split_once("://").ok_or_else(invalid)?;
+            let (host, port) = address.rsplit_once(':').ok_or_else(invalid)?;
+            Ok(Listener::new(
+                name,
+                host,
+                port.parse().map_err(|_| invalid())?,
+            ))
+        })
+        .collect()
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config::new("/tmp/kraft-combined-logs")
+    }
+}
+
+// https://docs.oracle.com/javase/8/docs/api/java/util/Properties.html#load-java.io.Reader-
+// Escapes and line continuations are not supported, Kafka configs do not need them
+pub fn parse_properties(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('!'))
+        .filter_map(|line| {
+            let (key, value) = line.split_once(['=', ':'])?;
+            Some((key.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::log::CleanupPolicy;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            "# comment\n\
+             process.roles=broker,controller\n\
+             node.id = 2\n\
+             listeners=PLAINTEXT://:9192,CONTROLLER://:9193\n\
+             advertised.listeners=PLAINTEXT://broker-2:9192\n\
+             controller.listener.names=CONTROLLER\n\
+             log.dirs=/tmp/a, /tmp/b\n\
+             num.partitions=3\n\
+             socket.request.max.bytes=1048576\n\
+             compression.type=uncompressed\n\
+             log.segment.bytes=1048576\n\
+             log.index.interval.bytes=1024\n\
+             log.retention.hours=24\n\
+             log.retention.bytes=1073741824\n\
+             log.retention.check.interval.ms=1000\n\
+             log.cleanup.policy=compact,delete\n\
+             log.cleaner.enable=false\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                node_id: 2,
+                listener: Listener::new("PLAINTEXT", "", 9192),
+                advertised_listener: Listener::new("PLAINTEXT", "broker-2", 9192),
+                log_dirs: vec![PathBuf::from("/tmp/a"), PathBuf::from("/tmp/b")],
+                metadata_log_dir: PathBuf::from("/tmp/a"),
+                num_partitions: 3,
+                socket_request_max_bytes: 1048576,
+                compression_type: CompressionType::Uncompressed,
+                log_config: LogConfig {
+                    segment_bytes: 1048576,
+                    index_interval_bytes: 1024,
+                    retention_ms: 24 * 60 * 60 * 1000,
+                    retention_bytes: 1073741824,
+                    cleanup_policy: CleanupPolicy {
+                        delete: true,
+                        compact: true,
+                    },
+                    ..LogConfig::default()
+                },
+                log_retention_check_interval_ms: 1000,
+                log_cleaner_enable: false,
+                ..Config::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_should_use_defaults() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+
+        let config = Config::parse("log.dir=/tmp/a\nmetadata.log.dir=/tmp/m").unwrap();
+        assert_eq!(config.log_dir(), Path::new("/tmp/a"));
+        assert_eq!(config.metadata_log_dir, PathBuf::from("/tmp/m"));
+        assert_eq!(config.bind_address(), "127.0.0.1:9092");
+
+        // the listener is advertised as it is, with localhost for every interface
+        let config = Config::parse("listeners=PLAINTEXT://:9192").unwrap();
+        assert_eq!(config.bind_address(), "0.0.0.0:9192");
+        assert_eq!(
+            config.advertised_listener,
+            Listener::new("PLAINTEXT", "localhost", 9192)
+        );
+        let config = Config::parse("listeners=PLAINTEXT://10.0.0.1:9192").unwrap();
+        assert_eq!(config.advertised_listener.host, "10.0.0.1");
+    }
+
+    #[test]
+    fn test_parse_should_fail_on_invalid_values() {
+        assert!(Config::parse("node.id=one").is_err());
+        assert!(Config::parse("log.dirs= , ").is_err());
+        assert!(Config::parse("compression.type=brotli").is_err());
+        assert!(Config::parse("log.cleanup.policy=compact,archive").is_err());
+        assert!(Config::parse("listeners=localhost:9092").is_err());
+        assert!(Config::parse("listeners=PLAINTEXT://:kafka").is_err());
+        assert!(Config::parse(
+            "listeners=CONTROLLER://:9093\ncontroller.listener.names=CONTROLLER"
+        )
+        .is_err());
+        assert!(Config::parse(
+            "listeners=PLAINTEXT://:9092\nadvertised.listeners=SSL://broker:9093"
+        )
+        .is_err());
+    }
+}
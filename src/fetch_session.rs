@@ -0,0 +1,457 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+use crate::server::model::{ErrorCode, TaggedFields, Uuid};
+use crate::server::requests;
+use crate::server::requests::fetch::{FetchPartition, FetchTopic};
+use crate::server::responses;
+
+// Session id and epochs of KIP-227, a request without session uses the final epoch
+// https://cwiki.apache.org/confluence/display/KAFKA/KIP-227%3A+Introduce+Incremental+FetchRequests+to+Increase+Partition+Scalability
+pub const INVALID_SESSION_ID: i32 = 0;
+const INITIAL_EPOCH: i32 = 0;
+const FINAL_EPOCH: i32 = -1;
+
+// Like max.incremental.fetch.session.cache.slots
+const DEFAULT_MAX_SESSIONS: usize = 1000;
+// Sessions used more recently can only be evicted by sessions with more partitions
+const MIN_EVICTION_TIME: Duration = Duration::from_secs(120);
+
+// Partitions fetched by each session, so that the clients only send the ones that change
+#[derive(Debug)]
+pub struct FetchSessionCache {
+    sessions: HashMap<i32, FetchSession>,
+    max_sessions: usize,
+}
+
+#[derive(Debug)]
+struct FetchSession {
+    // expected in the next request
+    epoch: i32,
+    partitions: Vec<CachedPartition>,
+    last_used: Instant,
+}
+
+#[derive(Debug)]
+struct CachedPartition {
+    topic: String,
+    topic_id: Uuid,
+    fetch: FetchPartition,
+    // as last sent, incremental responses only hold the partitions that changed
+    high_watermark: i64,
+    log_start_offset: i64,
+}
+
+// Partitions to read for a request, and what to do with its session
+#[derive(Debug, PartialEq)]
+pub struct FetchContext {
+    pub topics: Vec<FetchTopic>,
+    session: SessionAction,
+}
+
+#[derive(Debug, PartialEq)]
+enum SessionAction {
+    None,
+    Create,
+    Update(i32),
+}
+
+impl FetchSessionCache {
+    pub fn new(max_sessions: usize) -> FetchSessionCache {
+        FetchSessionCache {
+            sessions: HashMap::new(),
+            max_sessions,
+        }
+    }
+
+    // Checks the session id and epoch of the request and applies its changes to the session.
+    // Fails with the error code of the response.
+    pub fn new_context(
+        &mut self,
+        request: &requests::Fetch,
+        now: Instant,
+    ) -> Result<FetchContext, ErrorCode> {
+        let session_id = request.session_id;
+        match request.session_epoch {
+            // a full request closes the previous session of the client
+            FINAL_EPOCH | INITIAL_EPOCH => {
+                if session_id != INVALID_SESSION_ID {
+                    self.sessions.remove(&session_id);
+                }
+                Ok(FetchContext {
+                    topics: request.topics.clone(),
+                    session: if request.session_epoch == INITIAL_EPOCH {
+                        SessionAction::Create
+                    } else {
+                        SessionAction::None
+                    },
+                })
+            }
+            epoch => {
+                let session = self
+                    .sessions
+                    .get_mut(&session_id)
+                    .ok_or(ErrorCode::FetchSessionIdNotFound)?;
+                if session.epoch != epoch {
+                    return Err(ErrorCode::InvalidFetchSessionEpoch);
+                }
+                session.epoch = next_epoch(epoch);
+                session.last_used = now;
+                session.update(request);
+                Ok(FetchContext {
+                    topics: session.topics(),
+                    session: SessionAction::Update(session_id),
+                })
+            }
+        }
+    }
+
+    // Sets the session id of the response, and removes the partitions that did not change from
+    // the incremental responses
+    pub fn complete(
+        &mut self,
+        context: &FetchContext,
+        response: &mut responses::Fetch,
+        now: Instant,
+    ) {
+        response.session_id = match context.session {
+            SessionAction::None => INVALID_SESSION_ID,
+            SessionAction::Create => self
+                .create_session(context, response, now)
+                .unwrap_or(INVALID_SESSION_ID),
+            SessionAction::Update(session_id) => {
+                // the session may have been evicted since the request was checked
+                if let Some(session) = self.sessions.get_mut(&session_id) {
+                    session.remove_unchanged(response);
+                }
+                session_id
+            }
+        };
+    }
+
+    // `None` when the cache is full of sessions that cannot be evicted
+    fn create_session(
+        &mut self,
+        context: &FetchContext,
+        response: &responses::Fetch,
+        now: Instant,
+    ) -> Option<i32> {
+        let mut session = FetchSession {
+            epoch: next_epoch(INITIAL_EPOCH),
+            partitions: vec![],
+            last_used: now,
+        };
+        session.add(&context.topics);
+        for topic in &response.topics {
+            for partition in &topic.partitions {
+                session.record_sent(&topic.topic, topic.topic_id, partition);
+            }
+        }
+
+        if self.sessions.len() >= self.max_sessions && !self.evict(session.partitions.len(), now) {
+            return None;
+        }
+        let session_id = loop {
+            let session_id = random_session_id();
+            if session_id != INVALID_SESSION_ID && !self.sessions.contains_key(&session_id) {
+                break session_id;
+            }
+        };
+        self.sessions.insert(session_id, session);
+        Some(session_id)
+    }
+
+    // Makes room for a session of `partition_count` partitions. The session unused for the
+    // longest time is evicted if it is stale, otherwise the smallest session if it is smaller.
+    fn evict(&mut self, partition_count: usize, now: Instant) -> bool {
+        let stale = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| now.duration_since(session.last_used) >= MIN_EVICTION_TIME)
+            .min_by_key(|(_, session)| session.last_used);
+        let smaller = || {
+            self.sessions
+                .iter()
+                .filter(|(_, session)| session.partitions.len() < partition_count)
+                .min_by_key(|(_, session)| (session.partitions.len(), session.last_used))
+        };
+        let Some((&session_id, _)) = stale.or_else(smaller) else {
+            return false;
+        };
+        self.sessions.remove(&session_id);
+        true
+    }
+}
+
+impl Default for FetchSessionCache {
+    fn default() -> FetchSessionCache {
+        FetchSessionCache::new(DEFAULT_MAX_SESSIONS)
+    }
+}
+
+impl FetchSession {
+    // The partitions of an incremental request are added or updated, then the forgotten ones
+    // are removed
+    fn update(&mut self, request: &requests::Fetch) {
+        self.add(&request.topics);
+        for forgotten in &request.forgotten_topics_data {
+            self.partitions.retain(|cached| {
+                !(cached.topic == forgotten.topic
+                    && cached.topic_id == forgotten.topic_id
+                    && forgotten.partitions.contains(&cached.fetch.partition))
+            });
+        }
+    }
+
+    fn add(&mut self, topics: &[FetchTopic]) {
+        for topic in topics {
+            for partition in &topic.partitions {
+                match self.find(&topic.topic, topic.topic_id, partition.partition) {
+                    Some(cached) => cached.fetch = partition.clone(),
+                    None => self.partitions.push(CachedPartition {
+                        topic: topic.topic.clone(),
+                        topic_id: topic.topic_id,
+                        fetch: partition.clone(),
+                        high_watermark: -1,
+                        log_start_offset: -1,
+                    }),
+                }
+            }
+        }
+    }
+
+    fn find(
+        &mut self,
+        topic: &str,
+        topic_id: Uuid,
+        partition: i32,
+    ) -> Option<&mut CachedPartition> {
+        self.partitions.iter_mut().find(|cached| {
+            cached.topic == topic
+                && cached.topic_id == topic_id
+                && cached.fetch.partition == partition
+        })
+    }
+
+    fn topics(&self) -> Vec<FetchTopic> {
+        let mut topics: Vec<FetchTopic> = vec![];
+        for cached in &self.partitions {
+            match topics
+                .iter_mut()
+                .find(|topic| topic.topic == cached.topic && topic.topic_id == cached.topic_id)
+            {
+                Some(topic) => topic.partitions.push(cached.fetch.clone()),
+                None => topics.push(FetchTopic {
+                    topic: cached.topic.clone(),
+                    topic_id: cached.topic_id,
+                    partitions: vec![cached.fetch.clone()],
+                    tagged_fields: TaggedFields::default(),
+                }),
+            }
+        }
+        topics
+    }
+
+    fn remove_unchanged(&mut self, response: &mut responses::Fetch) {
+        for topic in &mut response.topics {
+            topic
+                .partitions
+                .retain(|partition| self.record_sent(&topic.topic, topic.topic_id, partition));
+        }
+        response.topics.retain(|topic| !topic.partitions.is_empty());
+    }
+
+    // Keeps the offsets sent for the partition, and returns whether it has to be sent: when it
+    // has records or an error, or when its offsets moved
+    fn record_sent(
+        &mut self,
+        topic: &str,
+        topic_id: Uuid,
+        partition: &responses::fetch::PartitionData,
+    ) -> bool {
+        let Some(cached) = self.find(topic, topic_id, partition.partition_index) else {
+            return true;
+        };
+        let changed = partition
+            .records
+            .as_ref()
+            .is_some_and(|records| !records.is_empty())
+            || partition.error_code != ErrorCode::Ok as i16
+            || partition.high_watermark != cached.high_watermark
+            || partition.log_start_offset != cached.log_start_offset;
+        cached.high_watermark = partition.high_watermark;
+        cached.log_start_offset = partition.log_start_offset;
+        changed
+    }
+}
+
+// Epochs wrap to 1, as 0 and -1 have a meaning
+fn next_epoch(epoch: i32) -> i32 {
+    if epoch == i32::MAX {
+        1
+    } else {
+        epoch + 1
+    }
+}
+
+// Random so that the sessions of a previous run of the broker are not mistaken for new ones
+fn random_session_id() -> i32 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u8(0);
+    (hasher.finish() as i32) & i32::MAX
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::server::model::ApiKey;
+    use crate::server::requests::RequestHeader;
+
+    fn request(session_id: i32, session_epoch: i32, partitions: &[i32]) -> requests::Fetch {
+        requests::Fetch {
+            header: RequestHeader {
+                request_api_key: ApiKey::Fetch,
+                request_api_version: 12,
+                correlation_id: 0,
+                client_id: None,
+                tagged_fields: TaggedFields::default(),
+            },
+            replica_id: -1,
+            max_wait_ms: 0,
+            min_bytes: 0,
+            max_bytes: 1024,
+            isolation_level: 0,
+            session_id,
+            session_epoch,
+            topics: vec![FetchTopic {
+                topic: "foo".to_string(),
+                topic_id: 0,
+                partitions: partitions
+                    .iter()
+                    .map(|&partition| FetchPartition {
+                        partition,
+                        current_leader_epoch: -1,
+                        fetch_offset: 0,
+                        last_fetched_epoch: -1,
+                        log_start_offset: -1,
+                        partition_max_bytes: 1024,
+                        tagged_fields: TaggedFields::default(),
+                    })
+                    .collect(),
+                tagged_fields: TaggedFields::default(),
+            }],
+            forgotten_topics_data: vec![],
+            rack_id: String::new(),
+            cluster_id: None,
+            replica_state: None,
+            tagged_fields: TaggedFields::default(),
+        }
+    }
+
+    // Empty partitions, with a high watermark of 0
+    fn response(context: &FetchContext) -> responses::Fetch {
+        responses::Fetch {
+            throttle_time_in_ms: 0,
+            error_code: 0,
+            session_id: 0,
+            topics: context
+                .topics
+                .iter()
+                .map(|topic| responses::fetch::FetchTopicResponse {
+                    topic: topic.topic.clone(),
+                    topic_id: topic.topic_id,
+                    partitions: topic
+                        .partitions
+                        .iter()
+                        .map(|partition| responses::fetch::PartitionData {
+                            partition_index: partition.partition,
+                            error_code: 0,
+                            high_watermark: 0,
+                            last_stable_offset: 0,
+                            log_start_offset: 0,
+                            diverging_epoch: None,
+                            current_leader: None,
+                            snapshot_id: None,
+                            aborted_transactions: None,
+                            preferred_read_replica: -1,
+                            records: Some(vec![]),
+                            tagged_fields: TaggedFields::default(),
+                        })
+                        .collect(),
+                    tagged_fields: TaggedFields::default(),
+                })
+                .collect(),
+            node_endpoints: None,
+            tagged_fields: TaggedFields::default(),
+        }
+    }
+
+    // Runs a request through the cache and returns the session id of the response
+    fn fetch(cache: &mut FetchSessionCache, request: &requests::Fetch, now: Instant) -> i32 {
+        let context = cache.new_context(request, now).unwrap();
+        let mut response = response(&context);
+        cache.complete(&context, &mut response, now);
+        response.session_id
+    }
+
+    #[test]
+    fn test_incremental_requests_should_update_the_session() {
+        let mut cache = FetchSessionCache::default();
+        let now = Instant::now();
+        assert_eq!(fetch(&mut cache, &request(0, -1, &[0]), now), 0);
+        assert!(cache.sessions.is_empty());
+
+        let session_id = fetch(&mut cache, &request(0, 0, &[0, 1]), now);
+        let mut incremental = request(session_id, 1, &[2]);
+        incremental.topics[0].partitions[0].fetch_offset = 5;
+        incremental.forgotten_topics_data = vec![requests::fetch::ForgottenTopic {
+            topic: "foo".to_string(),
+            topic_id: 0,
+            partitions: vec![0],
+            tagged_fields: TaggedFields::default(),
+        }];
+        let context = cache.new_context(&incremental, now).unwrap();
+        let partitions: Vec<_> = context.topics[0]
+            .partitions
+            .iter()
+            .map(|partition| (partition.partition, partition.fetch_offset))
+            .collect();
+        assert_eq!(partitions, vec![(1, 0), (2, 5)]);
+
+        assert_eq!(
+            cache.new_context(&incremental, now),
+            Err(ErrorCode::InvalidFetchSessionEpoch)
+        );
+        assert_eq!(
+            cache.new_context(&request(session_id + 1, 1, &[]), now),
+            Err(ErrorCode::FetchSessionIdNotFound)
+        );
+        assert_eq!(next_epoch(i32::MAX), 1);
+    }
+
+    #[test]
+    fn test_create_session_should_evict_stale_or_smaller_sessions() {
+        let mut cache = FetchSessionCache::new(2);
+        let start = Instant::now();
+        let small = fetch(&mut cache, &request(0, 0, &[0]), start);
+        let large = fetch(&mut cache, &request(0, 0, &[0, 1]), start);
+
+        // the cache is full of sessions as large and recent
+        let later = start + Duration::from_secs(1);
+        assert_eq!(fetch(&mut cache, &request(0, 0, &[0]), later), 0);
+
+        let larger = fetch(&mut cache, &request(0, 0, &[0, 1, 2]), later);
+        assert_ne!(larger, 0);
+        assert!(!cache.sessions.contains_key(&small));
+
+        // unused sessions are evicted first whatever their size
+        let stale = later + MIN_EVICTION_TIME;
+        fetch(&mut cache, &request(larger, 1, &[]), stale);
+        assert_ne!(fetch(&mut cache, &request(0, 0, &[0]), stale), 0);
+        assert!(!cache.sessions.contains_key(&large));
+        assert!(cache.sessions.contains_key(&larger));
+    }
+}
@@ -0,0 +1,1267 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::record::{self, RecordError};
+
+pub mod checkpoint;
+mod compaction;
+mod index;
+pub mod partition_metadata;
+mod segment;
+
+use segment::{BatchPosition, Segment};
+
+#[derive(Debug, thiserror::Error)]
+pub enum LogError {
+    #[error(transparent)]
+    Record(#[from] RecordError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("offset {offset} is not between {log_start_offset} and {log_end_offset}")]
+    OffsetOutOfRange {
+        offset: i64,
+        log_start_offset: i64,
+        log_end_offset: i64,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, LogError>;
+
+// Files of a log dir, like the Kafka ones. The marker is written on orderly shutdown, without it
+// the logs are recovered from their recovery points, up to which they were flushed.
+pub const CLEAN_SHUTDOWN_FILE: &str = ".kafka_cleanshutdown";
+pub const RECOVERY_POINT_CHECKPOINT_FILE: &str = "recovery-point-offset-checkpoint";
+
+pub const CLEANER_CHECKPOINT_FILE: &str = "cleaner-offset-checkpoint";
+
+// Extension added to the files of the deleted segments until they are removed
+const DELETED_SUFFIX: &str = "deleted";
+// Extensions of a compacted segment while it is written, then while it replaces the old ones
+const CLEANED_SUFFIX: &str = "cleaned";
+const SWAP_SUFFIX: &str = "swap";
+
+// Segments taken out of a log, whose files are handled without holding the log. Like Kafka they
+// are renamed with the `.deleted` suffix first, then removed after file.delete.delay.ms.
+#[derive(Debug)]
+pub struct DeletedSegments {
+    dir: PathBuf,
+    segments: Vec<Segment>,
+    pub delay: Duration,
+}
+
+impl DeletedSegments {
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    // Renames the files of the segments, and returns their new paths
+    pub fn rename(self) -> Result<Vec<PathBuf>> {
+        let mut files = vec![];
+        for segment in self.segments {
+            files.extend(segment.rename(&self.dir, DELETED_SUFFIX)?);
+        }
+        Ok(files)
+    }
+}
+
+// Segments of a log to compact, read by the cleaner without holding the log
+#[derive(Debug)]
+pub struct Cleaning {
+    dir: PathBuf,
+    config: LogConfig,
+    segments: Vec<SealedSegment>,
+    first_dirty: usize,
+    next_dirty_offset: i64,
+}
+
+#[derive(Debug)]
+struct SealedSegment {
+    base_offset: i64,
+    size: u64,
+    last_offset: Option<i64>,
+    reader: segment::SegmentReader,
+}
+
+// Compacted segments, in `.swap` files until `PartitionLog::replace_segments` swaps them in for
+// the segments of their base offsets
+#[derive(Debug)]
+pub struct CleanedSegments {
+    segments: Vec<(Vec<i64>, Segment)>,
+}
+
+impl Cleaning {
+    // First dirty offset of the next cleaning
+    pub fn next_dirty_offset(&self) -> i64 {
+        self.next_dirty_offset
+    }
+
+    // Only the latest record of each key of the dirty segments is kept, and tombstones are
+    // removed once older than delete.retention.ms. The segments are read batch by batch, and like
+    // Kafka the compacted ones are written to `.cleaned` files, renamed to `.swap` once complete.
+    pub fn clean(self, now_ms: i64) -> Result<CleanedSegments> {
+        let mut offsets = compaction::OffsetMap::new();
+        for segment in &self.segments[self.first_dirty..] {
+            segment
+                .reader
+                .for_each_batch(|batch| compaction::add_keys(&mut offsets, batch))?;
+        }
+        let delete_horizon_ms = now_ms - self.config.delete_retention_ms;
+
+        let mut cleaned = vec![];
+        let mut start = 0;
+        while start < self.segments.len() {
+            // consecutive segments are merged while they fit in one
+            let base_offset = self.segments[start].base_offset;
+            let mut group_end = start + 1;
+            let mut group_size = self.segments[start].size;
+            while let Some(segment) = self.segments.get(group_end) {
+                if group_size + segment.size > self.config.segment_bytes
+                    || segment
+                        .last_offset
+                        .is_some_and(|last_offset| last_offset - base_offset > i32::MAX as i64)
+                {
+                    break;
+                }
+                group_size += segment.size;
+                group_end += 1;
+            }
+            let group = &self.segments[start..group_end];
+            start = group_end;
+
+            // read once to find whether anything is removed, so that the segments left as they
+            // are are not rewritten
+            let mut changed = group.len() > 1;
+            let mut buffer = vec![];
+            for segment in group {
+                if changed {
+                    break;
+                }
+                segment.reader.for_each_batch(|batch| {
+                    buffer.clear();
+                    changed |= compaction::clean(batch, &offsets, delete_horizon_ms, &mut buffer)?;
+                    Ok(())
+                })?;
+            }
+            if !changed {
+                continue;
+            }
+
+            let mut segment =
+                Segment::create_with_suffix(&self.dir, base_offset, &self.config, CLEANED_SUFFIX)?;
+            for old in group {
+                old.reader.for_each_batch(|batch| {
+                    buffer.clear();
+                    compaction::clean(batch, &offsets, delete_horizon_ms, &mut buffer)?;
+                    if !buffer.is_empty() {
+                        segment.append_batch(&buffer)?;
+                    }
+                    Ok(())
+                })?;
+            }
+            segment.seal()?;
+            segment.change_suffix(&self.dir, Some(CLEANED_SUFFIX), Some(SWAP_SUFFIX))?;
+            let base_offsets = group.iter().map(|segment| segment.base_offset).collect();
+            cleaned.push((base_offsets, segment));
+        }
+        Ok(CleanedSegments { segments: cleaned })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AppendInfo {
+    pub base_offset: i64,
+    pub last_offset: i64,
+}
+
+// Settings of the partition logs, from the broker log.* configs and the topic overrides
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogConfig {
+    // the active segment is rolled once it would grow past it
+    pub segment_bytes: u64,
+    // or once its batches span more than it
+    pub segment_ms: i64,
+    // bytes of batches between two index entries
+    pub index_interval_bytes: u64,
+    // largest size of the indexes of a segment, which is rolled once they are full
+    pub max_index_bytes: usize,
+    // the oldest segments are deleted past them, -1 keeps them
+    pub retention_ms: i64,
+    pub retention_bytes: i64,
+    // how long the files of a deleted segment are kept, renamed with the `.deleted` suffix
+    pub file_delete_delay_ms: u64,
+    pub cleanup_policy: CleanupPolicy,
+    // how long tombstones are kept by compaction
+    pub delete_retention_ms: i64,
+    // share of the log that must be dirty before it is compacted
+    pub min_cleanable_dirty_ratio: f64,
+}
+
+impl LogConfig {
+    // Topic configs are named like the broker ones without the `log.` prefix
+    pub fn with_overrides(&self, configs: &BTreeMap<String, String>) -> LogConfig {
+        fn parse<T: FromStr>(configs: &BTreeMap<String, String>, name: &str) -> Option<T> {
+            configs.get(name).and_then(|value| value.parse().ok())
+        }
+
+        let mut config = self.clone();
+        if let Some(segment_bytes) = parse(configs, "segment.bytes") {
+            config.segment_bytes = segment_bytes;
+        }
+        if let Some(segment_ms) = parse(configs, "segment.ms") {
+            config.segment_ms = segment_ms;
+        }
+        if let Some(index_interval_bytes) = parse(configs, "index.interval.bytes") {
+            config.index_interval_bytes = index_interval_bytes;
+        }
+        if let Some(max_index_bytes) = parse(configs, "segment.index.bytes") {
+            config.max_index_bytes = max_index_bytes;
+        }
+        if let Some(retention_ms) = parse(configs, "retention.ms") {
+            config.retention_ms = retention_ms;
+        }
+        if let Some(retention_bytes) = parse(configs, "retention.bytes") {
+            config.retention_bytes = retention_bytes;
+        }
+        if let Some(file_delete_delay_ms) = parse(configs, "file.delete.delay.ms") {
+            config.file_delete_delay_ms = file_delete_delay_ms;
+        }
+        if let Some(cleanup_policy) = parse(configs, "cleanup.policy") {
+            config.cleanup_policy = cleanup_policy;
+        }
+        if let Some(delete_retention_ms) = parse(configs, "delete.retention.ms") {
+            config.delete_retention_ms = delete_retention_ms;
+        }
+        if let Some(min_cleanable_dirty_ratio) = parse(configs, "min.cleanable.dirty.ratio") {
+            config.min_cleanable_dirty_ratio = min_cleanable_dirty_ratio;
+        }
+        config
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> LogConfig {
+        LogConfig {
+            segment_bytes: 1024 * 1024 * 1024,
+            segment_ms: 7 * 24 * 60 * 60 * 1000,
+            index_interval_bytes: 4096,
+            max_index_bytes: 10 * 1024 * 1024,
+            retention_ms: 7 * 24 * 60 * 60 * 1000,
+            retention_bytes: -1,
+            file_delete_delay_ms: 60_000,
+            cleanup_policy: CleanupPolicy::default(),
+            delete_retention_ms: 24 * 60 * 60 * 1000,
+            min_cleanable_dirty_ratio: 0.5,
+        }
+    }
+}
+
+// What happens to the old segments of a log, cleanup.policy lists `delete` and `compact`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CleanupPolicy {
+    // past retention.ms and retention.bytes
+    pub delete: bool,
+    // records followed by a later one of the same key
+    pub compact: bool,
+}
+
+impl Default for CleanupPolicy {
+    fn default() -> CleanupPolicy {
+        CleanupPolicy {
+            delete: true,
+            compact: false,
+        }
+    }
+}
+
+impl FromStr for CleanupPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<CleanupPolicy, String> {
+        let mut policy = CleanupPolicy {
+            delete: false,
+            compact: false,
+        };
+        for name in value.split(',').map(str::trim) {
+            match name {
+                "delete" => policy.delete = true,
+                "compact" => policy.compact = true,
+                _ => return Err(format!("invalid cleanup.policy {}", value)),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+// Append-only log of a topic partition, stored as record batches in segment files named by their
+// base offset, in the layout of the Kafka log dirs
+#[derive(Debug)]
+pub struct PartitionLog {
+    dir: PathBuf,
+    config: LogConfig,
+    // in offset order, the last one is the active segment that is appended to
+    segments: Vec<Segment>,
+    log_start_offset: i64,
+    next_offset: i64,
+    // the batches before it are durable
+    recovery_point: i64,
+}
+
+impl PartitionLog {
+    pub fn open(
+        log_dir: &Path,
+        topic: &str,
+        partition: i32,
+        config: LogConfig,
+    ) -> Result<PartitionLog> {
+        Self::load(log_dir, topic, partition, config, None)
+    }
+
+    // Opens the log after a crash. The segments from the one holding `recovery_point` are
+    // checked and truncated at the first corrupt batch, the segments that follow it are deleted.
+    pub fn recover(
+        log_dir: &Path,
+        topic: &str,
+        partition: i32,
+        config: LogConfig,
+        recovery_point: i64,
+    ) -> Result<PartitionLog> {
+        Self::load(log_dir, topic, partition, config, Some(recovery_point))
+    }
+
+    fn load(
+        log_dir: &Path,
+        topic: &str,
+        partition: i32,
+        config: LogConfig,
+        recovery_point: Option<i64>,
+    ) -> Result<PartitionLog> {
+        let dir = Self::dir(log_dir, topic, partition);
+        fs::create_dir_all(&dir)?;
+
+        let mut base_offsets = vec![];
+        let mut swaps = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let extension = path.extension().and_then(|extension| extension.to_str());
+            // left by segments deleted or compacted right before the broker stopped
+            if extension == Some(DELETED_SUFFIX) || extension == Some(CLEANED_SUFFIX) {
+                fs::remove_file(&path)?;
+            } else if extension == Some(SWAP_SUFFIX) {
+                // the index swaps are handled with the one of their segment
+                if path
+                    .file_stem()
+                    .and_then(|stem| Path::new(stem).extension())
+                    .is_some_and(|extension| extension == "log")
+                {
+                    swaps.push(path);
+                }
+            } else if path.extension().is_some_and(|extension| extension == "log") {
+                if let Some(base_offset) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse().ok())
+                {
+                    base_offsets.push(base_offset);
+                }
+            }
+        }
+        for swap in swaps {
+            if let Some(base_offset) = Self::complete_swap(&dir, &swap)? {
+                base_offsets.push(base_offset);
+            }
+        }
+        // index swaps of a discarded segment swap
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == SWAP_SUFFIX)
+            {
+                fs::remove_file(&path)?;
+            }
+        }
+        base_offsets.sort_unstable();
+        if base_offsets.is_empty() {
+            base_offsets.push(0);
+        }
+        let mut segments = base_offsets
+            .into_iter()
+            .map(|base_offset| Segment::open(&dir, base_offset, &config))
+            .collect::<Result<Vec<_>>>()?;
+
+        // a swap completed above overlaps the segments it replaced that were not deleted yet
+        let mut n = 1;
+        while n < segments.len() {
+            if segments[n - 1]
+                .last_offset()
+                .is_some_and(|last_offset| segments[n].base_offset <= last_offset)
+            {
+                segments.remove(n).delete(&dir, None)?;
+            } else {
+                n += 1;
+            }
+        }
+
+        if let Some(recovery_point) = recovery_point {
+            let mut n = segments
+                .partition_point(|segment| segment.base_offset <= recovery_point)
+                .saturating_sub(1);
+            while n < segments.len() {
+                if segments[n].recover()? {
+                    // their offsets would not follow the ones of the truncated segment
+                    for segment in segments.drain(n + 1..) {
+                        segment.delete(&dir, None)?;
+                    }
+                    segments[n].flush()?;
+                }
+                n += 1;
+            }
+        }
+
+        let mut log_start_offset = segments[0].base_offset;
+        for segment in &segments {
+            if let Some(first_offset) = segment.first_offset()? {
+                log_start_offset = first_offset;
+                break;
+            }
+        }
+        // a segment can be empty when the broker stopped right after rolling it
+        let active = segments.last().unwrap();
+        let next_offset = active
+            .last_offset()
+            .map_or(active.base_offset, |last_offset| last_offset + 1);
+        Ok(PartitionLog {
+            dir,
+            config,
+            segments,
+            log_start_offset,
+            next_offset,
+            recovery_point: next_offset,
+        })
+    }
+
+    // A compacted segment replaces the old ones once the first of them has been deleted, before
+    // that they are kept. Returns the base offset of the completed segment.
+    fn complete_swap(dir: &Path, swap: &Path) -> Result<Option<i64>> {
+        let Some(base_offset) = swap
+            .file_stem()
+            .and_then(|stem| Path::new(stem).file_stem())
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok())
+        else {
+            return Ok(None);
+        };
+        let path = Segment::path(dir, base_offset);
+        if path.exists() {
+            fs::remove_file(swap)?;
+            return Ok(None);
+        }
+        // the index swaps are renamed before the segment one, without them the indexes can be
+        // the ones of the old segment and are rebuilt
+        for index in [
+            index::OffsetIndex::path(dir, base_offset),
+            index::TimeIndex::path(dir, base_offset),
+        ] {
+            let index_swap = segment::with_suffix(&index, SWAP_SUFFIX);
+            if index_swap.exists() {
+                fs::rename(index_swap, index)?;
+            } else if index.exists() {
+                fs::remove_file(index)?;
+            }
+        }
+        fs::rename(swap, path)?;
+        Ok(Some(base_offset))
+    }
+
+    pub fn dir(log_dir: &Path, topic: &str, partition: i32) -> PathBuf {
+        log_dir.join(format!("{}-{}", topic, partition))
+    }
+
+    pub fn log_start_offset(&self) -> i64 {
+        self.log_start_offset
+    }
+
+    pub fn recovery_point(&self) -> i64 {
+        self.recovery_point
+    }
+
+    // Offset of the next appended record, and the high watermark as the broker is the only
+    // replica
+    pub fn log_end_offset(&self) -> i64 {
+        self.next_offset
+    }
+
+    // Assigns offsets to the batches, following the ones already in the log, then writes them
+    pub fn append(&mut self, records: &[u8]) -> Result<AppendInfo> {
+        let mut records = records.to_vec();
+        let base_offset = self.next_offset;
+        let mut next_offset = self.next_offset;
+        let mut batches = vec![];
+
+        let mut position = 0;
+        while position < records.len() {
+            let batch = &mut records[position..];
+            let batch_size = record::validate_records(batch)?;
+            let last_offset_delta = segment::read_i32(batch, record::LAST_OFFSET_DELTA);
+            let max_timestamp = segment::read_i64(batch, record::MAX_TIMESTAMP);
+
+            // the base offset is not covered by the crc
+            batch[record::BASE_OFFSET..record::BASE_OFFSET + 8]
+                .copy_from_slice(&next_offset.to_be_bytes());
+            batches.push(BatchPosition {
+                base_offset: next_offset,
+                last_offset: next_offset + last_offset_delta as i64,
+                max_timestamp,
+                position: position as u64,
+                size: batch_size,
+            });
+            next_offset += last_offset_delta as i64 + 1;
+            position += batch_size;
+        }
+        if next_offset == base_offset {
+            return Err(RecordError::CorruptBatch("no record batch").into());
+        }
+
+        let max_timestamp = batches.iter().map(|batch| batch.max_timestamp).max();
+        let active = self.active_segment();
+        if active.size() > 0
+            && (active.size() + records.len() as u64 > self.config.segment_bytes
+                || active.is_index_full()
+                || active.first_timestamp().zip(max_timestamp).is_some_and(
+                    |(first_timestamp, max_timestamp)| {
+                        max_timestamp - first_timestamp > self.config.segment_ms
+                    },
+                ))
+        {
+            self.roll()?;
+        }
+        self.segments
+            .last_mut()
+            .unwrap()
+            .append(&records, &batches)?;
+        self.next_offset = next_offset;
+        Ok(AppendInfo {
+            base_offset,
+            last_offset: next_offset - 1,
+        })
+    }
+
+    // Reads the batches from the one holding `offset`, up to `max_bytes`. With `min_one_batch`
+    // the first batch is returned even when it is larger, so that consumers can make progress.
+    // A read does not go past the end of a segment.
+    pub fn read(&self, offset: i64, max_bytes: usize, min_one_batch: bool) -> Result<Vec<u8>> {
+        if offset < self.log_start_offset || offset > self.next_offset {
+            return Err(LogError::OffsetOutOfRange {
+                offset,
+                log_start_offset: self.log_start_offset,
+                log_end_offset: self.next_offset,
+            });
+        }
+        let first = self
+            .segments
+            .partition_point(|segment| segment.base_offset <= offset)
+            .saturating_sub(1);
+        match self.segments[first..].iter().find(|segment| {
+            segment
+                .last_offset()
+                .is_some_and(|last_offset| last_offset >= offset)
+        }) {
+            Some(segment) => segment.read(offset, max_bytes, min_one_batch),
+            None => Ok(vec![]),
+        }
+    }
+
+    // Offset of the first batch with a timestamp at or after `timestamp`, `None` when all the
+    // batches are older
+    #[allow(dead_code)] // for ListOffsets by timestamp, which is not supported yet
+    pub fn offset_for_timestamp(&self, timestamp: i64) -> Result<Option<i64>> {
+        for segment in &self.segments {
+            if let Some(offset) = segment.offset_for_timestamp(timestamp)? {
+                return Ok(Some(offset));
+            }
+        }
+        Ok(None)
+    }
+
+    // Takes out the oldest segments once their batches are older than retention.ms, or while the
+    // log is larger than retention.bytes without them, and moves the log start offset to the
+    // first remaining segment. Their files are left to the caller, see `DeletedSegments`.
+    pub fn delete_old_segments(&mut self, now_ms: i64) -> Result<DeletedSegments> {
+        let mut deleted = DeletedSegments {
+            dir: self.dir.clone(),
+            segments: vec![],
+            delay: self.file_delete_delay(),
+        };
+        if !self.config.cleanup_policy.delete {
+            return Ok(deleted);
+        }
+        let mut excess_bytes = self.size() as i64 - self.config.retention_bytes;
+        let mut count = 0;
+        for segment in &self.segments {
+            let expired = self.config.retention_ms >= 0
+                && now_ms - segment.largest_timestamp()? > self.config.retention_ms;
+            let oversized =
+                self.config.retention_bytes >= 0 && excess_bytes - segment.size() as i64 >= 0;
+            if segment.size() == 0 || !(expired || oversized) {
+                break;
+            }
+            excess_bytes -= segment.size() as i64;
+            count += 1;
+        }
+        if count == 0 {
+            return Ok(deleted);
+        }
+
+        // there is always an active segment to append to
+        if count == self.segments.len() {
+            self.roll()?;
+        }
+        deleted.segments = self.segments.drain(..count).collect();
+        self.log_start_offset = self.log_start_offset.max(self.segments[0].base_offset);
+        Ok(deleted)
+    }
+
+    // Takes what `Cleaning::clean` needs to compact the segments before the active one, the
+    // clean ones and the dirty ones from `first_dirty_offset`, so that it runs without holding
+    // the log. Nothing is done until the dirty segments are min.cleanable.dirty.ratio of them.
+    pub fn start_cleaning(&self, first_dirty_offset: i64) -> Result<Option<Cleaning>> {
+        if !self.config.cleanup_policy.compact {
+            return Ok(None);
+        }
+        let end = self.segments.len() - 1;
+        let first_dirty = self
+            .segments
+            .partition_point(|segment| segment.base_offset <= first_dirty_offset)
+            .saturating_sub(1);
+        if first_dirty >= end {
+            return Ok(None);
+        }
+        let size = |segments: &[Segment]| segments.iter().map(Segment::size).sum::<u64>();
+        let dirty_bytes = size(&self.segments[first_dirty..end]) as f64;
+        if dirty_bytes == 0.0
+            || dirty_bytes
+                < size(&self.segments[..end]) as f64 * self.config.min_cleanable_dirty_ratio
+        {
+            return Ok(None);
+        }
+
+        let segments = self.segments[..end]
+            .iter()
+            .map(|segment| {
+                Ok(SealedSegment {
+                    base_offset: segment.base_offset,
+                    size: segment.size(),
+                    last_offset: segment.last_offset(),
+                    reader: segment.reader()?,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Some(Cleaning {
+            dir: self.dir.clone(),
+            config: self.config.clone(),
+            segments,
+            first_dirty,
+            next_dirty_offset: self.segments[end].base_offset,
+        }))
+    }
+
+    // Swaps in the segments compacted by `Cleaning::clean`, unless the ones they replace were
+    // deleted meanwhile. The old segments are renamed to `.deleted`, the first one first, and
+    // then the `.swap` files of the new one lose their suffix, see `complete_swap`. Returns the
+    // files of the old segments, to remove as for retention.
+    pub fn replace_segments(&mut self, cleaned: CleanedSegments) -> Result<Vec<PathBuf>> {
+        let mut files = vec![];
+        for (base_offsets, segment) in cleaned.segments {
+            let active = self.segments.len() - 1;
+            let start = self.segments[..active]
+                .iter()
+                .position(|segment| segment.base_offset == base_offsets[0])
+                .filter(|&start| {
+                    self.segments[start..active]
+                        .iter()
+                        .map(|segment| segment.base_offset)
+                        .take(base_offsets.len())
+                        .eq(base_offsets.iter().copied())
+                });
+            let Some(start) = start else {
+                segment.delete(&self.dir, Some(SWAP_SUFFIX))?;
+                continue;
+            };
+            for old in self.segments.drain(start..start + base_offsets.len()) {
+                files.extend(old.rename(&self.dir, DELETED_SUFFIX)?);
+            }
+            segment.change_suffix(&self.dir, Some(SWAP_SUFFIX), None)?;
+            self.segments.insert(start, segment);
+        }
+        Ok(files)
+    }
+
+    pub fn file_delete_delay(&self) -> Duration {
+        Duration::from_millis(self.config.file_delete_delay_ms)
+    }
+
+    // Bytes of the segment files
+    pub fn size(&self) -> u64 {
+        self.segments.iter().map(Segment::size).sum()
+    }
+
+    // Makes the appended batches durable
+    pub fn flush(&mut self) -> Result<()> {
+        self.segments.last_mut().unwrap().flush()?;
+        self.recovery_point = self.next_offset;
+        Ok(())
+    }
+
+    fn active_segment(&self) -> &Segment {
+        self.segments.last().unwrap()
+    }
+
+    // Starts a new segment at the next offset, the previous one is made durable first
+    fn roll(&mut self) -> Result<()> {
+        self.segments.last_mut().unwrap().seal()?;
+        self.recovery_point = self.next_offset;
+        self.segments
+            .push(Segment::open(&self.dir, self.next_offset, &self.config)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    use crate::record::legacy::tests::{message, message_set};
+    use crate::record::tests::{batch, record, record_batch};
+    use std::io::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    pub fn base_offsets(data: &[u8]) -> Vec<i64> {
+        record::tests::read_batches(data)
+            .unwrap()
+            .iter()
+            .map(|batch| batch.base_offset)
+            .collect()
+    }
+
+    fn keyed_batch(key: &[u8], value: Option<&[u8]>) -> Vec<u8> {
+        let mut record = record(0, b"");
+        record.key = Some(key.to_vec());
+        record.value = value.map(<[u8]>::to_vec);
+        let mut buffer = vec![];
+        batch(0, vec![record]).write(&mut buffer);
+        buffer
+    }
+
+    fn timestamped_batch(timestamp: i64) -> Vec<u8> {
+        let mut batch = batch(0, vec![record(0, b"")]);
+        batch.base_timestamp = timestamp;
+        batch.max_timestamp = timestamp;
+        let mut buffer = vec![];
+        batch.write(&mut buffer);
+        buffer
+    }
+
+    // Empty directory, unique to the test
+    pub fn temp_log_dir() -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "kafka-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_append_should_assign_offsets() {
+        let log_dir = temp_log_dir();
+        let mut log = PartitionLog::open(&log_dir, "foo", 0, LogConfig::default()).unwrap();
+
+        let mut records = record_batch(3);
+        records.extend(record_batch(2));
+        assert_eq!(
+            log.append(&records).unwrap(),
+            AppendInfo {
+                base_offset: 0,
+                last_offset: 4
+            }
+        );
+        assert_eq!(
+            log.append(&record_batch(1)).unwrap(),
+            AppendInfo {
+                base_offset: 5,
+                last_offset: 5
+            }
+        );
+
+        let data = fs::read(PartitionLog::dir(&log_dir, "foo", 0).join("00000000000000000000.log"))
+            .unwrap();
+        let batches = record::tests::read_batches(&data).unwrap();
+        let base_offsets: Vec<_> = batches.iter().map(|batch| batch.base_offset).collect();
+        assert_eq!(base_offsets, vec![0, 3, 5]);
+    }
+
+    #[test]
+    fn test_open_should_continue_after_the_existing_batches() {
+        let log_dir = temp_log_dir();
+        let mut log = PartitionLog::open(&log_dir, "foo", 0, LogConfig::default()).unwrap();
+        log.append(&record_batch(3)).unwrap();
+        fs::OpenOptions::new()
+            .append(true)
+            .open(Segment::path(&PartitionLog::dir(&log_dir, "foo", 0), 0))
+            .unwrap()
+            .write_all(&record_batch(1)[..20])
+            .unwrap();
+        drop(log);
+
+        let mut log = PartitionLog::open(&log_dir, "foo", 0, LogConfig::default()).unwrap();
+        assert_eq!(log.next_offset, 3);
+        assert_eq!(log.append(&record_batch(1)).unwrap().base_offset, 3);
+    }
+
+    #[test]
+    fn test_open_should_continue_after_legacy_messages() {
+        let log_dir = temp_log_dir();
+        let dir = PartitionLog::dir(&log_dir, "foo", 0);
+        fs::create_dir_all(&dir).unwrap();
+        let messages = [message(7, 0, b"foo"), message(8, 1, b"bar")];
+        fs::write(dir.join("00000000000000000000.log"), message_set(&messages)).unwrap();
+
+        let mut log = PartitionLog::open(&log_dir, "foo", 0, LogConfig::default()).unwrap();
+        assert_eq!(log.log_start_offset(), 7);
+        assert_eq!(log.append(&record_batch(1)).unwrap().base_offset, 9);
+    }
+
+    #[test]
+    fn test_read() {
+        let log_dir = temp_log_dir();
+        let mut log = PartitionLog::open(&log_dir, "foo", 0, LogConfig::default()).unwrap();
+        assert_eq!(log.read(0, 1024, true).unwrap(), vec![]);
+        for count in [3, 2, 1] {
+            log.append(&record_batch(count)).unwrap();
+        }
+        let batch_size = |count| record_batch(count).len();
+
+        // from the batch holding the offset
+        assert_eq!(
+            base_offsets(&log.read(0, 1024, false).unwrap()),
+            vec![0, 3, 5]
+        );
+        assert_eq!(base_offsets(&log.read(4, 1024, false).unwrap()), vec![3, 5]);
+        assert_eq!(log.read(6, 1024, false).unwrap(), vec![]);
+
+        // only whole batches
+        let max_bytes = batch_size(3) + batch_size(2) - 1;
+        assert_eq!(
+            base_offsets(&log.read(0, max_bytes, false).unwrap()),
+            vec![0]
+        );
+        assert_eq!(log.read(0, 10, false).unwrap(), vec![]);
+        assert_eq!(base_offsets(&log.read(0, 10, true).unwrap()), vec![0]);
+
+        assert!(matches!(
+            log.read(7, 1024, false),
+            Err(LogError::OffsetOutOfRange {
+                log_end_offset: 6,
+                ..
+            })
+        ));
+        assert!(matches!(
+            log.read(-1, 1024, false),
+            Err(LogError::OffsetOutOfRange { .. })
+        ));
+
+        // the positions are found again by the scan
+        drop(log);
+        let log = PartitionLog::open(&log_dir, "foo", 0, LogConfig::default()).unwrap();
+        assert_eq!(base_offsets(&log.read(4, 1024, false).unwrap()), vec![3, 5]);
+    }
+
+    #[test]
+    fn test_append_should_roll_segments() {
+        let log_dir = temp_log_dir();
+        let config = LogConfig {
+            segment_bytes: 2 * record_batch(1).len() as u64,
+            ..LogConfig::default()
+        };
+        let mut log = PartitionLog::open(&log_dir, "foo", 0, config.clone()).unwrap();
+        for _ in 0..3 {
+            log.append(&record_batch(1)).unwrap();
+        }
+        // a batch larger than a segment still gets one
+        log.append(&record_batch(5)).unwrap();
+        log.append(&record_batch(1)).unwrap();
+
+        let dir = PartitionLog::dir(&log_dir, "foo", 0);
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|file| file.ends_with(".log"))
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                "00000000000000000000.log",
+                "00000000000000000002.log",
+                "00000000000000000003.log",
+                "00000000000000000008.log"
+            ]
+        );
+
+        // reads stop at the end of a segment
+        assert_eq!(base_offsets(&log.read(0, 1024, false).unwrap()), vec![0, 1]);
+        assert_eq!(base_offsets(&log.read(2, 1024, false).unwrap()), vec![2]);
+        assert_eq!(base_offsets(&log.read(6, 1024, false).unwrap()), vec![3]);
+
+        drop(log);
+        let mut log = PartitionLog::open(&log_dir, "foo", 0, config).unwrap();
+        assert_eq!(log.log_start_offset(), 0);
+        assert_eq!(log.append(&record_batch(1)).unwrap().base_offset, 9);
+        assert_eq!(base_offsets(&log.read(8, 1024, false).unwrap()), vec![8, 9]);
+    }
+
+    #[test]
+    fn test_append_should_reject_invalid_batches() {
+        let mut log = PartitionLog::open(&temp_log_dir(), "foo", 0, LogConfig::default()).unwrap();
+
+        assert!(matches!(
+            log.append(&record_batch(1)[..30]),
+            Err(LogError::Record(RecordError::CorruptBatch(_)))
+        ));
+        let mut batch = record_batch(1);
+        batch[record::MAGIC] = 1;
+        assert!(matches!(
+            log.append(&batch),
+            Err(LogError::Record(RecordError::UnsupportedMagic(1)))
+        ));
+        // the second batch is corrupt, so the first one is not appended either
+        let mut records = record_batch(1);
+        records.extend(record_batch(1));
+        let last = records.len() - 1;
+        records[last] ^= 1;
+        assert!(matches!(
+            log.append(&records),
+            Err(LogError::Record(RecordError::InvalidCrc { .. }))
+        ));
+        assert!(matches!(
+            log.append(&[]),
+            Err(LogError::Record(RecordError::CorruptBatch(_)))
+        ));
+        assert_eq!(log.next_offset, 0);
+    }
+
+    #[test]
+    fn test_indexes() {
+        let log_dir = temp_log_dir();
+        let dir = PartitionLog::dir(&log_dir, "foo", 0);
+        // every batch but the first one is indexed
+        let config = LogConfig {
+            index_interval_bytes: 0,
+            ..LogConfig::default()
+        };
+        let mut log = PartitionLog::open(&log_dir, "foo", 0, config.clone()).unwrap();
+        for n in 0..10 {
+            log.append(&timestamped_batch(1000 + 10 * n)).unwrap();
+        }
+        let check = |log: &PartitionLog| {
+            assert_eq!(
+                base_offsets(&log.read(5, 1024, false).unwrap()),
+                vec![5, 6, 7, 8, 9]
+            );
+            assert_eq!(base_offsets(&log.read(0, 1, true).unwrap()), vec![0]);
+            assert_eq!(log.offset_for_timestamp(0).unwrap(), Some(0));
+            assert_eq!(log.offset_for_timestamp(1045).unwrap(), Some(5));
+            assert_eq!(log.offset_for_timestamp(1050).unwrap(), Some(5));
+            assert_eq!(log.offset_for_timestamp(1091).unwrap(), None);
+        };
+        check(&log);
+
+        // the indexes hold their entries only
+        drop(log);
+        let index_path = index::OffsetIndex::path(&dir, 0);
+        let time_index_path = index::TimeIndex::path(&dir, 0);
+        assert_eq!(fs::metadata(&index_path).unwrap().len(), 9 * 8);
+        assert_eq!(fs::metadata(&time_index_path).unwrap().len(), 9 * 12);
+        let log = PartitionLog::open(&log_dir, "foo", 0, config.clone()).unwrap();
+        check(&log);
+        // and are not grown when opened
+        assert_eq!(fs::metadata(&index_path).unwrap().len(), 9 * 8);
+        assert_eq!(fs::metadata(&time_index_path).unwrap().len(), 9 * 12);
+
+        // missing or corrupt indexes are rebuilt
+        drop(log);
+        fs::remove_file(&time_index_path).unwrap();
+        fs::write(&index_path, [0xff; 12]).unwrap();
+        let mut log = PartitionLog::open(&log_dir, "foo", 0, config).unwrap();
+        check(&log);
+        assert_eq!(
+            log.append(&timestamped_batch(1100)).unwrap().base_offset,
+            10
+        );
+        drop(log);
+        assert_eq!(fs::metadata(&index_path).unwrap().len(), 10 * 8);
+    }
+
+    #[test]
+    fn test_append_should_roll_when_the_index_is_full() {
+        let log_dir = temp_log_dir();
+        let config = LogConfig {
+            index_interval_bytes: 0,
+            max_index_bytes: 24,
+            ..LogConfig::default()
+        };
+        let mut log = PartitionLog::open(&log_dir, "foo", 0, config).unwrap();
+        for n in 0..7 {
+            log.append(&timestamped_batch(n)).unwrap();
+        }
+        // the time index is full with two entries, the first batch of a segment is not indexed
+        assert_eq!(
+            log.segments
+                .iter()
+                .map(|segment| segment.base_offset)
+                .collect::<Vec<_>>(),
+            vec![0, 3, 6]
+        );
+        assert_eq!(
+            base_offsets(&log.read(0, 1024, false).unwrap()),
+            vec![0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn test_recover_should_truncate_at_the_first_corrupt_batch() {
+        let log_dir = temp_log_dir();
+        let dir = PartitionLog::dir(&log_dir, "foo", 0);
+        let config = LogConfig {
+            segment_bytes: 3 * record_batch(1).len() as u64,
+            ..LogConfig::default()
+        };
+        let mut log = PartitionLog::open(&log_dir, "foo", 0, config.clone()).unwrap();
+        for _ in 0..9 {
+            log.append(&record_batch(1)).unwrap();
+        }
+        assert_eq!(log.recovery_point(), 6);
+        drop(log);
+
+        // the second batch of each segment is corrupt
+        let batch_size = record_batch(1).len() as u64;
+        for base_offset in [0, 3, 6] {
+            let file = fs::OpenOptions::new()
+                .write(true)
+                .open(Segment::path(&dir, base_offset))
+                .unwrap();
+            std::os::unix::fs::FileExt::write_all_at(&file, b"x", batch_size + 30).unwrap();
+        }
+
+        // the segments before the recovery point are not checked
+        let mut log = PartitionLog::recover(&log_dir, "foo", 0, config, 4).unwrap();
+        assert_eq!(log.log_end_offset(), 4);
+        assert_eq!(log.recovery_point(), 4);
+        assert!(!Segment::path(&dir, 6).exists());
+        assert!(!index::OffsetIndex::path(&dir, 6).exists());
+        assert_eq!(base_offsets(&log.read(3, 1024, false).unwrap()), vec![3]);
+        assert_eq!(log.append(&record_batch(1)).unwrap().base_offset, 4);
+    }
+
+    #[test]
+    fn test_delete_old_segments() {
+        let log_dir = temp_log_dir();
+        let dir = PartitionLog::dir(&log_dir, "foo", 0);
+        let config = LogConfig {
+            segment_ms: 100,
+            retention_ms: 1000,
+            ..LogConfig::default()
+        };
+        let mut log = PartitionLog::open(&log_dir, "foo", 0, config).unwrap();
+        // a segment per batch, as their timestamps are too far apart
+        for timestamp in [0, 500, 1000] {
+            log.append(&timestamped_batch(timestamp)).unwrap();
+        }
+        assert_eq!(log.segments.len(), 3);
+
+        assert!(log.delete_old_segments(1000).unwrap().is_empty());
+        let deleted = log.delete_old_segments(1600).unwrap();
+        // the files are only renamed once the log is not needed anymore
+        assert!(dir.join("00000000000000000000.log").exists());
+        let files = deleted.rename().unwrap();
+        assert_eq!(
+            files,
+            vec![
+                dir.join("00000000000000000000.log.deleted"),
+                dir.join("00000000000000000000.index.deleted"),
+                dir.join("00000000000000000000.timeindex.deleted"),
+                dir.join("00000000000000000001.log.deleted"),
+                dir.join("00000000000000000001.index.deleted"),
+                dir.join("00000000000000000001.timeindex.deleted"),
+            ]
+        );
+        assert!(files.iter().all(|file| file.exists()));
+        assert_eq!(log.log_start_offset(), 2);
+        assert!(matches!(
+            log.read(1, 1024, false),
+            Err(LogError::OffsetOutOfRange { .. })
+        ));
+
+        // the active segment is deleted too once expired, after rolling a new one
+        log.delete_old_segments(3000).unwrap().rename().unwrap();
+        assert_eq!(log.log_start_offset(), 3);
+        assert_eq!(log.size(), 0);
+        assert_eq!(log.append(&timestamped_batch(3000)).unwrap().base_offset, 3);
+
+        // the renamed files are removed when the log is opened again
+        drop(log);
+        let log = PartitionLog::open(&log_dir, "foo", 0, LogConfig::default()).unwrap();
+        assert!(files.iter().all(|file| !file.exists()));
+        assert_eq!(log.log_start_offset(), 3);
+    }
+
+    #[test]
+    fn test_delete_old_segments_past_retention_bytes() {
+        let batch_size = record_batch(1).len() as u64;
+        let config = LogConfig {
+            segment_bytes: 2 * batch_size,
+            retention_bytes: 3 * batch_size as i64,
+            ..LogConfig::default()
+        };
+        let mut log = PartitionLog::open(&temp_log_dir(), "foo", 0, config).unwrap();
+        for _ in 0..7 {
+            log.append(&record_batch(1)).unwrap();
+        }
+        // segments of 2, 2, 2 and 1 batches, the first two are past retention.bytes
+        let deleted = log.delete_old_segments(0).unwrap();
+        assert_eq!(deleted.rename().unwrap().len(), 6);
+        assert_eq!(log.log_start_offset(), 4);
+        assert_eq!(log.size(), 3 * batch_size);
+        assert!(log.delete_old_segments(0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_cleanup_policy() {
+        assert_eq!("delete".parse(), Ok(CleanupPolicy::default()));
+        assert_eq!(
+            "compact, delete".parse(),
+            Ok(CleanupPolicy {
+                delete: true,
+                compact: true
+            })
+        );
+        assert!("".parse::<CleanupPolicy>().is_err());
+    }
+
+    // What the cleaner does, see `cleaner::clean_log`
+    fn clean(
+        log: &mut PartitionLog,
+        first_dirty_offset: i64,
+        now_ms: i64,
+    ) -> Option<(i64, Vec<PathBuf>)> {
+        let cleaning = log.start_cleaning(first_dirty_offset).unwrap()?;
+        let next_dirty_offset = cleaning.next_dirty_offset();
+        let cleaned = cleaning.clean(now_ms).unwrap();
+        Some((next_dirty_offset, log.replace_segments(cleaned).unwrap()))
+    }
+
+    #[test]
+    fn test_clean() {
+        let log_dir = temp_log_dir();
+        let dir = PartitionLog::dir(&log_dir, "foo", 0);
+        let batch_size = keyed_batch(b"a", Some(b"1")).len() as u64;
+        let config = LogConfig {
+            segment_bytes: 2 * batch_size,
+            cleanup_policy: "compact".parse().unwrap(),
+            delete_retention_ms: 1000,
+            ..LogConfig::default()
+        };
+        let mut log = PartitionLog::open(&log_dir, "foo", 0, config.clone()).unwrap();
+        for (key, value) in [
+            (b"a", Some(b"1")),
+            (b"b", Some(b"1")),
+            (b"a", Some(b"2")),
+            (b"b", None),
+            (b"c", Some(b"1")),
+        ] {
+            log.append(&keyed_batch(key, value.map(|value| value.as_slice())))
+                .unwrap();
+        }
+        let values = |log: &PartitionLog| {
+            log.segments
+                .iter()
+                .flat_map(|segment| {
+                    record::tests::read_batches(&segment.read_all().unwrap()).unwrap()
+                })
+                .flat_map(|batch| {
+                    batch.records.into_iter().map(move |record| {
+                        (batch.base_offset + record.offset_delta as i64, record.value)
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // the compact topics are not deleted by retention
+        assert!(log.delete_old_segments(i64::MAX).unwrap().is_empty());
+
+        // the replaced records are removed, not from the active segment
+        let (next_dirty_offset, files) = clean(&mut log, 0, 0).unwrap();
+        assert_eq!(next_dirty_offset, 4);
+        assert_eq!(files.len(), 3);
+        assert!(files.iter().all(|file| file.exists()));
+        assert_eq!(log.segments.len(), 3);
+        assert_eq!(
+            values(&log),
+            vec![
+                (2, Some(b"2".to_vec())),
+                (3, None),
+                (4, Some(b"1".to_vec()))
+            ]
+        );
+        assert_eq!(log.log_start_offset(), 0);
+
+        // nothing is dirty until more segments are rolled
+        assert_eq!(clean(&mut log, next_dirty_offset, 0), None);
+
+        // the tombstone is removed once older than delete.retention.ms, and the first two
+        // segments are merged as they now fit in one
+        log.append(&keyed_batch(b"c", Some(b"2"))).unwrap();
+        log.append(&keyed_batch(b"d", Some(b"1"))).unwrap();
+        let now_ms = 1_700_000_000_000 + 1001;
+        let (next_dirty_offset, _) = clean(&mut log, next_dirty_offset, now_ms).unwrap();
+        assert_eq!(next_dirty_offset, 6);
+        assert_eq!(log.segments.len(), 3);
+        assert_eq!(log.append(&keyed_batch(b"e", None)).unwrap().base_offset, 7);
+        assert_eq!(
+            values(&log),
+            vec![
+                (2, Some(b"2".to_vec())),
+                (5, Some(b"2".to_vec())),
+                (6, Some(b"1".to_vec())),
+                (7, None)
+            ]
+        );
+
+        // a swap left by a crash replaces the segments, with its indexes
+        drop(log);
+        let swap = segment::with_suffix(&Segment::path(&dir, 0), SWAP_SUFFIX);
+        fs::rename(Segment::path(&dir, 0), &swap).unwrap();
+        let index = index::OffsetIndex::path(&dir, 0);
+        let index_swap = segment::with_suffix(&index, SWAP_SUFFIX);
+        fs::rename(&index, &index_swap).unwrap();
+        let log = PartitionLog::open(&log_dir, "foo", 0, config).unwrap();
+        assert!(!swap.exists());
+        assert!(!index_swap.exists());
+        assert!(index.exists());
+        assert_eq!(
+            values(&log),
+            vec![
+                (2, Some(b"2".to_vec())),
+                (5, Some(b"2".to_vec())),
+                (6, Some(b"1".to_vec())),
+                (7, None)
+            ]
+        );
+    }
+}
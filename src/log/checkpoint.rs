@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+// Offsets per partition, in the text format of the Kafka checkpoint files of the log dirs:
+// a version line, a count line, then `topic partition offset` lines
+const VERSION: i32 = 0;
+
+pub type Offsets = HashMap<(String, i32), i64>;
+
+// A missing file has no offsets
+pub fn read(path: &Path) -> io::Result<Offsets> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Offsets::new()),
+        Err(e) => return Err(e),
+    };
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid checkpoint file {}", path.display()),
+        )
+    };
+
+    let mut lines = content.lines();
+    if lines.next().and_then(|version| version.parse().ok()) != Some(VERSION) {
+        return Err(invalid());
+    }
+    let count: usize = lines
+        .next()
+        .and_then(|count| count.parse().ok())
+        .ok_or_else(invalid)?;
+    let mut offsets = Offsets::new();
+    for line in lines.take(count) {
+        let mut fields = line.split(' ');
+        let (Some(topic), Some(partition), Some(offset), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid());
+        };
+        let partition = partition.parse().map_err(|_| invalid())?;
+        let offset = offset.parse().map_err(|_| invalid())?;
+        offsets.insert((topic.to_string(), partition), offset);
+    }
+    if offsets.len() != count {
+        return Err(invalid());
+    }
+    Ok(offsets)
+}
+
+// Written to a temporary file first, so that a crash leaves either the old or the new offsets
+pub fn write(path: &Path, offsets: &Offsets) -> io::Result<()> {
+    let mut content = format!("{}\n{}\n", VERSION, offsets.len());
+    let mut entries: Vec<_> = offsets.iter().collect();
+    entries.sort();
+    for ((topic, partition), offset) in entries {
+        content.push_str(&format!("{} {} {}\n", topic, partition, offset));
+    }
+
+    let temp_path = path.with_extension("tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::log::tests::temp_log_dir;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = temp_log_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("recovery-point-offset-checkpoint");
+        assert_eq!(read(&path).unwrap(), Offsets::new());
+
+        let offsets = Offsets::from([(("foo".to_string(), 0), 42), (("bar".to_string(), 1), 0)]);
+        write(&path, &offsets).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "0\n2\nbar 1 0\nfoo 0 42\n"
+        );
+        assert_eq!(read(&path).unwrap(), offsets);
+
+        fs::write(&path, "0\n2\nfoo 0 42\n").unwrap();
+        assert_eq!(read(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use bytes::Buf;
+
+use super::Result;
+use crate::record::{self, RecordBatch};
+
+// Latest offset of each key of the dirty segments
+pub type OffsetMap = HashMap<Vec<u8>, i64>;
+
+// Adds the keys of the records of `data`, control batches and legacy messages are not compacted
+pub fn add_keys(offsets: &mut OffsetMap, mut data: &[u8]) -> Result<()> {
+    while let Some(batch_size) = record::batch_size(data) {
+        if record::magic(data) == Some(record::CURRENT_MAGIC) {
+            let batch = RecordBatch::read(&mut &data[..batch_size])?;
+            if !batch.is_control() {
+                for record in batch.records {
+                    if let Some(key) = record.key {
+                        offsets.insert(key, batch.base_offset + record.offset_delta as i64);
+                    }
+                }
+            }
+        }
+        data.advance(batch_size);
+    }
+    Ok(())
+}
+
+// Appends the batches of `data` to `cleaned` without the records followed by a later one of the
+// same key, nor the tombstones older than `delete_horizon_ms`. The batches keep their offsets,
+// those left without records are dropped. Returns whether records were removed.
+pub fn clean(
+    mut data: &[u8],
+    offsets: &OffsetMap,
+    delete_horizon_ms: i64,
+    cleaned: &mut Vec<u8>,
+) -> Result<bool> {
+    let mut removed = false;
+    while let Some(batch_size) = record::batch_size(data) {
+        let original = &data[..batch_size];
+        data.advance(batch_size);
+        if record::magic(original) != Some(record::CURRENT_MAGIC) {
+            cleaned.extend_from_slice(original);
+            continue;
+        }
+        let mut batch = RecordBatch::read(&mut &original[..])?;
+        if batch.is_control() {
+            cleaned.extend_from_slice(original);
+            continue;
+        }
+
+        let count = batch.records.len();
+        let (base_offset, base_timestamp, max_timestamp) =
+            (batch.base_offset, batch.base_timestamp, batch.max_timestamp);
+        let is_log_append_time = batch.is_log_append_time();
+        batch.records.retain(|record| {
+            let Some(key) = &record.key else {
+                return true;
+            };
+            let offset = base_offset + record.offset_delta as i64;
+            let timestamp = if is_log_append_time {
+                max_timestamp
+            } else {
+                base_timestamp + record.timestamp_delta
+            };
+            let is_latest = offsets.get(key).map_or(true, |&latest| offset >= latest);
+            let is_expired_tombstone = record.value.is_none() && timestamp < delete_horizon_ms;
+            is_latest && !is_expired_tombstone
+        });
+
+        if batch.records.len() == count {
+            cleaned.extend_from_slice(original);
+        } else {
+            removed = true;
+            // the last offset delta is kept so that the offsets of the removed records are not
+            // assigned again
+            if !batch.records.is_empty() {
+                batch.write(cleaned);
+            }
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::record::tests::{batch, record};
+
+    fn keyed_batch(base_offset: i64, entries: &[(&[u8], Option<&[u8]>)]) -> Vec<u8> {
+        let records = entries
+            .iter()
+            .enumerate()
+            .map(|(offset_delta, (key, value))| {
+                let mut record = record(offset_delta as i32, b"");
+                record.key = Some(key.to_vec());
+                record.value = value.map(<[u8]>::to_vec);
+                record
+            })
+            .collect();
+        let mut buffer = vec![];
+        batch(base_offset, records).write(&mut buffer);
+        buffer
+    }
+
+    #[test]
+    fn test_clean_should_keep_the_latest_record_of_each_key() {
+        let mut data = keyed_batch(0, &[(b"a", Some(b"1")), (b"b", Some(b"1"))]);
+        data.extend(keyed_batch(2, &[(b"a", Some(b"2")), (b"c", None)]));
+        data.extend(keyed_batch(4, &[(b"b", None)]));
+        let mut offsets = OffsetMap::new();
+        add_keys(&mut offsets, &data).unwrap();
+        assert_eq!(offsets[b"a".as_slice()], 2);
+        assert_eq!(offsets[b"b".as_slice()], 4);
+
+        // the tombstones are kept until the delete horizon
+        let mut cleaned = vec![];
+        assert!(clean(&data, &offsets, 0, &mut cleaned).unwrap());
+        let batches = record::tests::read_batches(&cleaned).unwrap();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].base_offset, 2);
+        assert_eq!(batches[0].last_offset_delta, 1);
+        assert_eq!(batches[0].records.len(), 2);
+        assert_eq!(batches[1].base_offset, 4);
+
+        let mut compacted = vec![];
+        assert!(clean(&cleaned, &offsets, i64::MAX, &mut compacted).unwrap());
+        let batches = record::tests::read_batches(&compacted).unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].records.len(), 1);
+        assert_eq!(batches[0].records[0].key.as_deref(), Some(b"a".as_slice()));
+
+        let mut unchanged = vec![];
+        assert!(!clean(&compacted, &offsets, i64::MAX, &mut unchanged).unwrap());
+        assert_eq!(unchanged, compacted);
+    }
+}
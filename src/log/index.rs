@@ -0,0 +1,291 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use memmap2::{Mmap, MmapOptions};
+
+// Sizes of the entries, which hold offsets relative to the base offset of the segment
+// https://kafka.apache.org/documentation/#log
+const OFFSET_ENTRY_SIZE: usize = 8;
+const TIME_ENTRY_SIZE: usize = 12;
+
+// Entries of a sparse index, in increasing order. The file keeps the length of its entries, and
+// is read through a read-only memory map. The entries appended since the last flush, which only
+// the active segment has, are kept in memory until the flush writes them and remaps the file, as
+// when the segment rolls or once it is truncated.
+#[derive(Debug)]
+struct IndexFile {
+    file: File,
+    // `None` while no entry is flushed, as an empty file cannot be mapped
+    mmap: Option<Mmap>,
+    // bytes of the mapped entries, and of the file, which can be longer until the next flush
+    mapped: usize,
+    file_length: usize,
+    appended: Vec<u8>,
+    entry_size: usize,
+    capacity: usize,
+    // the file did not hold whole entries when opened
+    misaligned: bool,
+}
+
+impl IndexFile {
+    fn open(path: &Path, entry_size: usize, max_bytes: usize) -> io::Result<IndexFile> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let file_length = file.metadata()?.len() as usize;
+        let mapped = file_length - file_length % entry_size;
+        Ok(IndexFile {
+            mmap: map(&file, mapped)?,
+            file,
+            mapped,
+            file_length,
+            appended: vec![],
+            entry_size,
+            capacity: (max_bytes / entry_size).max(mapped / entry_size).max(1),
+            misaligned: file_length % entry_size != 0,
+        })
+    }
+
+    fn len(&self) -> usize {
+        (self.mapped + self.appended.len()) / self.entry_size
+    }
+
+    fn entry(&self, n: usize) -> &[u8] {
+        let start = n * self.entry_size;
+        match &self.mmap {
+            Some(mmap) if start < self.mapped => &mmap[start..start + self.entry_size],
+            _ => &self.appended[start - self.mapped..start - self.mapped + self.entry_size],
+        }
+    }
+
+    fn last_entry(&self) -> Option<&[u8]> {
+        self.len().checked_sub(1).map(|n| self.entry(n))
+    }
+
+    // Number of entries for which `predicate` holds, as they are sorted by it
+    fn partition_point(&self, predicate: impl Fn(&[u8]) -> bool) -> usize {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if predicate(self.entry(middle)) {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        low
+    }
+
+    fn is_full(&self) -> bool {
+        self.len() >= self.capacity
+    }
+
+    // Entries past the capacity are dropped, the index stays usable as it is sparse
+    fn append(&mut self, entry: &[u8]) {
+        if !self.is_full() {
+            self.appended.extend_from_slice(entry);
+        }
+    }
+
+    // The file is truncated by the next flush
+    fn clear(&mut self) {
+        self.mmap = None;
+        self.mapped = 0;
+        self.appended.clear();
+        self.misaligned = false;
+    }
+
+    // Writes the appended entries, drops the cleared ones, and maps the file again
+    fn flush(&mut self) -> io::Result<()> {
+        if self.appended.is_empty() && self.file_length == self.mapped {
+            return Ok(());
+        }
+        // the map is not read past the new end of the file, which would be a SIGBUS
+        self.mmap = None;
+        if self.file_length > self.mapped {
+            self.file.set_len(self.mapped as u64)?;
+        }
+        self.file.write_all_at(&self.appended, self.mapped as u64)?;
+        self.file.sync_data()?;
+        self.mapped += self.appended.len();
+        self.file_length = self.mapped;
+        self.appended.clear();
+        self.mmap = map(&self.file, self.mapped)?;
+        Ok(())
+    }
+}
+
+impl Drop for IndexFile {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+fn map(file: &File, length: usize) -> io::Result<Option<Mmap>> {
+    if length == 0 {
+        return Ok(None);
+    }
+    // SAFETY: the mapped bytes are not written again, and the file is only truncated once the
+    // map is dropped
+    unsafe { MmapOptions::new().len(length).map(file) }.map(Some)
+}
+
+// Maps the last offset of a batch to its position in the segment file
+#[derive(Debug)]
+pub struct OffsetIndex {
+    base_offset: i64,
+    index: IndexFile,
+}
+
+impl OffsetIndex {
+    pub fn open(path: &Path, base_offset: i64, max_bytes: usize) -> io::Result<OffsetIndex> {
+        Ok(OffsetIndex {
+            base_offset,
+            index: IndexFile::open(path, OFFSET_ENTRY_SIZE, max_bytes)?,
+        })
+    }
+
+    pub fn path(dir: &Path, base_offset: i64) -> PathBuf {
+        dir.join(format!("{:020}.index", base_offset))
+    }
+
+    fn entry(&self, entry: &[u8]) -> (i64, u64) {
+        let relative_offset = i32::from_be_bytes(entry[..4].try_into().unwrap());
+        let position = u32::from_be_bytes(entry[4..].try_into().unwrap());
+        (self.base_offset + relative_offset as i64, position as u64)
+    }
+
+    // Position of a batch at or before the one holding `offset`, the start of the segment when
+    // no entry precedes it
+    pub fn lookup(&self, offset: i64) -> u64 {
+        let n = self
+            .index
+            .partition_point(|entry| self.entry(entry).0 <= offset);
+        n.checked_sub(1)
+            .map_or(0, |n| self.entry(self.index.entry(n)).1)
+    }
+
+    pub fn last_position(&self) -> u64 {
+        self.index
+            .last_entry()
+            .map_or(0, |entry| self.entry(entry).1)
+    }
+
+    pub fn append(&mut self, offset: i64, position: u64) {
+        let mut entry = [0; OFFSET_ENTRY_SIZE];
+        entry[..4].copy_from_slice(&((offset - self.base_offset) as i32).to_be_bytes());
+        entry[4..].copy_from_slice(&(position as u32).to_be_bytes());
+        self.index.append(&entry);
+    }
+
+    // Offsets and positions must increase and stay within the segment
+    pub fn is_valid(&self, segment_size: u64) -> bool {
+        let entries: Vec<_> = (0..self.index.len())
+            .map(|n| self.entry(self.index.entry(n)))
+            .collect();
+        !self.index.misaligned
+            && entries
+                .windows(2)
+                .all(|pair| pair[0].0 < pair[1].0 && pair[0].1 < pair[1].1)
+            && entries
+                .iter()
+                .all(|&(offset, position)| offset >= self.base_offset && position < segment_size)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.index.is_full()
+    }
+
+    pub fn clear(&mut self) {
+        self.index.clear();
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.index.flush()
+    }
+}
+
+// Maps the largest timestamp of the batches so far to the last offset of its batch
+#[derive(Debug)]
+pub struct TimeIndex {
+    base_offset: i64,
+    index: IndexFile,
+}
+
+impl TimeIndex {
+    pub fn open(path: &Path, base_offset: i64, max_bytes: usize) -> io::Result<TimeIndex> {
+        Ok(TimeIndex {
+            base_offset,
+            index: IndexFile::open(path, TIME_ENTRY_SIZE, max_bytes)?,
+        })
+    }
+
+    pub fn path(dir: &Path, base_offset: i64) -> PathBuf {
+        dir.join(format!("{:020}.timeindex", base_offset))
+    }
+
+    fn entry(&self, entry: &[u8]) -> (i64, i64) {
+        let timestamp = i64::from_be_bytes(entry[..8].try_into().unwrap());
+        let relative_offset = i32::from_be_bytes(entry[8..].try_into().unwrap());
+        (timestamp, self.base_offset + relative_offset as i64)
+    }
+
+    // Offset from which the batches can have a timestamp at or after `timestamp`, those before
+    // are all older
+    pub fn lookup(&self, timestamp: i64) -> i64 {
+        let n = self
+            .index
+            .partition_point(|entry| self.entry(entry).0 < timestamp);
+        n.checked_sub(1)
+            .map_or(self.base_offset, |n| self.entry(self.index.entry(n)).1 + 1)
+    }
+
+    pub fn last_entry(&self) -> Option<(i64, i64)> {
+        self.index.last_entry().map(|entry| self.entry(entry))
+    }
+
+    // Only a timestamp larger than the last one is indexed
+    pub fn maybe_append(&mut self, timestamp: i64, offset: i64) {
+        if self
+            .last_entry()
+            .is_some_and(|(last_timestamp, _)| timestamp <= last_timestamp)
+        {
+            return;
+        }
+        let mut entry = [0; TIME_ENTRY_SIZE];
+        entry[..8].copy_from_slice(&timestamp.to_be_bytes());
+        entry[8..].copy_from_slice(&((offset - self.base_offset) as i32).to_be_bytes());
+        self.index.append(&entry);
+    }
+
+    // Timestamps must increase, and offsets not decrease
+    pub fn is_valid(&self) -> bool {
+        let entries: Vec<_> = (0..self.index.len())
+            .map(|n| self.entry(self.index.entry(n)))
+            .collect();
+        !self.index.misaligned
+            && entries
+                .windows(2)
+                .all(|pair| pair[0].0 < pair[1].0 && pair[0].1 <= pair[1].1)
+            && entries
+                .iter()
+                .all(|&(_, offset)| offset >= self.base_offset)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.index.is_full()
+    }
+
+    pub fn clear(&mut self) {
+        self.index.clear();
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.index.flush()
+    }
+}
@@ -0,0 +1,119 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::server::model::Uuid;
+
+// File of a partition dir holding the id of its topic, in the Kafka format: a version line, then
+// the id in the url-safe base64 of `org.apache.kafka.common.Uuid`
+pub const FILE: &str = "partition.metadata";
+const VERSION: i32 = 0;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+// A missing file has no topic id
+pub fn read(dir: &Path) -> io::Result<Option<Uuid>> {
+    let path = dir.join(FILE);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid partition metadata file {}", path.display()),
+        )
+    };
+
+    let mut lines = content.lines();
+    let field = |line: Option<&str>, name| {
+        line.and_then(|line| line.split_once(':'))
+            .filter(|(key, _)| key.trim() == name)
+            .map(|(_, value)| value.trim().to_string())
+    };
+    if field(lines.next(), "version").and_then(|version| version.parse().ok()) != Some(VERSION) {
+        return Err(invalid());
+    }
+    let topic_id = field(lines.next(), "topic_id").ok_or_else(invalid)?;
+    decode_uuid(&topic_id).map(Some).ok_or_else(invalid)
+}
+
+// Written to a temporary file first, so that a crash leaves either no file or the whole one
+pub fn write(dir: &Path, topic_id: Uuid) -> io::Result<()> {
+    let content = format!(
+        "version: {}\ntopic_id: {}\n",
+        VERSION,
+        encode_uuid(topic_id)
+    );
+    let path = dir.join(FILE);
+    let temp_path = path.with_extension("tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)
+}
+
+// 22 characters, as the padding is left out
+fn encode_uuid(uuid: Uuid) -> String {
+    (0..22)
+        .map(|i| {
+            // the 128 bits are followed by 4 zero bits to fill the last character
+            let shift = 128 - 6 * (i + 1);
+            let sextet = if shift >= 0 {
+                uuid >> shift
+            } else {
+                uuid << -shift
+            };
+            BASE64_ALPHABET[(sextet & 0x3f) as usize] as char
+        })
+        .collect()
+}
+
+fn decode_uuid(value: &str) -> Option<Uuid> {
+    if value.len() != 22 {
+        return None;
+    }
+    let sextet = |c| {
+        BASE64_ALPHABET
+            .iter()
+            .position(|&a| a == c)
+            .map(|i| i as u128)
+    };
+    let bits = value.as_bytes()[..21]
+        .iter()
+        .try_fold(0u128, |bits, &c| Some(bits << 6 | sextet(c)?))?;
+    // the last character holds the last 2 bits and the 4 zero bits
+    let last = sextet(value.as_bytes()[21])?;
+    (last & 0xf == 0).then_some(bits << 2 | last >> 4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::log::tests::temp_log_dir;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = temp_log_dir();
+        fs::create_dir_all(&dir).unwrap();
+        assert_eq!(read(&dir).unwrap(), None);
+
+        let topic_id = 0x7a3f_52c1_09b8_4e6d_a1f0_33c2_d4e5_f607;
+        write(&dir, topic_id).unwrap();
+        assert_eq!(read(&dir).unwrap(), Some(topic_id));
+    }
+
+    #[test]
+    fn test_uuids_are_encoded_like_kafka() {
+        // Uuid.METADATA_TOPIC_ID, the uuid with only the last bit set
+        assert_eq!(encode_uuid(1), "AAAAAAAAAAAAAAAAAAAAAQ");
+        assert_eq!(decode_uuid("AAAAAAAAAAAAAAAAAAAAAQ"), Some(1));
+        assert_eq!(encode_uuid(u128::MAX), "_____________________w");
+        assert_eq!(decode_uuid("_____________________w"), Some(u128::MAX));
+        assert_eq!(decode_uuid("AAAAAAAAAAAAAAAAAAAAAB"), None);
+        assert_eq!(decode_uuid("AAAA"), None);
+    }
+}
@@ -0,0 +1,444 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use super::index::{OffsetIndex, TimeIndex};
+use super::{LogConfig, Result};
+use crate::record::{self, legacy, RecordError};
+
+// File of consecutive batches, named by the offset of its first batch, with its sparse offset and
+// time indexes
+#[derive(Debug)]
+pub struct Segment {
+    pub base_offset: i64,
+    file: File,
+    size: u64,
+    offset_index: OffsetIndex,
+    time_index: TimeIndex,
+    index_interval_bytes: u64,
+    bytes_since_last_index_entry: u64,
+    // of the last batch, `None` while the segment is empty
+    last_offset: Option<i64>,
+    // largest timestamp of the batches and the last offset of its batch
+    max_timestamp: i64,
+    offset_of_max_timestamp: i64,
+    // of the first batch, segment.ms is counted from it
+    first_timestamp: Option<i64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatchPosition {
+    pub base_offset: i64,
+    pub last_offset: i64,
+    pub max_timestamp: i64,
+    pub position: u64,
+    pub size: usize,
+}
+
+impl BatchPosition {
+    fn end(&self) -> u64 {
+        self.position + self.size as u64
+    }
+}
+
+impl Segment {
+    // Opens the segment, created empty if it does not exist. The indexes are rebuilt from the
+    // batches when they are missing or corrupt.
+    pub fn open(dir: &Path, base_offset: i64, config: &LogConfig) -> Result<Segment> {
+        Self::open_files(Self::files(dir, base_offset, None), base_offset, config)
+    }
+
+    // Creates an empty segment whose files have `.suffix` appended, like the ones written by the
+    // cleaner. The files left by a failed attempt are replaced.
+    pub fn create_with_suffix(
+        dir: &Path,
+        base_offset: i64,
+        config: &LogConfig,
+        suffix: &str,
+    ) -> Result<Segment> {
+        let files = Self::files(dir, base_offset, Some(suffix));
+        for file in &files {
+            match fs::remove_file(file) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Self::open_files(files, base_offset, config)
+    }
+
+    fn open_files(
+        [path, offset_index_path, time_index_path]: [PathBuf; 3],
+        base_offset: i64,
+        config: &LogConfig,
+    ) -> Result<Segment> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let indexes_exist = offset_index_path.exists() && time_index_path.exists();
+
+        let mut segment = Segment {
+            base_offset,
+            size: file.metadata()?.len(),
+            file,
+            offset_index: OffsetIndex::open(
+                &offset_index_path,
+                base_offset,
+                config.max_index_bytes,
+            )?,
+            time_index: TimeIndex::open(&time_index_path, base_offset, config.max_index_bytes)?,
+            index_interval_bytes: config.index_interval_bytes,
+            bytes_since_last_index_entry: 0,
+            last_offset: None,
+            max_timestamp: legacy::NO_TIMESTAMP,
+            offset_of_max_timestamp: base_offset,
+            first_timestamp: None,
+        };
+        if indexes_exist
+            && segment.offset_index.is_valid(segment.size)
+            && segment.time_index.is_valid()
+        {
+            // only the batches from the last indexed one are scanned
+            segment.first_timestamp = segment.batch_at(0)?.map(|batch| batch.max_timestamp);
+            if let Some((timestamp, offset)) = segment.time_index.last_entry() {
+                segment.max_timestamp = timestamp;
+                segment.offset_of_max_timestamp = offset;
+            }
+            segment.load_batches(segment.offset_index.last_position(), false)?;
+        } else {
+            segment.rebuild_indexes(false)?;
+        }
+        Ok(segment)
+    }
+
+    pub fn path(dir: &Path, base_offset: i64) -> PathBuf {
+        dir.join(format!("{:020}.log", base_offset))
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn last_offset(&self) -> Option<i64> {
+        self.last_offset
+    }
+
+    pub fn first_offset(&self) -> Result<Option<i64>> {
+        Ok(self.batch_at(0)?.map(|batch| batch.base_offset))
+    }
+
+    // `None` for an empty segment, or one of v0 messages which have no timestamp
+    pub fn first_timestamp(&self) -> Option<i64> {
+        self.first_timestamp
+            .filter(|&timestamp| timestamp != legacy::NO_TIMESTAMP)
+    }
+
+    // Largest timestamp of the batches, the time the file was last modified when they have none
+    pub fn largest_timestamp(&self) -> Result<i64> {
+        if self.max_timestamp != legacy::NO_TIMESTAMP {
+            return Ok(self.max_timestamp);
+        }
+        let modified = self.file.metadata()?.modified()?;
+        Ok(modified
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_millis() as i64))
+    }
+
+    pub fn is_index_full(&self) -> bool {
+        self.offset_index.is_full() || self.time_index.is_full()
+    }
+
+    // Checks the crc of every batch after a crash, the segment is truncated at the first corrupt
+    // one. Returns whether batches were dropped.
+    pub fn recover(&mut self) -> Result<bool> {
+        self.rebuild_indexes(true)
+    }
+
+    fn rebuild_indexes(&mut self, check_crcs: bool) -> Result<bool> {
+        self.offset_index.clear();
+        self.time_index.clear();
+        self.bytes_since_last_index_entry = 0;
+        self.last_offset = None;
+        self.max_timestamp = legacy::NO_TIMESTAMP;
+        self.offset_of_max_timestamp = self.base_offset;
+        self.first_timestamp = None;
+        self.load_batches(0, check_crcs)
+    }
+
+    // Indexes the batches from `position`, which is the start of an indexed batch, up to the
+    // end of the file. A batch cut by a crash is dropped so that the next appends start on a
+    // batch boundary. Returns whether the segment was truncated.
+    fn load_batches(&mut self, mut position: u64, check_crcs: bool) -> Result<bool> {
+        while let Some(batch) = self.batch_at(position)? {
+            if check_crcs && !self.is_valid(&batch)? {
+                break;
+            }
+            self.index(&batch);
+            self.last_offset = Some(batch.last_offset);
+            position = batch.end();
+        }
+        let truncated = position < self.size;
+        if truncated {
+            self.file.set_len(position)?;
+            self.size = position;
+        }
+        Ok(truncated)
+    }
+
+    fn is_valid(&self, batch: &BatchPosition) -> Result<bool> {
+        let mut data = vec![0; batch.size];
+        self.file.read_exact_at(&mut data, batch.position)?;
+        Ok(if record::magic(&data) == Some(record::CURRENT_MAGIC) {
+            record::validate(&data).is_ok()
+        } else {
+            legacy::Message::read(&mut data.as_slice()).is_ok()
+        })
+    }
+
+    // Adds index entries every index.interval.bytes of batches, like Kafka the first batch is
+    // not indexed as the lookups start at the beginning of the segment anyway
+    fn index(&mut self, batch: &BatchPosition) {
+        self.first_timestamp.get_or_insert(batch.max_timestamp);
+        if batch.max_timestamp > self.max_timestamp {
+            self.max_timestamp = batch.max_timestamp;
+            self.offset_of_max_timestamp = batch.last_offset;
+        }
+        if self.bytes_since_last_index_entry > self.index_interval_bytes {
+            self.offset_index.append(batch.last_offset, batch.position);
+            self.time_index
+                .maybe_append(self.max_timestamp, self.offset_of_max_timestamp);
+            self.bytes_since_last_index_entry = 0;
+        }
+        self.bytes_since_last_index_entry += batch.size as u64;
+    }
+
+    // Writes batches whose positions are relative to the end of the segment
+    pub fn append(&mut self, records: &[u8], batches: &[BatchPosition]) -> Result<()> {
+        self.file.write_all(records)?;
+        for batch in batches {
+            self.index(&BatchPosition {
+                position: self.size + batch.position,
+                ..*batch
+            });
+        }
+        self.size += records.len() as u64;
+        if let Some(batch) = batches.last() {
+            self.last_offset = Some(batch.last_offset);
+        }
+        Ok(())
+    }
+
+    // Reads the batches from the one holding `offset`, up to `max_bytes`, or the first one
+    // whatever its size with `min_one_batch`
+    pub fn read(&self, offset: i64, max_bytes: usize, min_one_batch: bool) -> Result<Vec<u8>> {
+        let mut position = self.offset_index.lookup(offset);
+        let first = loop {
+            match self.batch_at(position)? {
+                Some(batch) if batch.last_offset >= offset => break batch,
+                Some(batch) => position = batch.end(),
+                None => return Ok(vec![]),
+            }
+        };
+
+        let mut size = 0;
+        let mut next = Some(first);
+        while let Some(batch) = next {
+            if size + batch.size > max_bytes && !(size == 0 && min_one_batch) {
+                break;
+            }
+            size += batch.size;
+            next = self.batch_at(batch.end())?;
+        }
+
+        let mut data = vec![0; size];
+        if size > 0 {
+            self.file.read_exact_at(&mut data, first.position)?;
+        }
+        Ok(data)
+    }
+
+    // Appends one batch, or one message of an old broker, with its offsets
+    pub fn append_batch(&mut self, batch: &[u8]) -> Result<()> {
+        let position = parse_batch_header(batch, 0, batch.len() as u64)
+            .filter(|position| position.size == batch.len())
+            .ok_or(RecordError::CorruptBatch("invalid batch header"))?;
+        self.append(batch, &[position])
+    }
+
+    // Reads the batches as they are now, without borrowing the segment
+    pub fn reader(&self) -> Result<SegmentReader> {
+        Ok(SegmentReader {
+            file: self.file.try_clone()?,
+            size: self.size,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn read_all(&self) -> Result<Vec<u8>> {
+        let mut data = vec![0; self.size as usize];
+        self.file.read_exact_at(&mut data, 0)?;
+        Ok(data)
+    }
+
+    // Offset of the first batch with a timestamp at or after `timestamp`, found from the time
+    // index
+    pub fn offset_for_timestamp(&self, timestamp: i64) -> Result<Option<i64>> {
+        if self.max_timestamp < timestamp {
+            return Ok(None);
+        }
+        let mut position = self.offset_index.lookup(self.time_index.lookup(timestamp));
+        while let Some(batch) = self.batch_at(position)? {
+            if batch.max_timestamp >= timestamp {
+                return Ok(Some(batch.base_offset));
+            }
+            position = batch.end();
+        }
+        Ok(None)
+    }
+
+    fn batch_at(&self, position: u64) -> Result<Option<BatchPosition>> {
+        batch_at(&self.file, self.size, position)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.file.sync_data()?;
+        self.offset_index.flush()?;
+        self.time_index.flush()?;
+        Ok(())
+    }
+
+    // Once it is not the active segment anymore, the time index gets the largest timestamp of
+    // the segment
+    pub fn seal(&mut self) -> Result<()> {
+        if self.last_offset.is_some() {
+            self.time_index
+                .maybe_append(self.max_timestamp, self.offset_of_max_timestamp);
+        }
+        self.flush()
+    }
+
+    // The segment file and its indexes, with `.suffix` appended to their names
+    fn files(dir: &Path, base_offset: i64, suffix: Option<&str>) -> [PathBuf; 3] {
+        [
+            Self::path(dir, base_offset),
+            OffsetIndex::path(dir, base_offset),
+            TimeIndex::path(dir, base_offset),
+        ]
+        .map(|path| match suffix {
+            Some(suffix) => with_suffix(&path, suffix),
+            None => path,
+        })
+    }
+
+    // Removes the segment file and its indexes, whose names have `.suffix` appended if any
+    pub fn delete(self, dir: &Path, suffix: Option<&str>) -> Result<()> {
+        for file in Self::files(dir, self.base_offset, suffix) {
+            fs::remove_file(file)?;
+        }
+        Ok(())
+    }
+
+    // Adds `suffix` to the names of the segment file and its indexes, and returns their new paths
+    pub fn rename(self, dir: &Path, suffix: &str) -> Result<Vec<PathBuf>> {
+        let files = Self::files(dir, self.base_offset, None);
+        let renamed = Self::files(dir, self.base_offset, Some(suffix));
+        for (file, name) in files.iter().zip(&renamed) {
+            fs::rename(file, name)?;
+        }
+        Ok(renamed.to_vec())
+    }
+
+    // Changes the suffix of the names of the files, which stay open. The segment file goes last,
+    // see `PartitionLog::complete_swap`.
+    pub fn change_suffix(&self, dir: &Path, from: Option<&str>, to: Option<&str>) -> Result<()> {
+        let files = Self::files(dir, self.base_offset, from);
+        let renamed = Self::files(dir, self.base_offset, to);
+        for (file, name) in files.iter().zip(&renamed).rev() {
+            fs::rename(file, name)?;
+        }
+        Ok(())
+    }
+}
+
+// Batches of a segment read one at a time, which the cleaner does without holding the log
+#[derive(Debug)]
+pub struct SegmentReader {
+    file: File,
+    size: u64,
+}
+
+impl SegmentReader {
+    // Calls `f` with each batch, from the start of the segment
+    pub fn for_each_batch(&self, mut f: impl FnMut(&[u8]) -> Result<()>) -> Result<()> {
+        let mut position = 0;
+        let mut data = vec![];
+        while let Some(batch) = batch_at(&self.file, self.size, position)? {
+            data.resize(batch.size, 0);
+            self.file.read_exact_at(&mut data, batch.position)?;
+            f(&data)?;
+            position = batch.end();
+        }
+        Ok(())
+    }
+}
+
+// The header of the batch at `position` of a segment file of `size` bytes, `None` at the end of
+// the segment or when the batch is cut
+fn batch_at(file: &File, size: u64, position: u64) -> Result<Option<BatchPosition>> {
+    let available = size.saturating_sub(position);
+    let mut header = vec![0; available.min(record::BATCH_HEADER_SIZE as u64) as usize];
+    file.read_exact_at(&mut header, position)?;
+    Ok(parse_batch_header(&header, position, available))
+}
+
+// `header` holds the start of the batch, which has `available` bytes up to the end of the segment
+fn parse_batch_header(header: &[u8], position: u64, available: u64) -> Option<BatchPosition> {
+    if header.len() < record::LOG_OVERHEAD {
+        return None;
+    }
+    let batch_length = read_i32(header, record::BATCH_LENGTH);
+    let size = usize::try_from(batch_length)
+        .ok()
+        .map(|batch_length| record::LOG_OVERHEAD + batch_length)
+        .filter(|&size| size as u64 <= available)?;
+
+    let base_offset = read_i64(header, record::BASE_OFFSET);
+    // segments written by old brokers hold messages, whose offset is their last offset
+    let (last_offset, max_timestamp) = match record::magic(header) {
+        Some(0) if size >= legacy::MIN_MESSAGE_SIZE => (base_offset, legacy::NO_TIMESTAMP),
+        Some(1) if size >= legacy::MIN_MESSAGE_SIZE + 8 => {
+            (base_offset, read_i64(header, legacy::TIMESTAMP))
+        }
+        Some(record::CURRENT_MAGIC) if size >= record::BATCH_HEADER_SIZE => (
+            base_offset + read_i32(header, record::LAST_OFFSET_DELTA) as i64,
+            read_i64(header, record::MAX_TIMESTAMP),
+        ),
+        _ => return None,
+    };
+    Some(BatchPosition {
+        base_offset,
+        last_offset,
+        max_timestamp,
+        position,
+        size,
+    })
+}
+
+// `path` with `.suffix` appended, like Kafka does for the files being deleted or replaced
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+pub fn read_i32(data: &[u8], position: usize) -> i32 {
+    i32::from_be_bytes(data[position..position + 4].try_into().unwrap())
+}
+
+pub fn read_i64(data: &[u8], position: usize) -> i64 {
+    i64::from_be_bytes(data[position..position + 8].try_into().unwrap())
+}
@@ -1,8 +1,64 @@
+mod broker;
+mod catalog;
+mod cleaner;
+mod cluster_metadata;
+mod config;
+mod fetch_session;
+mod log;
+mod purgatory;
+mod record;
 mod request_handler;
+mod retention;
 mod server;
 use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    server::start_server("127.0.0.1:9092").await?;
+    // the only argument is an optional server.properties, like for kafka-server-start.sh
+    let config = match std::env::args().nth(1) {
+        Some(path) => config::Config::load(Path::new(&path))?,
+        None => config::Config::default(),
+    };
+    let mut broker = broker::Broker::new(&config);
+    let cluster_metadata = broker.load_cluster_metadata()?;
+    println!(
+        "Loaded {} topics from the cluster metadata log, up to offset {}",
+        cluster_metadata.topics().len(),
+        cluster_metadata.last_offset
+    );
+    println!("Loaded {} partition logs", broker.load_logs()?);
+
+    let broker = Arc::new(broker);
+    tokio::spawn(retention::run(
+        broker.clone(),
+        Duration::from_millis(config.log_retention_check_interval_ms),
+    ));
+    if config.log_cleaner_enable {
+        tokio::spawn(cleaner::run(
+            broker.clone(),
+            Duration::from_millis(config.log_cleaner_backoff_ms),
+        ));
+    }
+    let address = config.bind_address();
+    tokio::select! {
+        result = server::start_server(&address, broker.clone()) => result?,
+        result = shutdown_signal() => {
+            result?;
+            broker.shutdown()?;
+            println!("Shut down cleanly");
+        }
+    }
     Ok(())
 }
+
+// Ctrl-C, or the SIGTERM sent by kafka-server-stop.sh
+async fn shutdown_signal() -> std::io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = terminate.recv() => Ok(()),
+    }
+}
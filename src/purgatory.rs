@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+
+use tokio::sync::Notify;
+
+type Watchers = Vec<Weak<Notify>>;
+
+// Requests parked until one of the partitions they watch is appended to, like the delayed
+// fetches of the Kafka purgatory
+#[derive(Debug, Default)]
+pub struct Purgatory {
+    watchers: Mutex<HashMap<(String, i32), Watchers>>,
+}
+
+impl Purgatory {
+    // The returned notify is woken by the next `complete` of one of the partitions, even when it
+    // happens before the notify is awaited. Dropping it stops watching.
+    pub fn watch(&self, partitions: impl IntoIterator<Item = (String, i32)>) -> Arc<Notify> {
+        let notify = Arc::new(Notify::new());
+        let mut watchers = self.watchers.lock().unwrap();
+        for partition in partitions {
+            let partition_watchers = watchers.entry(partition).or_default();
+            // the requests that are done are only removed here, or when the partition completes
+            partition_watchers.retain(|watcher| watcher.strong_count() > 0);
+            partition_watchers.push(Arc::downgrade(&notify));
+        }
+        notify
+    }
+
+    pub fn complete(&self, topic: &str, partition: i32) {
+        let watchers = self
+            .watchers
+            .lock()
+            .unwrap()
+            .remove(&(topic.to_string(), partition));
+        for notify in watchers
+            .into_iter()
+            .flatten()
+            .filter_map(|watcher| watcher.upgrade())
+        {
+            notify.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_complete_should_wake_the_watchers_of_the_partition() {
+        let purgatory = Purgatory::default();
+        let foo = purgatory.watch([("foo".to_string(), 0), ("foo".to_string(), 1)]);
+        let bar = purgatory.watch([("bar".to_string(), 0)]);
+        drop(purgatory.watch([("foo".to_string(), 1)]));
+
+        purgatory.complete("foo", 1);
+        purgatory.complete("baz", 0);
+
+        // the permit is kept until the watcher awaits it
+        foo.notified().await;
+        let timeout = std::time::Duration::from_millis(10);
+        assert!(tokio::time::timeout(timeout, bar.notified()).await.is_err());
+
+        let watchers = purgatory.watchers.lock().unwrap();
+        assert!(!watchers.contains_key(&("foo".to_string(), 1)));
+        assert_eq!(watchers[&("foo".to_string(), 0)].len(), 1);
+    }
+}
@@ -0,0 +1,525 @@
+use bytes::{Buf, BufMut};
+
+use crate::server::codec;
+use crate::server::codec::{Decoder, Encoder};
+
+pub mod compression;
+pub mod legacy;
+
+use compression::Compression;
+
+// Offsets of the record batch v2 header fields
+// https://kafka.apache.org/documentation/#recordbatch
+pub const BASE_OFFSET: usize = 0;
+pub const BATCH_LENGTH: usize = 8;
+pub const MAGIC: usize = 16;
+const CRC: usize = 17;
+const ATTRIBUTES: usize = 21;
+pub const LAST_OFFSET_DELTA: usize = 23;
+pub const MAX_TIMESTAMP: usize = 35;
+pub const BATCH_HEADER_SIZE: usize = 61;
+
+// Size of the base offset and batch length fields, which are not counted in the batch length
+pub const LOG_OVERHEAD: usize = 12;
+
+pub const CURRENT_MAGIC: i8 = 2;
+
+const COMPRESSION_CODEC_MASK: i16 = 0x07;
+const TIMESTAMP_TYPE_FLAG: i16 = 0x08;
+const CONTROL_FLAG: i16 = 0x20;
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum RecordError {
+    #[error("corrupt record batch: {0}")]
+    CorruptBatch(&'static str),
+    #[error("record batch crc {stored:#010x} does not match the computed crc {computed:#010x}")]
+    InvalidCrc { stored: u32, computed: u32 },
+    #[error("unsupported message format {0}")]
+    UnsupportedMagic(i8),
+    #[error("unsupported compression codec {0}")]
+    UnsupportedCompression(i16),
+    #[error("corrupt record: {0}")]
+    CorruptRecord(#[from] codec::DecodeError),
+    #[error("cannot decompress the records: {0}")]
+    Decompression(String),
+}
+
+pub type Result<T> = std::result::Result<T, RecordError>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordBatch {
+    pub base_offset: i64,
+    pub partition_leader_epoch: i32,
+    pub attributes: i16,
+    pub last_offset_delta: i32,
+    pub base_timestamp: i64,
+    pub max_timestamp: i64,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub base_sequence: i32,
+    pub records: Vec<Record>,
+}
+
+// Offsets and timestamps are stored as deltas from the ones of the batch
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub attributes: i8,
+    pub timestamp_delta: i64,
+    pub offset_delta: i32,
+    pub key: Option<Vec<u8>>,
+    pub value: Option<Vec<u8>>,
+    pub headers: Vec<Header>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    pub key: String,
+    pub value: Option<Vec<u8>>,
+}
+
+impl RecordBatch {
+    // Reads the batch at the start of `buffer` and advances past it
+    pub fn read(buffer: &mut &[u8]) -> Result<RecordBatch> {
+        let batch_size = validate(buffer)?;
+        let mut batch = &buffer[..batch_size];
+        buffer.advance(batch_size);
+
+        let base_offset = batch.read_int64()?;
+        let _batch_length = batch.read_int32()?;
+        let partition_leader_epoch = batch.read_int32()?;
+        let _magic = batch.read_int8()?;
+        let _crc = batch.read_uint32()?;
+        let attributes = batch.read_int16()?;
+        let compression = Compression::from_id(attributes & COMPRESSION_CODEC_MASK)?;
+        let last_offset_delta = batch.read_int32()?;
+        let base_timestamp = batch.read_int64()?;
+        let max_timestamp = batch.read_int64()?;
+        let producer_id = batch.read_int64()?;
+        let producer_epoch = batch.read_int16()?;
+        let base_sequence = batch.read_int32()?;
+        let records_count = batch.read_int32()?;
+        if records_count < 0 {
+            return Err(RecordError::CorruptBatch("invalid records count"));
+        }
+        // only the records are compressed, the count stays in the header
+        let decompressed = compression.decompress(batch)?;
+        let mut batch = decompressed.as_slice();
+        let records = (0..records_count)
+            .map(|_| Record::read(&mut batch))
+            .collect::<Result<Vec<_>>>()?;
+        if batch.has_remaining() {
+            return Err(RecordError::CorruptBatch(
+                "unexpected bytes after the records",
+            ));
+        }
+
+        Ok(RecordBatch {
+            base_offset,
+            partition_leader_epoch,
+            attributes,
+            last_offset_delta,
+            base_timestamp,
+            max_timestamp,
+            producer_id,
+            producer_epoch,
+            base_sequence,
+            records,
+        })
+    }
+
+    // The crc is computed over the bytes that follow it
+    pub fn write(&self, buffer: &mut Vec<u8>) {
+        let start = buffer.len();
+        buffer.put_i64(self.base_offset);
+        buffer.put_i32(0); // batch length, set once the records are written
+        buffer.put_i32(self.partition_leader_epoch);
+        buffer.put_i8(CURRENT_MAGIC);
+        buffer.put_u32(0); // crc
+        buffer.put_i16(self.attributes);
+        buffer.put_i32(self.last_offset_delta);
+        buffer.put_i64(self.base_timestamp);
+        buffer.put_i64(self.max_timestamp);
+        buffer.put_i64(self.producer_id);
+        buffer.put_i16(self.producer_epoch);
+        buffer.put_i32(self.base_sequence);
+        buffer.put_i32(codec::checked_length(self.records.len()));
+        let mut records = vec![];
+        for record in &self.records {
+            record.write(&mut records);
+        }
+        buffer.put_slice(&self.compression().compress(&records));
+
+        let batch = &mut buffer[start..];
+        let batch_length = (batch.len() - LOG_OVERHEAD) as i32;
+        batch[BATCH_LENGTH..BATCH_LENGTH + 4].copy_from_slice(&batch_length.to_be_bytes());
+        let crc = crc32c(&batch[ATTRIBUTES..]);
+        batch[CRC..CRC + 4].copy_from_slice(&crc.to_be_bytes());
+    }
+
+    pub fn compression(&self) -> Compression {
+        // the codec was checked when the batch was read
+        Compression::from_id(self.attributes & COMPRESSION_CODEC_MASK).unwrap_or(Compression::None)
+    }
+
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.attributes = self.attributes & !COMPRESSION_CODEC_MASK | compression.id();
+    }
+
+    // Timestamps are set by the broker instead of the producer, see message.timestamp.type
+    pub fn is_log_append_time(&self) -> bool {
+        self.attributes & TIMESTAMP_TYPE_FLAG != 0
+    }
+
+    // Control batches hold transaction markers instead of data
+    pub fn is_control(&self) -> bool {
+        self.attributes & CONTROL_FLAG != 0
+    }
+}
+
+impl Record {
+    fn read(buffer: &mut &[u8]) -> Result<Record> {
+        let length = buffer.read_varint()?;
+        if length < 0 {
+            return Err(codec::DecodeError::InvalidLength(length as i64).into());
+        }
+        buffer.ensure_remaining(length as usize)?;
+        let mut record = &buffer[..length as usize];
+        buffer.advance(length as usize);
+
+        let attributes = record.read_int8()?;
+        let timestamp_delta = record.read_varlong()?;
+        let offset_delta = record.read_varint()?;
+        let key = read_varint_bytes(&mut record)?;
+        let value = read_varint_bytes(&mut record)?;
+        let headers_count = record.read_varint()?;
+        let headers = (0..headers_count.max(0))
+            .map(|_| {
+                let key =
+                    read_varint_bytes(&mut record)?.ok_or(codec::DecodeError::UnexpectedNull)?;
+                Ok(Header {
+                    key: String::from_utf8(key).map_err(|_| codec::DecodeError::InvalidString)?,
+                    value: read_varint_bytes(&mut record)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if record.has_remaining() {
+            return Err(RecordError::CorruptBatch("unexpected bytes after a record"));
+        }
+
+        Ok(Record {
+            attributes,
+            timestamp_delta,
+            offset_delta,
+            key,
+            value,
+            headers,
+        })
+    }
+
+    fn write(&self, buffer: &mut Vec<u8>) {
+        let mut record = vec![];
+        record.put_i8(self.attributes);
+        record.write_varlong(self.timestamp_delta);
+        record.write_varint(self.offset_delta);
+        write_varint_bytes(&mut record, self.key.as_deref());
+        write_varint_bytes(&mut record, self.value.as_deref());
+        record.write_varint(codec::checked_length(self.headers.len()));
+        for header in &self.headers {
+            write_varint_bytes(&mut record, Some(header.key.as_bytes()));
+            write_varint_bytes(&mut record, header.value.as_deref());
+        }
+        buffer.write_varint(codec::checked_length(record.len()));
+        buffer.put_slice(&record);
+    }
+}
+
+// Magic of the first batch or message of `data`, both formats have it at the same position
+pub fn magic(data: &[u8]) -> Option<i8> {
+    data.get(MAGIC).map(|&magic| magic as i8)
+}
+
+// Size of the first batch of `data`, `None` if it is cut
+pub fn batch_size(data: &[u8]) -> Option<usize> {
+    if data.len() < LOG_OVERHEAD {
+        return None;
+    }
+    let batch_length = i32::from_be_bytes(data[BATCH_LENGTH..BATCH_LENGTH + 4].try_into().unwrap());
+    let batch_size = LOG_OVERHEAD + usize::try_from(batch_length).ok()?;
+    (batch_size <= data.len()).then_some(batch_size)
+}
+
+// Checks the header and the crc of the first batch of `data` without decoding its records, and
+// returns its size
+pub fn validate(data: &[u8]) -> Result<usize> {
+    if data.len() < BATCH_HEADER_SIZE {
+        return Err(RecordError::CorruptBatch("batch header is truncated"));
+    }
+    let magic = data[MAGIC] as i8;
+    if magic != CURRENT_MAGIC {
+        return Err(RecordError::UnsupportedMagic(magic));
+    }
+    let batch_size = batch_size(data)
+        .filter(|&batch_size| batch_size >= BATCH_HEADER_SIZE)
+        .ok_or(RecordError::CorruptBatch("invalid batch length"))?;
+    let batch = &data[..batch_size];
+    if i32::from_be_bytes(
+        batch[LAST_OFFSET_DELTA..LAST_OFFSET_DELTA + 4]
+            .try_into()
+            .unwrap(),
+    ) < 0
+    {
+        return Err(RecordError::CorruptBatch("invalid last offset delta"));
+    }
+
+    let stored = u32::from_be_bytes(batch[CRC..CRC + 4].try_into().unwrap());
+    let computed = crc32c(&batch[ATTRIBUTES..]);
+    if stored != computed {
+        return Err(RecordError::InvalidCrc { stored, computed });
+    }
+    Ok(batch_size)
+}
+
+// Like `validate`, and also decompresses and decodes the records to check that they match the
+// header
+pub fn validate_records(data: &[u8]) -> Result<usize> {
+    let batch_size = validate(data)?;
+    let batch = RecordBatch::read(&mut &data[..batch_size])?;
+    if batch.records.len() as i64 != batch.last_offset_delta as i64 + 1 {
+        return Err(RecordError::CorruptBatch(
+            "records count does not match the last offset delta",
+        ));
+    }
+    Ok(batch_size)
+}
+
+// Rewrites the batches of `data` that are not compressed with `compression`, the others are
+// kept as they are
+pub fn recompress(mut data: &[u8], compression: Compression) -> Result<Vec<u8>> {
+    let mut buffer = vec![];
+    while data.has_remaining() {
+        let batch_size = validate(data)?;
+        let attributes = i16::from_be_bytes(data[ATTRIBUTES..ATTRIBUTES + 2].try_into().unwrap());
+        if attributes & COMPRESSION_CODEC_MASK == compression.id() {
+            buffer.put_slice(&data[..batch_size]);
+            data.advance(batch_size);
+            continue;
+        }
+        let mut batch = RecordBatch::read(&mut data)?;
+        batch.set_compression(compression);
+        batch.write(&mut buffer);
+    }
+    Ok(buffer)
+}
+
+fn read_varint_bytes(buffer: &mut &[u8]) -> codec::Result<Option<Vec<u8>>> {
+    let length = buffer.read_varint()?;
+    buffer.read_bytes_of_length(length as i64)
+}
+
+fn write_varint_bytes(buffer: &mut Vec<u8>, value: Option<&[u8]>) {
+    match value {
+        Some(value) => {
+            buffer.write_varint(codec::checked_length(value.len()));
+            buffer.put_slice(value);
+        }
+        None => buffer.write_varint(-1),
+    }
+}
+
+// Lookup table of a reflected crc
+const fn crc_table(polynomial: u32) -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ polynomial
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+const CRC32_TABLE: [u32; 256] = crc_table(0xedb8_8320);
+const CRC32C_TABLE: [u32; 256] = crc_table(0x82f6_3b78);
+
+fn crc(table: &[u32; 256], data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        table[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+// CRC-32 (IEEE), the checksum of v0 and v1 messages
+pub fn crc32(data: &[u8]) -> u32 {
+    crc(&CRC32_TABLE, data)
+}
+
+// CRC-32C (Castagnoli), the checksum of record batches
+pub fn crc32c(data: &[u8]) -> u32 {
+    crc(&CRC32C_TABLE, data)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    pub fn read_batches(mut data: &[u8]) -> Result<Vec<RecordBatch>> {
+        let mut batches = vec![];
+        while data.has_remaining() {
+            batches.push(RecordBatch::read(&mut data)?);
+        }
+        Ok(batches)
+    }
+
+    pub fn record(offset_delta: i32, value: &[u8]) -> Record {
+        Record {
+            attributes: 0,
+            timestamp_delta: offset_delta as i64,
+            offset_delta,
+            key: None,
+            value: Some(value.to_vec()),
+            headers: vec![],
+        }
+    }
+
+    pub fn batch(base_offset: i64, records: Vec<Record>) -> RecordBatch {
+        RecordBatch {
+            base_offset,
+            partition_leader_epoch: 0,
+            attributes: 0,
+            last_offset_delta: records.len() as i32 - 1,
+            base_timestamp: 1_700_000_000_000,
+            max_timestamp: 1_700_000_000_000 + records.len() as i64 - 1,
+            producer_id: -1,
+            producer_epoch: -1,
+            base_sequence: -1,
+            records,
+        }
+    }
+
+    // Encoded batch of `count` records
+    pub fn record_batch(count: i32) -> Vec<u8> {
+        let records = (0..count)
+            .map(|offset_delta| record(offset_delta, b""))
+            .collect();
+        let mut buffer = vec![];
+        batch(0, records).write(&mut buffer);
+        buffer
+    }
+
+    #[test]
+    fn test_crc() {
+        assert_eq!(crc32c(b""), 0);
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_record_batch_round_trip() {
+        let mut records = vec![record(0, b"foo"), record(1, b"bar")];
+        records[1].key = Some(b"key".to_vec());
+        records[1].headers = vec![Header {
+            key: "trace".to_string(),
+            value: None,
+        }];
+        let batch = batch(5, records);
+
+        let mut buffer = vec![];
+        batch.write(&mut buffer);
+        batch.write(&mut buffer);
+
+        assert_eq!(validate(&buffer), Ok(buffer.len() / 2));
+        assert_eq!(
+            read_batches(&buffer),
+            Ok(vec![batch.clone(), batch.clone()])
+        );
+        assert!(!batch.is_control());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compressed_batch_round_trip() {
+        let records = (0..100)
+            .map(|offset_delta| record(offset_delta, b"value"))
+            .collect();
+        let mut batch = batch(0, records);
+        let mut uncompressed = vec![];
+        batch.write(&mut uncompressed);
+
+        batch.set_compression(Compression::Zstd);
+        let mut buffer = vec![];
+        batch.write(&mut buffer);
+        assert!(buffer.len() < uncompressed.len());
+        assert_eq!(validate_records(&buffer), Ok(buffer.len()));
+        assert_eq!(read_batches(&buffer), Ok(vec![batch.clone()]));
+
+        // the same records, with the codec changed
+        let recompressed = recompress(&buffer, Compression::Lz4).unwrap();
+        let batches = read_batches(&recompressed).unwrap();
+        assert_eq!(batches[0].compression(), Compression::Lz4);
+        assert_eq!(batches[0].records, batch.records);
+        assert_eq!(
+            recompress(&recompressed, Compression::Lz4),
+            Ok(recompressed)
+        );
+        assert_eq!(recompress(&buffer, Compression::None), Ok(uncompressed));
+    }
+
+    // Batch written by hand with a crc computed independently: 1 record, key "k", value "v"
+    #[test]
+    fn test_read_batch_with_known_crc() {
+        let mut data = vec![0; 8];
+        data.extend_from_slice(&[0, 0, 0, 58, 0, 0, 0, 0, 2, 0xe9, 0x9b, 0x8d, 0xd8, 0, 0]);
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 1, 0x8b, 0xcf, 0xe5, 0x68, 0]);
+        data.extend_from_slice(&[0, 0, 1, 0x8b, 0xcf, 0xe5, 0x68, 0]);
+        data.extend_from_slice(&[255, 255, 255, 255, 255, 255, 255, 255, 255, 255]);
+        data.extend_from_slice(&[255, 255, 255, 255, 0, 0, 0, 1]);
+        data.extend_from_slice(&[16, 0, 0, 0, 2, b'k', 2, b'v', 0]);
+
+        let batch = RecordBatch::read(&mut data.as_slice()).unwrap();
+        assert_eq!(batch.base_timestamp, 1_700_000_000_000);
+        assert_eq!(batch.records[0].key.as_deref(), Some(&b"k"[..]));
+        assert_eq!(batch.records[0].value.as_deref(), Some(&b"v"[..]));
+    }
+
+    #[test]
+    fn test_validate_should_reject_corrupt_batches() {
+        let mut data = record_batch(2);
+        assert!(matches!(
+            validate(&data[..30]),
+            Err(RecordError::CorruptBatch(_))
+        ));
+
+        data[MAGIC] = 1;
+        assert_eq!(validate(&data), Err(RecordError::UnsupportedMagic(1)));
+        data[MAGIC] = 2;
+
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(matches!(
+            validate(&data),
+            Err(RecordError::InvalidCrc { .. })
+        ));
+        assert!(matches!(
+            RecordBatch::read(&mut data.as_slice()),
+            Err(RecordError::InvalidCrc { .. })
+        ));
+
+        let mut buffer = vec![];
+        let mut mismatched = batch(0, vec![record(0, b"foo")]);
+        mismatched.last_offset_delta = 1;
+        mismatched.write(&mut buffer);
+        assert_eq!(validate(&buffer), Ok(buffer.len()));
+        assert!(matches!(
+            validate_records(&buffer),
+            Err(RecordError::CorruptBatch(_))
+        ));
+    }
+}
@@ -0,0 +1,355 @@
+use std::io;
+use std::str::FromStr;
+
+use super::{RecordError, Result};
+
+// Codecs of the compression bits of the batch and message attributes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None = 0,
+    Gzip = 1,
+    Snappy = 2,
+    Lz4 = 3,
+    Zstd = 4,
+}
+
+// Most bytes the records of a batch, or the messages of a wrapper message, decompress to. A small
+// batch could otherwise make the broker allocate without bound, while producers send batches of
+// at most max.request.size, 1 MiB by default.
+const MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+impl Compression {
+    pub fn from_id(id: i16) -> Result<Compression> {
+        match id {
+            0 => Ok(Compression::None),
+            // the codecs are only built with the compression feature
+            1..=4 if !cfg!(feature = "compression") => Err(RecordError::UnsupportedCompression(id)),
+            1 => Ok(Compression::Gzip),
+            2 => Ok(Compression::Snappy),
+            3 => Ok(Compression::Lz4),
+            4 => Ok(Compression::Zstd),
+            _ => Err(RecordError::UnsupportedCompression(id)),
+        }
+    }
+
+    pub fn id(self) -> i16 {
+        self as i16
+    }
+
+    pub fn compress(self, data: &[u8]) -> Vec<u8> {
+        // writing to a Vec cannot fail
+        codecs::compress(self, data).expect("compression to memory")
+    }
+
+    pub fn decompress(self, data: &[u8]) -> Result<Vec<u8>> {
+        self.try_decompress(data, MAX_DECOMPRESSED_SIZE)
+            .map_err(|error| RecordError::Decompression(error.to_string()))
+    }
+
+    fn try_decompress(self, data: &[u8], max_size: usize) -> io::Result<Vec<u8>> {
+        let buffer = codecs::decompress(self, data, max_size)?;
+        check_size(buffer.len(), max_size)?;
+        Ok(buffer)
+    }
+}
+
+fn check_size(size: usize, max_size: usize) -> io::Result<()> {
+    if size > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("decompressed records exceed {} bytes", max_size),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "compression")]
+mod codecs {
+    use std::io::{self, Read, Write};
+
+    use super::{check_size, Compression};
+
+    // Framing written by the snappy-java SnappyOutputStream, used by the java clients
+    const XERIAL_MAGIC: &[u8] = b"\x82SNAPPY\0";
+    const XERIAL_VERSION: i32 = 1;
+    const XERIAL_COMPATIBLE_VERSION: i32 = 1;
+    const XERIAL_HEADER_SIZE: usize = 16;
+    const XERIAL_BLOCK_SIZE: usize = 32 * 1024;
+
+    pub fn compress(compression: Compression, data: &[u8]) -> io::Result<Vec<u8>> {
+        match compression {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Compression::Snappy => {
+                let mut buffer = XERIAL_MAGIC.to_vec();
+                buffer.extend_from_slice(&XERIAL_VERSION.to_be_bytes());
+                buffer.extend_from_slice(&XERIAL_COMPATIBLE_VERSION.to_be_bytes());
+                let mut encoder = snap::raw::Encoder::new();
+                for block in data.chunks(XERIAL_BLOCK_SIZE) {
+                    let compressed = encoder.compress_vec(block)?;
+                    buffer.extend_from_slice(&(compressed.len() as i32).to_be_bytes());
+                    buffer.extend_from_slice(&compressed);
+                }
+                Ok(buffer)
+            }
+            Compression::Lz4 => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(vec![]);
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            Compression::Zstd => zstd::stream::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL),
+        }
+    }
+
+    pub fn decompress(
+        compression: Compression,
+        data: &[u8],
+        max_size: usize,
+    ) -> io::Result<Vec<u8>> {
+        let mut buffer = vec![];
+        match compression {
+            Compression::None => buffer.extend_from_slice(data),
+            Compression::Gzip => {
+                read_to_end(
+                    flate2::read::MultiGzDecoder::new(data),
+                    &mut buffer,
+                    max_size,
+                )?;
+            }
+            // other clients send raw snappy blocks
+            Compression::Snappy if !data.starts_with(XERIAL_MAGIC) => {
+                check_size(snap::raw::decompress_len(data)?, max_size)?;
+                buffer = snap::raw::Decoder::new().decompress_vec(data)?;
+            }
+            Compression::Snappy => {
+                let mut decoder = snap::raw::Decoder::new();
+                let mut blocks = data.get(XERIAL_HEADER_SIZE..).ok_or_else(truncated)?;
+                while !blocks.is_empty() {
+                    let length = blocks.get(..4).ok_or_else(truncated)?;
+                    let length = i32::from_be_bytes(length.try_into().unwrap());
+                    let block = usize::try_from(length)
+                        .ok()
+                        .and_then(|length| blocks.get(4..4 + length))
+                        .ok_or_else(truncated)?;
+                    // the blocks start with their decompressed length
+                    check_size(buffer.len() + snap::raw::decompress_len(block)?, max_size)?;
+                    buffer.extend_from_slice(&decoder.decompress_vec(block)?);
+                    blocks = &blocks[4 + block.len()..];
+                }
+            }
+            Compression::Lz4 => {
+                read_to_end(
+                    lz4_flex::frame::FrameDecoder::new(data),
+                    &mut buffer,
+                    max_size,
+                )?;
+            }
+            Compression::Zstd => {
+                read_to_end(zstd::stream::Decoder::new(data)?, &mut buffer, max_size)?;
+            }
+        }
+        Ok(buffer)
+    }
+
+    // Stops one byte past `max_size`, which is enough to know that the data is too large
+    fn read_to_end(decoder: impl Read, buffer: &mut Vec<u8>, max_size: usize) -> io::Result<()> {
+        decoder.take(max_size as u64 + 1).read_to_end(buffer)?;
+        check_size(buffer.len(), max_size)
+    }
+
+    fn truncated() -> io::Error {
+        io::Error::new(io::ErrorKind::UnexpectedEof, "truncated snappy block")
+    }
+}
+
+// Without the codecs only uncompressed batches are accepted, `Compression::from_id` and
+// `CompressionType::from_str` reject the others
+#[cfg(not(feature = "compression"))]
+mod codecs {
+    use std::io;
+
+    use super::Compression;
+
+    pub fn compress(compression: Compression, data: &[u8]) -> io::Result<Vec<u8>> {
+        match compression {
+            Compression::None => Ok(data.to_vec()),
+            _ => Err(unsupported(compression)),
+        }
+    }
+
+    pub fn decompress(
+        compression: Compression,
+        data: &[u8],
+        _max_size: usize,
+    ) -> io::Result<Vec<u8>> {
+        match compression {
+            Compression::None => Ok(data.to_vec()),
+            _ => Err(unsupported(compression)),
+        }
+    }
+
+    fn unsupported(compression: Compression) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{:?} needs the compression feature", compression),
+        )
+    }
+}
+
+// Codec of a broker or topic compression.type, which `Producer` leaves as the producer sent it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompressionType {
+    #[default]
+    Producer,
+    Uncompressed,
+    Gzip,
+    Snappy,
+    Lz4,
+    Zstd,
+}
+
+impl CompressionType {
+    // Codec the appended batches should have, `None` to keep the one of the producer
+    pub fn target(self) -> Option<Compression> {
+        match self {
+            CompressionType::Producer => None,
+            CompressionType::Uncompressed => Some(Compression::None),
+            CompressionType::Gzip => Some(Compression::Gzip),
+            CompressionType::Snappy => Some(Compression::Snappy),
+            CompressionType::Lz4 => Some(Compression::Lz4),
+            CompressionType::Zstd => Some(Compression::Zstd),
+        }
+    }
+}
+
+impl FromStr for CompressionType {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<CompressionType, String> {
+        let compression_type = match value.to_ascii_lowercase().as_str() {
+            "producer" => CompressionType::Producer,
+            "uncompressed" | "none" => CompressionType::Uncompressed,
+            "gzip" => CompressionType::Gzip,
+            "snappy" => CompressionType::Snappy,
+            "lz4" => CompressionType::Lz4,
+            "zstd" => CompressionType::Zstd,
+            _ => return Err(format!("invalid compression.type {}", value)),
+        };
+        let needs_codec = compression_type
+            .target()
+            .is_some_and(|compression| compression != Compression::None);
+        if needs_codec && !cfg!(feature = "compression") {
+            return Err(format!(
+                "compression.type {} needs the compression feature",
+                value
+            ));
+        }
+        Ok(compression_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "compression")]
+    const CODECS: [Compression; 5] = [
+        Compression::None,
+        Compression::Gzip,
+        Compression::Snappy,
+        Compression::Lz4,
+        Compression::Zstd,
+    ];
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_round_trip() {
+        let data = b"hello ".repeat(10_000);
+        for codec in CODECS {
+            let compressed = codec.compress(&data);
+            if codec != Compression::None {
+                assert!(compressed.len() < data.len(), "{:?}", codec);
+            }
+            assert_eq!(
+                codec.decompress(&compressed),
+                Ok(data.clone()),
+                "{:?}",
+                codec
+            );
+            assert_eq!(Compression::from_id(codec.id()), Ok(codec));
+        }
+        assert_eq!(
+            Compression::from_id(5),
+            Err(RecordError::UnsupportedCompression(5))
+        );
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_decompress_should_stop_at_the_max_size() {
+        let data = b"hello ".repeat(10_000);
+        let raw_snappy = snap::raw::Encoder::new().compress_vec(&data).unwrap();
+        let compressed = CODECS.map(|codec| (codec, codec.compress(&data)));
+        for (codec, compressed) in compressed
+            .into_iter()
+            .chain([(Compression::Snappy, raw_snappy)])
+        {
+            assert_eq!(
+                codec.try_decompress(&compressed, data.len()).unwrap(),
+                data,
+                "{:?}",
+                codec
+            );
+            let error = codec
+                .try_decompress(&compressed, data.len() - 1)
+                .unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{:?}", codec);
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_decompress_raw_snappy() {
+        let compressed = snap::raw::Encoder::new().compress_vec(b"foo").unwrap();
+        assert_eq!(
+            Compression::Snappy.decompress(&compressed),
+            Ok(b"foo".to_vec())
+        );
+
+        let mut truncated = Compression::Snappy.compress(b"foo");
+        truncated.pop();
+        assert!(matches!(
+            Compression::Snappy.decompress(&truncated),
+            Err(RecordError::Decompression(_))
+        ));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_parse_compression_type() {
+        assert_eq!("producer".parse(), Ok(CompressionType::Producer));
+        assert_eq!("LZ4".parse(), Ok(CompressionType::Lz4));
+        assert_eq!(
+            "uncompressed".parse::<CompressionType>().unwrap().target(),
+            Some(Compression::None)
+        );
+        assert!("brotli".parse::<CompressionType>().is_err());
+    }
+
+    #[cfg(not(feature = "compression"))]
+    #[test]
+    fn test_codecs_should_need_the_compression_feature() {
+        assert_eq!(Compression::from_id(0), Ok(Compression::None));
+        assert_eq!(
+            Compression::from_id(1),
+            Err(RecordError::UnsupportedCompression(1))
+        );
+        assert_eq!("none".parse(), Ok(CompressionType::Uncompressed));
+        assert!("gzip".parse::<CompressionType>().is_err());
+    }
+}
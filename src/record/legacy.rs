@@ -0,0 +1,396 @@
+use bytes::{Buf, BufMut};
+
+use super::compression::Compression;
+use super::{Record, RecordBatch, RecordError, Result};
+use crate::server::codec::{self, Decoder};
+
+// Messages v0 and v1, which record batches replaced in Kafka 0.11
+// https://kafka.apache.org/documentation/#messageset
+const CRC: usize = 12;
+const MAGIC: usize = 16;
+
+// Size of a v0 message with a null key and a null value
+pub const MIN_MESSAGE_SIZE: usize = super::LOG_OVERHEAD + 4 + 1 + 1 + 4 + 4;
+
+const COMPRESSION_CODEC_MASK: i8 = 0x07;
+// v1 only
+const TIMESTAMP_TYPE_FLAG: i8 = 0x08;
+pub const TIMESTAMP: usize = 18;
+
+// Timestamp of the v0 messages, which have none
+pub const NO_TIMESTAMP: i64 = -1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub offset: i64,
+    pub magic: i8,
+    pub attributes: i8,
+    pub timestamp: i64,
+    pub key: Option<Vec<u8>>,
+    pub value: Option<Vec<u8>>,
+}
+
+impl Message {
+    // Reads the message at the start of `buffer` and advances past it
+    pub fn read(buffer: &mut &[u8]) -> Result<Message> {
+        let message_size = super::batch_size(buffer)
+            .filter(|&message_size| message_size >= MIN_MESSAGE_SIZE)
+            .ok_or(RecordError::CorruptBatch("invalid message size"))?;
+        let mut message = &buffer[..message_size];
+        buffer.advance(message_size);
+
+        let stored = u32::from_be_bytes(message[CRC..CRC + 4].try_into().unwrap());
+        let computed = super::crc32(&message[MAGIC..]);
+        if stored != computed {
+            return Err(RecordError::InvalidCrc { stored, computed });
+        }
+
+        let offset = message.read_int64()?;
+        let _message_size = message.read_int32()?;
+        let _crc = message.read_uint32()?;
+        let magic = message.read_int8()?;
+        if !(0..=1).contains(&magic) {
+            return Err(RecordError::UnsupportedMagic(magic));
+        }
+        let attributes = message.read_int8()?;
+        let timestamp = if magic >= 1 {
+            message.read_int64()?
+        } else {
+            NO_TIMESTAMP
+        };
+        let key = message.read_nullable_bytes()?;
+        let value = message.read_nullable_bytes()?;
+        if message.has_remaining() {
+            return Err(RecordError::CorruptBatch(
+                "unexpected bytes after a message",
+            ));
+        }
+
+        Ok(Message {
+            offset,
+            magic,
+            attributes,
+            timestamp,
+            key,
+            value,
+        })
+    }
+
+    pub fn compression(&self) -> Result<Compression> {
+        Compression::from_id((self.attributes & COMPRESSION_CODEC_MASK) as i16)
+    }
+
+    // A compressed message is a wrapper whose value is a compressed message set, this returns
+    // the messages it holds, or the message itself when it is not compressed
+    pub fn expand(self) -> Result<Vec<Message>> {
+        let compression = self.compression()?;
+        if compression == Compression::None {
+            return Ok(vec![self]);
+        }
+        let value = compression.decompress(self.value.as_deref().unwrap_or_default())?;
+        let mut messages = vec![];
+        let mut data = value.as_slice();
+        while data.has_remaining() {
+            let message = Message::read(&mut data)?;
+            if message.compression()? != Compression::None {
+                return Err(RecordError::CorruptBatch("nested compressed message"));
+            }
+            messages.push(message);
+        }
+
+        // v1 inner offsets are relative, the wrapper has the offset of the last message
+        if self.magic >= 1 {
+            let last_offset = messages.last().map_or(0, |message| message.offset);
+            let is_log_append_time = self.attributes & TIMESTAMP_TYPE_FLAG != 0;
+            for message in &mut messages {
+                message.offset += self.offset - last_offset;
+                if is_log_append_time {
+                    message.timestamp = self.timestamp;
+                }
+            }
+        }
+        Ok(messages)
+    }
+
+    // The crc is computed over the bytes that follow it
+    pub fn write(&self, buffer: &mut Vec<u8>) {
+        let start = buffer.len();
+        buffer.put_i64(self.offset);
+        buffer.put_i32(0); // message size, set once the value is written
+        buffer.put_u32(0); // crc
+        buffer.put_i8(self.magic);
+        buffer.put_i8(self.attributes);
+        if self.magic >= 1 {
+            buffer.put_i64(self.timestamp);
+        }
+        for bytes in [&self.key, &self.value] {
+            match bytes {
+                Some(bytes) => {
+                    buffer.put_i32(codec::checked_length(bytes.len()));
+                    buffer.put_slice(bytes);
+                }
+                None => buffer.put_i32(-1),
+            }
+        }
+
+        let message = &mut buffer[start..];
+        let message_size = (message.len() - super::LOG_OVERHEAD) as i32;
+        message[super::BATCH_LENGTH..super::BATCH_LENGTH + 4]
+            .copy_from_slice(&message_size.to_be_bytes());
+        let crc = super::crc32(&message[MAGIC..]);
+        message[CRC..CRC + 4].copy_from_slice(&crc.to_be_bytes());
+    }
+}
+
+// Reads the messages of `data`, with the compressed ones expanded
+pub fn read_message_set(mut data: &[u8]) -> Result<Vec<Message>> {
+    let mut messages = vec![];
+    while data.has_remaining() {
+        messages.extend(Message::read(&mut data)?.expand()?);
+    }
+    Ok(messages)
+}
+
+// Converts a message set to a single record batch, the offsets set by the producer are ignored
+// like for record batches. The batch keeps the codec of the compressed messages.
+pub fn up_convert(data: &[u8]) -> Result<Vec<u8>> {
+    let compression = match super::magic(data) {
+        Some(_) => Message::read(&mut &data[..])?.compression()?,
+        None => Compression::None,
+    };
+    let messages = read_message_set(data)?;
+    if messages.is_empty() {
+        return Err(RecordError::CorruptBatch("no message"));
+    }
+    let base_timestamp = messages[0].timestamp;
+    let is_log_append_time = messages
+        .iter()
+        .any(|message| message.magic >= 1 && message.attributes & TIMESTAMP_TYPE_FLAG != 0);
+
+    let mut batch = RecordBatch {
+        base_offset: 0,
+        partition_leader_epoch: -1,
+        attributes: if is_log_append_time {
+            super::TIMESTAMP_TYPE_FLAG
+        } else {
+            0
+        },
+        last_offset_delta: messages.len() as i32 - 1,
+        base_timestamp,
+        max_timestamp: messages
+            .iter()
+            .map(|message| message.timestamp)
+            .max()
+            .unwrap_or(NO_TIMESTAMP),
+        producer_id: -1,
+        producer_epoch: -1,
+        base_sequence: -1,
+        records: messages
+            .into_iter()
+            .enumerate()
+            .map(|(offset_delta, message)| Record {
+                attributes: 0,
+                timestamp_delta: message.timestamp - base_timestamp,
+                offset_delta: offset_delta as i32,
+                key: message.key,
+                value: message.value,
+                headers: vec![],
+            })
+            .collect(),
+    };
+    batch.set_compression(compression);
+    let mut buffer = vec![];
+    batch.write(&mut buffer);
+    Ok(buffer)
+}
+
+// Converts record batches to messages of `magic` for the clients that cannot read batches.
+// Control batches and record headers have no equivalent and are dropped.
+pub fn down_convert(mut data: &[u8], magic: i8) -> Result<Vec<u8>> {
+    let mut buffer = vec![];
+    while data.has_remaining() {
+        // segments written before the upgrade to batches hold messages already
+        if super::magic(data).is_some_and(|batch_magic| batch_magic < super::CURRENT_MAGIC) {
+            for mut message in Message::read(&mut data)?.expand()? {
+                if message.magic > magic {
+                    message.magic = magic;
+                    message.attributes &= !TIMESTAMP_TYPE_FLAG;
+                }
+                message.write(&mut buffer);
+            }
+            continue;
+        }
+
+        let batch = RecordBatch::read(&mut data)?;
+        if batch.is_control() {
+            continue;
+        }
+        let is_log_append_time = batch.is_log_append_time();
+        let attributes = if magic >= 1 && is_log_append_time {
+            TIMESTAMP_TYPE_FLAG
+        } else {
+            0
+        };
+        for record in batch.records {
+            let timestamp = if magic == 0 {
+                NO_TIMESTAMP
+            } else if is_log_append_time {
+                batch.max_timestamp
+            } else {
+                batch.base_timestamp + record.timestamp_delta
+            };
+            Message {
+                offset: batch.base_offset + record.offset_delta as i64,
+                magic,
+                attributes,
+                timestamp,
+                key: record.key,
+                value: record.value,
+            }
+            .write(&mut buffer);
+        }
+    }
+    Ok(buffer)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    use crate::record::crc32;
+    use crate::record::tests::{batch, read_batches, record};
+
+    pub fn message(offset: i64, magic: i8, value: &[u8]) -> Message {
+        Message {
+            offset,
+            magic,
+            attributes: 0,
+            timestamp: if magic >= 1 {
+                1_700_000_000_000 + offset
+            } else {
+                NO_TIMESTAMP
+            },
+            key: None,
+            value: Some(value.to_vec()),
+        }
+    }
+
+    pub fn message_set(messages: &[Message]) -> Vec<u8> {
+        let mut buffer = vec![];
+        for message in messages {
+            message.write(&mut buffer);
+        }
+        buffer
+    }
+
+    #[test]
+    fn test_message_layout() {
+        let mut message = message(3, 0, b"v");
+        message.key = Some(b"k".to_vec());
+        let data = message_set(&[message.clone()]);
+
+        let mut expected = vec![0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 16];
+        expected
+            .extend_from_slice(&crc32(&[0, 0, 0, 0, 0, 1, b'k', 0, 0, 0, 1, b'v']).to_be_bytes());
+        expected.extend_from_slice(&[0, 0, 0, 0, 0, 1, b'k', 0, 0, 0, 1, b'v']);
+        assert_eq!(data, expected);
+        assert_eq!(read_message_set(&data), Ok(vec![message]));
+    }
+
+    #[test]
+    fn test_read_should_check_the_crc() {
+        let mut data = message_set(&[message(0, 1, b"foo")]);
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(matches!(
+            read_message_set(&data),
+            Err(RecordError::InvalidCrc { .. })
+        ));
+    }
+
+    // Wrapper of `messages` compressed with `compression`, with the offsets of v1
+    #[cfg(feature = "compression")]
+    fn wrapper(compression: Compression, messages: &[Message]) -> Message {
+        let last = messages.last().unwrap();
+        let mut inner = messages.to_vec();
+        if last.magic >= 1 {
+            for message in &mut inner {
+                message.offset -= messages[0].offset;
+            }
+        }
+        Message {
+            attributes: compression.id() as i8,
+            value: Some(compression.compress(&message_set(&inner))),
+            ..message(last.offset, last.magic, b"")
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_read_compressed_messages() {
+        let messages = vec![message(4, 1, b"foo"), message(5, 1, b"bar")];
+        for compression in [Compression::Gzip, Compression::Snappy, Compression::Lz4] {
+            let data = message_set(&[wrapper(compression, &messages)]);
+            assert_eq!(read_message_set(&data), Ok(messages.clone()));
+
+            let batches = read_batches(&up_convert(&data).unwrap()).unwrap();
+            assert_eq!(batches[0].compression(), compression);
+            assert_eq!(batches[0].records.len(), 2);
+        }
+
+        let v0 = vec![message(4, 0, b"foo"), message(5, 0, b"bar")];
+        let data = message_set(&[wrapper(Compression::Gzip, &v0)]);
+        assert_eq!(read_message_set(&data), Ok(v0));
+    }
+
+    #[test]
+    fn test_up_convert() {
+        let data = message_set(&[message(7, 1, b"foo"), message(9, 1, b"bar")]);
+
+        let batches = read_batches(&up_convert(&data).unwrap()).unwrap();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.base_timestamp, 1_700_000_000_007);
+        assert_eq!(batch.max_timestamp, 1_700_000_000_009);
+        assert_eq!(batch.last_offset_delta, 1);
+        assert_eq!(batch.records[1].offset_delta, 1);
+        assert_eq!(batch.records[1].timestamp_delta, 2);
+        assert_eq!(batch.records[1].value.as_deref(), Some(&b"bar"[..]));
+
+        assert!(matches!(up_convert(&[]), Err(RecordError::CorruptBatch(_))));
+    }
+
+    #[test]
+    fn test_down_convert() {
+        let mut data = vec![];
+        batch(5, vec![record(0, b"foo"), record(1, b"bar")]).write(&mut data);
+
+        let v1 = read_message_set(&down_convert(&data, 1).unwrap()).unwrap();
+        assert_eq!(
+            v1,
+            vec![
+                Message {
+                    timestamp: 1_700_000_000_000,
+                    ..message(5, 1, b"foo")
+                },
+                Message {
+                    timestamp: 1_700_000_000_001,
+                    ..message(6, 1, b"bar")
+                },
+            ]
+        );
+
+        let v0 = read_message_set(&down_convert(&data, 0).unwrap()).unwrap();
+        assert_eq!(v0, vec![message(5, 0, b"foo"), message(6, 0, b"bar")]);
+
+        // messages stored before record batches are passed through
+        let legacy = message_set(&[message(4, 1, b"baz")]);
+        let converted = down_convert(&[legacy, data].concat(), 0).unwrap();
+        let offsets: Vec<_> = read_message_set(&converted)
+            .unwrap()
+            .iter()
+            .map(|message| (message.offset, message.magic))
+            .collect();
+        assert_eq!(offsets, vec![(4, 0), (5, 0), (6, 0)]);
+    }
+}
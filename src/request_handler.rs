@@ -1,36 +1,730 @@
+use std::panic;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::task;
+
+use crate::broker::{Broker, SharedPartitionLog};
+use crate::catalog;
+use crate::fetch_session;
+use crate::log;
+use crate::record;
 use crate::server;
 
 use server::model;
 use server::requests;
 use server::responses;
 
-pub fn process_request(request: &requests::Request) -> responses::Response {
+// Fetch requests that cannot return min_bytes yet are parked in the purgatory until a Produce
+// appends to one of their partitions or max_wait_ms elapses, the other requests complete at once
+pub async fn process_request(broker: &Broker, request: &requests::Request) -> responses::Response {
     match request {
-        requests::Request::ApiVersions(api_versions_request) => {
-            responses::Response::ApiVersions(process_api_versions_request(api_versions_request))
-        }
+        requests::Request::ApiVersions(api_versions_request) => responses::Response::ApiVersions(
+            process_api_versions_request(broker, api_versions_request),
+        ),
         requests::Request::Fetch(fetch_request) => {
-            responses::Response::Fetch(process_fetch_request(fetch_request))
+            responses::Response::Fetch(process_fetch_request(broker, fetch_request).await)
+        }
+        requests::Request::Produce(produce_request) => {
+            responses::Response::Produce(process_produce_request(broker, produce_request))
+        }
+        requests::Request::Metadata(metadata_request) => {
+            responses::Response::Metadata(process_metadata_request(broker, metadata_request))
+        }
+        requests::Request::DescribeTopicPartitions(describe_request) => {
+            responses::Response::DescribeTopicPartitions(process_describe_topic_partitions_request(
+                broker,
+                describe_request,
+            ))
         }
     }
 }
 
-fn process_api_versions_request(_request: &requests::ApiVersions) -> responses::ApiVersions {
+// The supported versions are sent even when the request version is not supported, so that the
+// client can retry with a version it shares with the broker
+fn process_api_versions_request(
+    broker: &Broker,
+    request: &requests::ApiVersions,
+) -> responses::ApiVersions {
+    let error_code = if model::ApiKeyVariant::Versions
+        .versions()
+        .is_version_valid(request.header.request_api_version)
+    {
+        model::ErrorCode::Ok
+    } else {
+        model::ErrorCode::UnsupportedVersion
+    };
     responses::ApiVersions {
-        api_key_versions: vec![model::ApiKeyVariant::Fetch, model::ApiKeyVariant::Versions],
+        error_code: error_code as i16,
+        api_key_versions: vec![
+            model::ApiKeyVariant::Produce.into(),
+            model::ApiKeyVariant::Fetch.into(),
+            model::ApiKeyVariant::Metadata.into(),
+            model::ApiKeyVariant::Versions.into(),
+            model::ApiKeyVariant::DescribeTopicPartitions.into(),
+        ],
         throttle_time_in_ms: 0,
+        supported_features: Some(
+            model::SUPPORTED_FEATURES
+                .iter()
+                .map(|feature| responses::api_versions::SupportedFeatureKey {
+                    name: feature.name.to_string(),
+                    min_version: feature.min_version,
+                    max_version: feature.max_version,
+                    tagged_fields: model::TaggedFields::default(),
+                })
+                .collect(),
+        ),
+        // like Kafka, both are left out until the metadata log sets a feature
+        finalized_features_epoch: (!broker.finalized_features.is_empty())
+            .then_some(broker.finalized_features_epoch),
+        finalized_features: (!broker.finalized_features.is_empty()).then(|| {
+            broker
+                .finalized_features
+                .iter()
+                .map(
+                    |(name, &level)| responses::api_versions::FinalizedFeatureKey {
+                        name: name.clone(),
+                        max_version_level: level,
+                        min_version_level: level,
+                        tagged_fields: model::TaggedFields::default(),
+                    },
+                )
+                .collect()
+        }),
+        zk_migration_ready: None,
+        tagged_fields: model::TaggedFields::default(),
     }
 }
 
-fn process_fetch_request(request: &requests::Fetch) -> responses::Fetch {
+async fn process_fetch_request(broker: &Broker, request: &requests::Fetch) -> responses::Fetch {
+    let now = Instant::now();
+    let context = match broker
+        .fetch_sessions
+        .lock()
+        .unwrap()
+        .new_context(request, now)
+    {
+        Ok(context) => context,
+        Err(error_code) => return fetch_session_error(error_code),
+    };
+    let deadline = tokio::time::Instant::from_std(now)
+        + Duration::from_millis(request.max_wait_ms.max(0) as u64);
+    // looked up once, the wakeups only read the logs again
+    let fetched = Arc::new(resolve_fetched_topics(broker, request, &context.topics));
+    let mut response = loop {
+        // watched before reading, so that the records appended in between wake the request
+        let appended = broker.fetch_purgatory.watch(fetched.watched_partitions());
+        let response = read_fetched_topics(&fetched).await;
+        if is_fetch_satisfied(request, &response) {
+            break response;
+        }
+        if tokio::time::timeout_at(deadline, appended.notified())
+            .await
+            .is_err()
+        {
+            break read_fetched_topics(&fetched).await;
+        }
+    };
+    broker
+        .fetch_sessions
+        .lock()
+        .unwrap()
+        .complete(&context, &mut response, Instant::now());
+    response
+}
+
+fn fetch_session_error(error_code: model::ErrorCode) -> responses::Fetch {
     responses::Fetch {
         throttle_time_in_ms: 0,
+        error_code: error_code as i16,
+        session_id: fetch_session::INVALID_SESSION_ID,
+        topics: vec![],
+        node_endpoints: None,
+        tagged_fields: model::TaggedFields::default(),
+    }
+}
+
+// The partitions of a Fetch request or of its session, with their logs looked up in the catalog
+// beforehand, so that the logs are read without holding it
+struct FetchedTopics {
+    version: i16,
+    session_id: i32,
+    max_bytes: i32,
+    topics: Vec<FetchedTopic>,
+}
+
+struct FetchedTopic {
+    topic: requests::fetch::FetchTopic,
+    // `None` if the topic is unknown
+    name: Option<String>,
+    // the log of each partition of `topic`, or the error it is answered with
+    logs: Vec<Result<SharedPartitionLog, model::ErrorCode>>,
+}
+
+impl FetchedTopics {
+    fn watched_partitions(&self) -> Vec<(String, i32)> {
+        self.topics
+            .iter()
+            .filter_map(|topic| Some((topic.name.as_ref()?, &topic.topic.partitions)))
+            .flat_map(|(name, partitions)| {
+                partitions
+                    .iter()
+                    .map(|partition| (name.clone(), partition.partition))
+            })
+            .collect()
+    }
+
+    fn read(&self) -> responses::Fetch {
+        let mut budget = FetchBudget {
+            remaining_bytes: self.max_bytes.max(0) as usize,
+            // max_bytes is a hard limit before v3, see KIP-74
+            min_one_batch: self.version >= 3,
+        };
+        responses::Fetch {
+            throttle_time_in_ms: 0,
+            error_code: model::ErrorCode::Ok as i16,
+            session_id: self.session_id,
+            topics: self
+                .topics
+                .iter()
+                .map(|topic| responses::fetch::FetchTopicResponse {
+                    topic: topic.topic.topic.clone(),
+                    topic_id: topic.topic.topic_id,
+                    partitions: topic
+                        .topic
+                        .partitions
+                        .iter()
+                        .zip(&topic.logs)
+                        .map(|(partition, log)| {
+                            process_fetch_partition(self.version, log, partition, &mut budget)
+                        })
+                        .collect(),
+                    tagged_fields: model::TaggedFields::default(),
+                })
+                .collect(),
+            node_endpoints: None,
+            tagged_fields: model::TaggedFields::default(),
+        }
+    }
+}
+
+// The catalog is only locked to find the topics
+fn resolve_fetched_topics(
+    broker: &Broker,
+    request: &requests::Fetch,
+    topics: &[requests::fetch::FetchTopic],
+) -> FetchedTopics {
+    let found: Vec<_> = {
+        let catalog = broker.catalog.lock().unwrap();
+        topics
+            .iter()
+            .map(|topic| fetched_topic(&catalog, request, topic).cloned())
+            .collect()
+    };
+    let version = request.header.request_api_version;
+    let topics = topics
+        .iter()
+        .zip(found)
+        .map(|(topic, found)| FetchedTopic {
+            topic: topic.clone(),
+            name: found.as_ref().map(|found| found.name.clone()),
+            logs: topic
+                .partitions
+                .iter()
+                .map(|partition| match &found {
+                    Some(found) if found.partition(partition.partition).is_some() => broker
+                        .partition_log(found, partition.partition)
+                        .map_err(|_| model::ErrorCode::KafkaStorageError),
+                    None if version >= 13 => Err(model::ErrorCode::UnknownTopicId),
+                    _ => Err(model::ErrorCode::UnknownTopicOrPartition),
+                })
+                .collect(),
+        })
+        .collect();
+    FetchedTopics {
+        version,
         session_id: request.session_id,
-        topics: request
-            .topics
+        max_bytes: request.max_bytes,
+        topics,
+    }
+}
+
+// The logs are read with blocking calls, off the runtime threads
+async fn read_fetched_topics(fetched: &Arc<FetchedTopics>) -> responses::Fetch {
+    let fetched = fetched.clone();
+    match task::spawn_blocking(move || fetched.read()).await {
+        Ok(response) => response,
+        Err(e) => panic::resume_unwind(e.into_panic()),
+    }
+}
+
+// Topics are fetched by id from v13
+fn fetched_topic<'a>(
+    catalog: &'a catalog::Catalog,
+    request: &requests::Fetch,
+    topic: &requests::fetch::FetchTopic,
+) -> Option<&'a catalog::Topic> {
+    if request.header.request_api_version >= 13 {
+        catalog.topic_by_id(topic.topic_id)
+    } else {
+        catalog.topic(&topic.topic)
+    }
+}
+
+// Errors are returned at once, like the requests that do not wait
+fn is_fetch_satisfied(request: &requests::Fetch, response: &responses::Fetch) -> bool {
+    let partitions = || response.topics.iter().flat_map(|topic| &topic.partitions);
+    let fetched_bytes: usize = partitions()
+        .map(|partition| partition.records.as_ref().map_or(0, Vec::len))
+        .sum();
+    request.max_wait_ms <= 0
+        || fetched_bytes >= request.min_bytes.max(0) as usize
+        || partitions().any(|partition| partition.error_code != model::ErrorCode::Ok as i16)
+}
+
+// The request max_bytes, shared by the partitions in the order of the request
+struct FetchBudget {
+    remaining_bytes: usize,
+    // the first batch is returned even if it is larger than the limits, until one is
+    min_one_batch: bool,
+}
+
+fn process_fetch_partition(
+    version: i16,
+    log: &Result<SharedPartitionLog, model::ErrorCode>,
+    partition: &requests::fetch::FetchPartition,
+    budget: &mut FetchBudget,
+) -> responses::fetch::PartitionData {
+    let mut response = responses::fetch::PartitionData {
+        partition_index: partition.partition,
+        error_code: model::ErrorCode::Ok as i16,
+        high_watermark: -1,
+        last_stable_offset: -1,
+        log_start_offset: -1,
+        diverging_epoch: None,
+        current_leader: None,
+        snapshot_id: None,
+        aborted_transactions: None,
+        preferred_read_replica: -1,
+        records: Some(vec![]),
+        tagged_fields: model::TaggedFields::default(),
+    };
+    let log = match log {
+        Ok(log) => log,
+        Err(error_code) => {
+            response.error_code = *error_code as i16;
+            return response;
+        }
+    };
+
+    let read = || -> log::Result<(Vec<u8>, i64, i64)> {
+        let log = log.lock().unwrap();
+        let max_bytes = budget
+            .remaining_bytes
+            .min(partition.partition_max_bytes.max(0) as usize);
+        let records = log.read(partition.fetch_offset, max_bytes, budget.min_one_batch)?;
+        Ok((records, log.log_end_offset(), log.log_start_offset()))
+    };
+    match read() {
+        Ok((records, log_end_offset, log_start_offset)) => {
+            budget.remaining_bytes = budget.remaining_bytes.saturating_sub(records.len());
+            budget.min_one_batch &= records.is_empty();
+            // without transactions every record is stable
+            response.high_watermark = log_end_offset;
+            response.last_stable_offset = log_end_offset;
+            response.log_start_offset = log_start_offset;
+            match fetch_records(version, records) {
+                Ok(records) => response.records = Some(records),
+                Err(_) => response.error_code = model::ErrorCode::CorruptMessage as i16,
+            }
+        }
+        Err(e) => {
+            let error_code = match e {
+                log::LogError::OffsetOutOfRange { .. } => model::ErrorCode::OffsetOutOfRange,
+                log::LogError::Record(_) => model::ErrorCode::CorruptMessage,
+                log::LogError::Io(_) => model::ErrorCode::KafkaStorageError,
+            };
+            response.error_code = error_code as i16;
+        }
+    }
+    response
+}
+
+// Fetch v0 and v1 responses hold messages v0, v2 and v3 responses hold messages v1
+fn fetch_magic(version: i16) -> i8 {
+    match version {
+        0 | 1 => 0,
+        2 | 3 => 1,
+        _ => record::CURRENT_MAGIC,
+    }
+}
+
+fn fetch_records(version: i16, records: Vec<u8>) -> record::Result<Vec<u8>> {
+    let magic = fetch_magic(version);
+    if magic < record::CURRENT_MAGIC {
+        return record::legacy::down_convert(&records, magic);
+    }
+    Ok(records)
+}
+
+fn process_produce_request(broker: &Broker, request: &requests::Produce) -> responses::Produce {
+    responses::Produce {
+        responses: request
+            .topic_data
             .iter()
-            .map(|topic| responses::fetch::FetchTopicResponse { topic_id: topic.id })
+            .map(|topic| responses::produce::TopicProduceResponse {
+                name: topic.name.clone(),
+                partition_responses: topic
+                    .partition_data
+                    .iter()
+                    .map(|partition| process_produce_partition(broker, request, topic, partition))
+                    .collect(),
+                tagged_fields: model::TaggedFields::default(),
+            })
             .collect(),
+        throttle_time_in_ms: 0,
+        node_endpoints: None,
+        tagged_fields: model::TaggedFields::default(),
+    }
+}
+
+fn process_produce_partition(
+    broker: &Broker,
+    request: &requests::Produce,
+    topic: &requests::produce::TopicProduceData,
+    partition: &requests::produce::PartitionProduceData,
+) -> responses::produce::PartitionProduceResponse {
+    let mut response = responses::produce::PartitionProduceResponse {
+        index: partition.index,
+        error_code: model::ErrorCode::Ok as i16,
+        base_offset: -1,
+        log_append_time_ms: -1, // records keep their create time
+        log_start_offset: -1,
+        record_errors: vec![],
+        error_message: None,
+        current_leader: None,
+        tagged_fields: model::TaggedFields::default(),
+    };
+
+    // the broker is the only replica, acks=-1 waits for the records to be on disk
+    if ![0, 1, -1].contains(&request.acks) {
+        response.error_code = model::ErrorCode::InvalidRequiredAcks as i16;
+        return response;
+    }
+    let Some(records) = &partition.records else {
+        response.error_code = model::ErrorCode::CorruptMessage as i16;
+        return response;
+    };
+    let found = match broker.catalog.lock().unwrap().topic(&topic.name) {
+        Some(found) if found.partition(partition.index).is_some() => found.clone(),
+        _ => {
+            response.error_code = model::ErrorCode::UnknownTopicOrPartition as i16;
+            return response;
+        }
+    };
+    let compression_type = found
+        .configs
+        .get("compression.type")
+        .and_then(|compression_type| compression_type.parse().ok())
+        .unwrap_or(broker.compression_type);
+
+    let append = || -> log::Result<(log::AppendInfo, i64)> {
+        // producers older than v3 send messages v0 or v1, the log only holds record batches
+        let up_converted;
+        let mut records = if request.header.request_api_version < 3 {
+            up_converted = record::legacy::up_convert(records)?;
+            &up_converted
+        } else {
+            records
+        };
+        // the batches are stored as sent unless the topic wants another codec
+        let recompressed;
+        if let Some(compression) = compression_type.target() {
+            recompressed = record::recompress(records, compression)?;
+            records = &recompressed;
+        }
+        let log = broker.partition_log(&found, partition.index)?;
+        let mut log = log.lock().unwrap();
+        let append_info = log.append(records)?;
+        if request.acks == -1 {
+            log.flush()?;
+        }
+        Ok((append_info, log.log_start_offset()))
+    };
+    match append() {
+        Ok((append_info, log_start_offset)) => {
+            response.base_offset = append_info.base_offset;
+            response.log_start_offset = log_start_offset;
+            broker
+                .fetch_purgatory
+                .complete(&topic.name, partition.index);
+        }
+        Err(e) => {
+            let error_code = match e {
+                log::LogError::Record(record::RecordError::UnsupportedMagic(_)) => {
+                    model::ErrorCode::UnsupportedForMessageFormat
+                }
+                log::LogError::Record(_) => model::ErrorCode::CorruptMessage,
+                log::LogError::Io(_) => model::ErrorCode::KafkaStorageError,
+                log::LogError::OffsetOutOfRange { .. } => model::ErrorCode::OffsetOutOfRange,
+            };
+            response.error_code = error_code as i16;
+            response.error_message = Some(e.to_string());
+        }
+    }
+    response
+}
+
+// Without ACLs every operation is authorized, these are bit fields of the AclOperation codes
+// https://github.com/apache/kafka/blob/3.8/clients/src/main/java/org/apache/kafka/common/acl/AclOperation.java
+const TOPIC_AUTHORIZED_OPERATIONS: i32 = 0b1101_1111_1000; // READ to DESCRIBE, DESCRIBE_CONFIGS, ALTER_CONFIGS
+const CLUSTER_AUTHORIZED_OPERATIONS: i32 = 0b1_1111_1010_0000; // CREATE, ALTER to IDEMPOTENT_WRITE
+const AUTHORIZED_OPERATIONS_OMITTED: i32 = i32::MIN;
+
+fn process_metadata_request(broker: &Broker, request: &requests::Metadata) -> responses::Metadata {
+    let mut catalog = broker.catalog.lock().unwrap();
+    let topics = match &request.topics {
+        None => catalog
+            .topics()
+            .map(|topic| metadata_topic(request, topic))
+            .collect(),
+        Some(topics) => topics
+            .iter()
+            .map(|topic| process_metadata_topic(broker, &mut catalog, request, topic))
+            .collect(),
+    };
+
+    responses::Metadata {
+        throttle_time_in_ms: 0,
+        brokers: vec![responses::metadata::MetadataResponseBroker {
+            node_id: broker.node_id,
+            host: broker.host.clone(),
+            port: broker.port,
+            rack: None,
+            tagged_fields: model::TaggedFields::default(),
+        }],
+        cluster_id: Some(broker.cluster_id.clone()),
+        controller_id: broker.node_id,
+        topics,
+        cluster_authorized_operations: if request.include_cluster_authorized_operations {
+            CLUSTER_AUTHORIZED_OPERATIONS
+        } else {
+            AUTHORIZED_OPERATIONS_OMITTED
+        },
+        tagged_fields: model::TaggedFields::default(),
+    }
+}
+
+fn process_metadata_topic(
+    broker: &Broker,
+    catalog: &mut catalog::Catalog,
+    request: &requests::Metadata,
+    topic: &requests::metadata::MetadataRequestTopic,
+) -> responses::metadata::MetadataResponseTopic {
+    let Some(name) = &topic.name else {
+        return match catalog.topic_by_id(topic.topic_id) {
+            Some(found) => metadata_topic(request, found),
+            None => metadata_topic_error(None, topic.topic_id, model::ErrorCode::UnknownTopicId),
+        };
+    };
+    if let Some(found) = catalog.topic(name) {
+        return metadata_topic(request, found);
+    }
+
+    let error_code = if !catalog::is_valid_topic_name(name) {
+        model::ErrorCode::InvalidTopicException
+    } else if !request.allow_auto_topic_creation {
+        model::ErrorCode::UnknownTopicOrPartition
+    } else {
+        match catalog.create_topic(name, broker.default_partition_count, broker.node_id) {
+            Ok(created) => return metadata_topic(request, created),
+            // no topic id could be generated
+            Err(_) => model::ErrorCode::UnknownServerError,
+        }
+    };
+    metadata_topic_error(Some(name.clone()), 0, error_code)
+}
+
+fn metadata_topic(
+    request: &requests::Metadata,
+    topic: &catalog::Topic,
+) -> responses::metadata::MetadataResponseTopic {
+    responses::metadata::MetadataResponseTopic {
+        error_code: model::ErrorCode::Ok as i16,
+        name: Some(topic.name.clone()),
+        topic_id: topic.id,
+        is_internal: topic.is_internal,
+        partitions: topic
+            .partitions
+            .iter()
+            .map(|partition| responses::metadata::MetadataResponsePartition {
+                error_code: model::ErrorCode::Ok as i16,
+                partition_index: partition.index,
+                leader_id: partition.leader_id,
+                leader_epoch: partition.leader_epoch,
+                replica_nodes: partition.replicas.clone(),
+                isr_nodes: partition.isr.clone(),
+                offline_replicas: partition.offline_replicas.clone(),
+                tagged_fields: model::TaggedFields::default(),
+            })
+            .collect(),
+        topic_authorized_operations: if request.include_topic_authorized_operations {
+            TOPIC_AUTHORIZED_OPERATIONS
+        } else {
+            AUTHORIZED_OPERATIONS_OMITTED
+        },
+        tagged_fields: model::TaggedFields::default(),
+    }
+}
+
+fn metadata_topic_error(
+    name: Option<String>,
+    topic_id: model::Uuid,
+    error_code: model::ErrorCode,
+) -> responses::metadata::MetadataResponseTopic {
+    responses::metadata::MetadataResponseTopic {
+        error_code: error_code as i16,
+        name,
+        topic_id,
+        is_internal: false,
+        partitions: vec![],
+        topic_authorized_operations: AUTHORIZED_OPERATIONS_OMITTED,
+        tagged_fields: model::TaggedFields::default(),
+    }
+}
+
+// Default of max.request.partition.size.limit, the most partitions a response can hold
+const MAX_RESPONSE_PARTITION_LIMIT: i32 = 2000;
+
+// Topics are described in name order, starting at the cursor and stopping once the partition
+// limit is reached, in which case the response has the cursor to continue from
+fn process_describe_topic_partitions_request(
+    broker: &Broker,
+    request: &requests::DescribeTopicPartitions,
+) -> responses::DescribeTopicPartitions {
+    let catalog = broker.catalog.lock().unwrap();
+    let mut names: Vec<String> = if request.topics.is_empty() {
+        catalog.topics().map(|topic| topic.name.clone()).collect()
+    } else {
+        request
+            .topics
+            .iter()
+            .map(|topic| topic.name.clone())
+            .collect()
+    };
+    names.sort();
+    names.dedup();
+
+    let mut response = responses::DescribeTopicPartitions {
+        throttle_time_in_ms: 0,
+        topics: vec![],
+        next_cursor: None,
+        tagged_fields: model::TaggedFields::default(),
+    };
+
+    let mut first_partition = 0;
+    if let Some(cursor) = &request.cursor {
+        // the cursor must point to one of the requested topics
+        if cursor.partition_index < 0 || !names.contains(&cursor.topic_name) {
+            response.topics = names
+                .into_iter()
+                .map(|name| describe_topic_error(name, model::ErrorCode::InvalidRequest))
+                .collect();
+            return response;
+        }
+        names.retain(|name| *name >= cursor.topic_name);
+        first_partition = cursor.partition_index;
+    }
+
+    let mut remaining = request
+        .response_partition_limit
+        .clamp(1, MAX_RESPONSE_PARTITION_LIMIT) as usize;
+    for name in names {
+        let Some(topic) = catalog.topic(&name) else {
+            let error_code = if catalog::is_valid_topic_name(&name) {
+                model::ErrorCode::UnknownTopicOrPartition
+            } else {
+                model::ErrorCode::InvalidTopicException
+            };
+            response.topics.push(describe_topic_error(name, error_code));
+            continue;
+        };
+        if remaining == 0 {
+            response.next_cursor = Some(describe_topic_partitions_cursor(name, first_partition));
+            break;
+        }
+
+        let partitions: Vec<_> = topic
+            .partitions
+            .iter()
+            .skip(first_partition as usize)
+            .take(remaining)
+            .map(describe_partition)
+            .collect();
+        remaining -= partitions.len();
+        let next_partition = first_partition + partitions.len() as i32;
+        response.topics.push(
+            responses::describe_topic_partitions::DescribeTopicPartitionsResponseTopic {
+                error_code: model::ErrorCode::Ok as i16,
+                name: Some(topic.name.clone()),
+                topic_id: topic.id,
+                is_internal: topic.is_internal,
+                partitions,
+                topic_authorized_operations: TOPIC_AUTHORIZED_OPERATIONS,
+                tagged_fields: model::TaggedFields::default(),
+            },
+        );
+
+        if (next_partition as usize) < topic.partitions.len() {
+            response.next_cursor = Some(describe_topic_partitions_cursor(name, next_partition));
+            break;
+        }
+        first_partition = 0;
+    }
+    response
+}
+
+fn describe_partition(
+    partition: &catalog::Partition,
+) -> responses::describe_topic_partitions::DescribeTopicPartitionsResponsePartition {
+    responses::describe_topic_partitions::DescribeTopicPartitionsResponsePartition {
+        error_code: model::ErrorCode::Ok as i16,
+        partition_index: partition.index,
+        leader_id: partition.leader_id,
+        leader_epoch: partition.leader_epoch,
+        replica_nodes: partition.replicas.clone(),
+        isr_nodes: partition.isr.clone(),
+        eligible_leader_replicas: Some(partition.eligible_leader_replicas.clone()),
+        last_known_elr: Some(partition.last_known_elr.clone()),
+        offline_replicas: partition.offline_replicas.clone(),
+        tagged_fields: model::TaggedFields::default(),
+    }
+}
+
+fn describe_topic_error(
+    name: String,
+    error_code: model::ErrorCode,
+) -> responses::describe_topic_partitions::DescribeTopicPartitionsResponseTopic {
+    responses::describe_topic_partitions::DescribeTopicPartitionsResponseTopic {
+        error_code: error_code as i16,
+        name: Some(name),
+        topic_id: 0,
+        is_internal: false,
+        partitions: vec![],
+        topic_authorized_operations: AUTHORIZED_OPERATIONS_OMITTED,
+        tagged_fields: model::TaggedFields::default(),
+    }
+}
+
+fn describe_topic_partitions_cursor(
+    topic_name: String,
+    partition_index: i32,
+) -> responses::describe_topic_partitions::Cursor {
+    responses::describe_topic_partitions::Cursor {
+        topic_name,
+        partition_index,
+        tagged_fields: model::TaggedFields::default(),
     }
 }
 
@@ -41,43 +735,1074 @@ mod tests {
 
     use super::model::ApiKey;
     use super::requests::RequestHeader;
+    use crate::broker::tests::test_broker;
+    #[cfg(feature = "compression")]
+    use crate::record::compression::{Compression, CompressionType};
+    use crate::record::legacy::tests::{message, message_set};
 
-    #[test]
-    fn test_process_request_api_versions() {
+    #[tokio::test]
+    async fn test_process_request_api_versions() {
         let request = &requests::Request::ApiVersions(requests::ApiVersions {
             header: RequestHeader {
                 request_api_key: ApiKey::Versions,
                 request_api_version: 4,
                 correlation_id: 311908132,
+                client_id: None,
+                tagged_fields: model::TaggedFields::default(),
             },
+            client_software_name: "kafka-cli".to_string(),
+            client_software_version: "0.1".to_string(),
+            tagged_fields: model::TaggedFields::default(),
         });
 
         let expected_response = responses::Response::ApiVersions(responses::ApiVersions {
-            api_key_versions: vec![model::ApiKeyVariant::Fetch, model::ApiKeyVariant::Versions],
+            error_code: 0,
+            api_key_versions: vec![
+                model::ApiKeyVariant::Produce.into(),
+                model::ApiKeyVariant::Fetch.into(),
+                model::ApiKeyVariant::Metadata.into(),
+                model::ApiKeyVariant::Versions.into(),
+                model::ApiKeyVariant::DescribeTopicPartitions.into(),
+            ],
             throttle_time_in_ms: 0,
+            supported_features: Some(vec![responses::api_versions::SupportedFeatureKey {
+                name: "metadata.version".to_string(),
+                min_version: 1,
+                max_version: 20,
+                tagged_fields: model::TaggedFields::default(),
+            }]),
+            finalized_features_epoch: None,
+            finalized_features: None,
+            zk_migration_ready: None,
+            tagged_fields: model::TaggedFields::default(),
         });
 
-        assert_eq!(process_request(request), expected_response);
+        assert_eq!(
+            process_request(&test_broker(), request).await,
+            expected_response
+        );
     }
 
-    #[test]
-    fn test_process_request_fetch() {
-        let request = &requests::Request::Fetch(requests::Fetch {
+    #[tokio::test]
+    async fn test_process_request_api_versions_should_send_the_finalized_features() {
+        let request = &requests::Request::ApiVersions(requests::ApiVersions {
             header: RequestHeader {
                 request_api_key: ApiKey::Versions,
                 request_api_version: 4,
                 correlation_id: 311908132,
+                client_id: None,
+                tagged_fields: model::TaggedFields::default(),
+            },
+            client_software_name: String::new(),
+            client_software_version: String::new(),
+            tagged_fields: model::TaggedFields::default(),
+        });
+        let mut broker = test_broker();
+        broker
+            .finalized_features
+            .insert("metadata.version".to_string(), 20);
+        broker.finalized_features_epoch = 8;
+
+        let responses::Response::ApiVersions(response) = process_request(&broker, request).await
+        else {
+            panic!("expected an ApiVersions response");
+        };
+        assert_eq!(response.finalized_features_epoch, Some(8));
+        assert_eq!(
+            response.finalized_features,
+            Some(vec![responses::api_versions::FinalizedFeatureKey {
+                name: "metadata.version".to_string(),
+                max_version_level: 20,
+                min_version_level: 20,
+                tagged_fields: model::TaggedFields::default(),
+            }])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_request_api_versions_should_list_versions_if_unsupported() {
+        let request = &requests::Request::ApiVersions(requests::ApiVersions {
+            header: RequestHeader {
+                request_api_key: ApiKey::Versions,
+                request_api_version: 5,
+                correlation_id: 311908132,
+                client_id: None,
+                tagged_fields: model::TaggedFields::default(),
             },
-            session_id: 85,
-            topics: vec![model::Topic { id: 37 }],
+            client_software_name: String::new(),
+            client_software_version: String::new(),
+            tagged_fields: model::TaggedFields::default(),
         });
 
+        let responses::Response::ApiVersions(response) =
+            process_request(&test_broker(), request).await
+        else {
+            panic!("expected an ApiVersions response");
+        };
+        assert_eq!(
+            response.error_code,
+            model::ErrorCode::UnsupportedVersion as i16
+        );
+        assert_eq!(response.api_key_versions.len(), 5);
+    }
+
+    fn fetch_request(version: i16, topics: Vec<requests::fetch::FetchTopic>) -> requests::Request {
+        requests::Request::Fetch(requests::Fetch {
+            header: RequestHeader {
+                request_api_key: ApiKey::Fetch,
+                request_api_version: version,
+                correlation_id: 311908132,
+                client_id: None,
+                tagged_fields: model::TaggedFields::default(),
+            },
+            replica_id: -1,
+            // answered at once, see `delayed_fetch_request`
+            max_wait_ms: 0,
+            min_bytes: 1,
+            max_bytes: 1024,
+            isolation_level: 0,
+            session_id: 0,
+            session_epoch: -1,
+            topics,
+            forgotten_topics_data: vec![],
+            rack_id: String::new(),
+            cluster_id: None,
+            replica_state: None,
+            tagged_fields: model::TaggedFields::default(),
+        })
+    }
+
+    fn fetch_topic(
+        topic: &str,
+        topic_id: model::Uuid,
+        partitions: &[i32],
+    ) -> requests::fetch::FetchTopic {
+        requests::fetch::FetchTopic {
+            topic: topic.to_string(),
+            topic_id,
+            partitions: partitions
+                .iter()
+                .map(|&partition| requests::fetch::FetchPartition {
+                    partition,
+                    current_leader_epoch: -1,
+                    fetch_offset: 0,
+                    last_fetched_epoch: -1,
+                    log_start_offset: -1,
+                    partition_max_bytes: 1024,
+                    tagged_fields: model::TaggedFields::default(),
+                })
+                .collect(),
+            tagged_fields: model::TaggedFields::default(),
+        }
+    }
+
+    async fn fetch_error_codes(broker: &Broker, request: &requests::Request) -> Vec<Vec<i16>> {
+        let responses::Response::Fetch(response) = process_request(broker, request).await else {
+            panic!("expected a Fetch response");
+        };
+        response
+            .topics
+            .iter()
+            .map(|topic| {
+                topic
+                    .partitions
+                    .iter()
+                    .map(|partition| partition.error_code)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_process_request_fetch() {
+        let request = &fetch_request(16, vec![fetch_topic("", 37, &[0])]);
+
         let expected_response = responses::Response::Fetch(responses::Fetch {
             throttle_time_in_ms: 0,
-            session_id: 85,
-            topics: vec![responses::fetch::FetchTopicResponse { topic_id: 37 }],
+            error_code: 0,
+            session_id: 0,
+            topics: vec![responses::fetch::FetchTopicResponse {
+                topic: String::new(),
+                topic_id: 37,
+                partitions: vec![responses::fetch::PartitionData {
+                    partition_index: 0,
+                    error_code: model::ErrorCode::UnknownTopicId as i16,
+                    high_watermark: -1,
+                    last_stable_offset: -1,
+                    log_start_offset: -1,
+                    diverging_epoch: None,
+                    current_leader: None,
+                    snapshot_id: None,
+                    aborted_transactions: None,
+                    preferred_read_replica: -1,
+                    records: Some(vec![]),
+                    tagged_fields: model::TaggedFields::default(),
+                }],
+                tagged_fields: model::TaggedFields::default(),
+            }],
+            node_endpoints: None,
+            tagged_fields: model::TaggedFields::default(),
         });
 
-        assert_eq!(process_request(request), expected_response);
+        assert_eq!(
+            process_request(&test_broker(), request).await,
+            expected_response
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_request_fetch_should_look_up_the_catalog() {
+        let broker = test_broker();
+        let topic_id = broker
+            .catalog
+            .lock()
+            .unwrap()
+            .create_topic("foo", 2, 1)
+            .unwrap()
+            .id;
+
+        let request = fetch_request(16, vec![fetch_topic("", topic_id, &[0, 1, 2])]);
+        assert_eq!(
+            fetch_error_codes(&broker, &request).await,
+            vec![vec![
+                model::ErrorCode::Ok as i16,
+                model::ErrorCode::Ok as i16,
+                model::ErrorCode::UnknownTopicOrPartition as i16
+            ]]
+        );
+
+        // by name before v13
+        let request = fetch_request(
+            12,
+            vec![fetch_topic("foo", 0, &[1]), fetch_topic("bar", 0, &[0])],
+        );
+        assert_eq!(
+            fetch_error_codes(&broker, &request).await,
+            vec![
+                vec![model::ErrorCode::Ok as i16],
+                vec![model::ErrorCode::UnknownTopicOrPartition as i16]
+            ]
+        );
+    }
+
+    async fn fetch_partitions(
+        broker: &Broker,
+        request: requests::Request,
+    ) -> Vec<responses::fetch::PartitionData> {
+        let responses::Response::Fetch(response) = process_request(broker, &request).await else {
+            panic!("expected a Fetch response");
+        };
+        response
+            .topics
+            .into_iter()
+            .flat_map(|topic| topic.partitions)
+            .collect()
+    }
+
+    fn base_offsets(records: &Option<Vec<u8>>) -> Vec<i64> {
+        record::tests::read_batches(records.as_deref().unwrap())
+            .unwrap()
+            .iter()
+            .map(|batch| batch.base_offset)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_process_request_fetch_should_read_the_partition_log() {
+        let broker = test_broker();
+        broker
+            .catalog
+            .lock()
+            .unwrap()
+            .create_topic("foo", 2, 1)
+            .unwrap();
+        for _ in 0..2 {
+            produce_partition_response(
+                &broker,
+                &produce_request(1, Some(record::tests::record_batch(2))),
+            )
+            .await;
+        }
+
+        let mut topic = fetch_topic("foo", 0, &[0, 1]);
+        topic.partitions[0].fetch_offset = 3;
+        let partitions = fetch_partitions(&broker, fetch_request(12, vec![topic])).await;
+        assert_eq!(partitions[0].error_code, model::ErrorCode::Ok as i16);
+        assert_eq!(base_offsets(&partitions[0].records), vec![2]);
+        assert_eq!(partitions[0].high_watermark, 4);
+        assert_eq!(partitions[0].last_stable_offset, 4);
+        assert_eq!(partitions[0].log_start_offset, 0);
+        // empty partition
+        assert_eq!(partitions[1].records, Some(vec![]));
+        assert_eq!(partitions[1].high_watermark, 0);
+
+        let mut topic = fetch_topic("foo", 0, &[0]);
+        topic.partitions[0].fetch_offset = 5;
+        let partitions = fetch_partitions(&broker, fetch_request(12, vec![topic])).await;
+        assert_eq!(
+            partitions[0].error_code,
+            model::ErrorCode::OffsetOutOfRange as i16
+        );
+        assert_eq!(partitions[0].high_watermark, -1);
+
+        // messages for the clients older than record batches
+        let partitions =
+            fetch_partitions(&broker, fetch_request(3, vec![fetch_topic("foo", 0, &[0])])).await;
+        let messages =
+            record::legacy::read_message_set(partitions[0].records.as_deref().unwrap()).unwrap();
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[3].offset, 3);
+    }
+
+    #[tokio::test]
+    async fn test_process_request_fetch_should_not_send_clients_to_a_follower() {
+        let broker = test_broker();
+        broker
+            .catalog
+            .lock()
+            .unwrap()
+            .create_topic("foo", 1, 1)
+            .unwrap();
+        produce_partition_response(
+            &broker,
+            &produce_request(1, Some(record::tests::record_batch(1))),
+        )
+        .await;
+
+        // v11 is the first version with the field, -1 means reading from the leader
+        let partitions = fetch_partitions(
+            &broker,
+            fetch_request(11, vec![fetch_topic("foo", 0, &[0])]),
+        )
+        .await;
+        assert_eq!(partitions[0].error_code, model::ErrorCode::Ok as i16);
+        assert_eq!(base_offsets(&partitions[0].records), vec![0]);
+        assert_eq!(partitions[0].preferred_read_replica, -1);
+    }
+
+    #[tokio::test]
+    async fn test_process_request_fetch_should_honor_max_bytes() {
+        let broker = test_broker();
+        broker
+            .catalog
+            .lock()
+            .unwrap()
+            .create_topic("foo", 2, 1)
+            .unwrap();
+        let batch_size = record::tests::record_batch(1).len() as i32;
+        for _ in 0..2 {
+            produce_partition_response(
+                &broker,
+                &produce_request(1, Some(record::tests::record_batch(1))),
+            )
+            .await;
+        }
+        let mut request = produce_request(1, Some(record::tests::record_batch(1)));
+        if let requests::Request::Produce(request) = &mut request {
+            request.topic_data[0].partition_data[0].index = 1;
+        }
+        produce_partition_response(&broker, &request).await;
+
+        let broker = &broker;
+        let fetch = |version, max_bytes, partition_max_bytes| async move {
+            let mut topic = fetch_topic("foo", 0, &[0, 1]);
+            for partition in &mut topic.partitions {
+                partition.partition_max_bytes = partition_max_bytes;
+            }
+            let mut request = fetch_request(version, vec![topic]);
+            if let requests::Request::Fetch(request) = &mut request {
+                request.max_bytes = max_bytes;
+            }
+            fetch_partitions(broker, request)
+                .await
+                .iter()
+                .map(|partition| base_offsets(&partition.records))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(fetch(12, 1024, 1024).await, vec![vec![0, 1], vec![0]]);
+        assert_eq!(fetch(12, 1024, batch_size).await, vec![vec![0], vec![0]]);
+        assert_eq!(
+            fetch(12, 2 * batch_size, 1024).await,
+            vec![vec![0, 1], vec![]]
+        );
+        // the first batch is returned even when it is too large, but not before v3
+        assert_eq!(fetch(12, 1, 1).await, vec![vec![0], vec![]]);
+        assert_eq!(fetch(12, 1024, 1).await, vec![vec![0], vec![]]);
+        assert_eq!(fetch(4, 1, 1).await, vec![vec![0], vec![]]);
+        let mut request = fetch_request(2, vec![fetch_topic("foo", 0, &[0])]);
+        if let requests::Request::Fetch(request) = &mut request {
+            request.topics[0].partitions[0].partition_max_bytes = 1;
+        }
+        assert_eq!(
+            fetch_partitions(broker, request).await[0].records,
+            Some(vec![])
+        );
+    }
+
+    fn delayed_fetch_request(max_wait_ms: i32, min_bytes: i32) -> requests::Request {
+        let mut request = fetch_request(12, vec![fetch_topic("foo", 0, &[0])]);
+        if let requests::Request::Fetch(request) = &mut request {
+            request.max_wait_ms = max_wait_ms;
+            request.min_bytes = min_bytes;
+        }
+        request
+    }
+
+    #[tokio::test]
+    async fn test_process_request_fetch_should_wait_for_min_bytes() {
+        let broker = test_broker();
+        broker
+            .catalog
+            .lock()
+            .unwrap()
+            .create_topic("foo", 1, 1)
+            .unwrap();
+
+        // completed by the produce
+        let start = Instant::now();
+        let request = delayed_fetch_request(10_000, 1);
+        let fetch = process_request(&broker, &request);
+        let produce = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            produce_partition_response(
+                &broker,
+                &produce_request(1, Some(record::tests::record_batch(1))),
+            )
+            .await;
+        };
+        let (response, ()) = tokio::join!(fetch, produce);
+        let responses::Response::Fetch(response) = response else {
+            panic!("expected a Fetch response");
+        };
+        assert_eq!(
+            base_offsets(&response.topics[0].partitions[0].records),
+            vec![0]
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // completed at once when min_bytes are available
+        let batch_size = record::tests::record_batch(1).len() as i32;
+        let start = Instant::now();
+        process_request(&broker, &delayed_fetch_request(10_000, batch_size)).await;
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_process_request_fetch_should_wait_for_max_wait_ms() {
+        let broker = test_broker();
+        broker
+            .catalog
+            .lock()
+            .unwrap()
+            .create_topic("foo", 1, 1)
+            .unwrap();
+        produce_partition_response(
+            &broker,
+            &produce_request(1, Some(record::tests::record_batch(1))),
+        )
+        .await;
+
+        let start = Instant::now();
+        let response = process_request(&broker, &delayed_fetch_request(50, 1024)).await;
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        let responses::Response::Fetch(response) = response else {
+            panic!("expected a Fetch response");
+        };
+        // what is there when the time is up
+        assert_eq!(
+            base_offsets(&response.topics[0].partitions[0].records),
+            vec![0]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_request_fetch_should_complete_every_fetch_waiting_on_a_partition() {
+        let broker = Arc::new(test_broker());
+        broker
+            .catalog
+            .lock()
+            .unwrap()
+            .create_topic("foo", 1, 1)
+            .unwrap();
+
+        let start = Instant::now();
+        let fetches: Vec<_> = (0..8)
+            .map(|_| {
+                let broker = broker.clone();
+                tokio::spawn(async move {
+                    process_request(&broker, &delayed_fetch_request(10_000, 1)).await
+                })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        produce_partition_response(
+            &broker,
+            &produce_request(1, Some(record::tests::record_batch(1))),
+        )
+        .await;
+
+        for fetch in fetches {
+            let responses::Response::Fetch(response) = fetch.await.unwrap() else {
+                panic!("expected a Fetch response");
+            };
+            assert_eq!(
+                base_offsets(&response.topics[0].partitions[0].records),
+                vec![0]
+            );
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    fn session_fetch_request(
+        session_id: i32,
+        session_epoch: i32,
+        topics: Vec<requests::fetch::FetchTopic>,
+        forgotten_partitions: &[i32],
+    ) -> requests::Request {
+        let mut request = fetch_request(12, topics);
+        if let requests::Request::Fetch(request) = &mut request {
+            request.session_id = session_id;
+            request.session_epoch = session_epoch;
+            if !forgotten_partitions.is_empty() {
+                request.forgotten_topics_data = vec![requests::fetch::ForgottenTopic {
+                    topic: "foo".to_string(),
+                    topic_id: 0,
+                    partitions: forgotten_partitions.to_vec(),
+                    tagged_fields: model::TaggedFields::default(),
+                }];
+            }
+        }
+        request
+    }
+
+    async fn fetch_response(broker: &Broker, request: requests::Request) -> responses::Fetch {
+        let responses::Response::Fetch(response) = process_request(broker, &request).await else {
+            panic!("expected a Fetch response");
+        };
+        response
+    }
+
+    fn fetched_partition_indexes(response: &responses::Fetch) -> Vec<i32> {
+        response
+            .topics
+            .iter()
+            .flat_map(|topic| &topic.partitions)
+            .map(|partition| partition.partition_index)
+            .collect()
+    }
+
+    async fn produce_to(broker: &Broker, partition: i32) {
+        let mut request = produce_request(1, Some(record::tests::record_batch(1)));
+        if let requests::Request::Produce(request) = &mut request {
+            request.topic_data[0].partition_data[0].index = partition;
+        }
+        let response = produce_partition_response(broker, &request).await;
+        assert_eq!(response.error_code, model::ErrorCode::Ok as i16);
+    }
+
+    #[tokio::test]
+    async fn test_process_request_fetch_should_use_incremental_sessions() {
+        let broker = test_broker();
+        broker
+            .catalog
+            .lock()
+            .unwrap()
+            .create_topic("foo", 2, 1)
+            .unwrap();
+        produce_to(&broker, 0).await;
+
+        // a full request creates the session
+        let request = session_fetch_request(0, 0, vec![fetch_topic("foo", 0, &[0, 1])], &[]);
+        let response = fetch_response(&broker, request).await;
+        let session_id = response.session_id;
+        assert_ne!(session_id, 0);
+        assert_eq!(fetched_partition_indexes(&response), vec![0, 1]);
+
+        // then only the partitions that changed are sent
+        let mut topic = fetch_topic("foo", 0, &[0]);
+        topic.partitions[0].fetch_offset = 1;
+        let response = fetch_response(
+            &broker,
+            session_fetch_request(session_id, 1, vec![topic], &[]),
+        )
+        .await;
+        assert_eq!(response.session_id, session_id);
+        assert!(response.topics.is_empty());
+
+        produce_to(&broker, 1).await;
+        let response =
+            fetch_response(&broker, session_fetch_request(session_id, 2, vec![], &[])).await;
+        assert_eq!(fetched_partition_indexes(&response), vec![1]);
+        assert_eq!(response.topics[0].partitions[0].high_watermark, 1);
+
+        // forgotten partitions are no longer fetched
+        let response =
+            fetch_response(&broker, session_fetch_request(session_id, 3, vec![], &[1])).await;
+        assert!(response.topics.is_empty());
+        produce_to(&broker, 1).await;
+        let response =
+            fetch_response(&broker, session_fetch_request(session_id, 4, vec![], &[])).await;
+        assert!(response.topics.is_empty());
+
+        let response =
+            fetch_response(&broker, session_fetch_request(session_id, 4, vec![], &[])).await;
+        assert_eq!(
+            response.error_code,
+            model::ErrorCode::InvalidFetchSessionEpoch as i16
+        );
+        assert_eq!(response.session_id, 0);
+
+        // a request with the final epoch closes the session
+        let request = session_fetch_request(session_id, -1, vec![fetch_topic("foo", 0, &[0])], &[]);
+        let response = fetch_response(&broker, request).await;
+        assert_eq!(response.session_id, 0);
+        assert_eq!(fetched_partition_indexes(&response), vec![0]);
+        let response =
+            fetch_response(&broker, session_fetch_request(session_id, 5, vec![], &[])).await;
+        assert_eq!(
+            response.error_code,
+            model::ErrorCode::FetchSessionIdNotFound as i16
+        );
+    }
+
+    fn produce_request(acks: i16, records: Option<Vec<u8>>) -> requests::Request {
+        requests::Request::Produce(requests::Produce {
+            header: RequestHeader {
+                request_api_key: ApiKey::Produce,
+                request_api_version: 11,
+                correlation_id: 311908132,
+                client_id: None,
+                tagged_fields: model::TaggedFields::default(),
+            },
+            transactional_id: None,
+            acks,
+            timeout_ms: 1000,
+            topic_data: vec![requests::produce::TopicProduceData {
+                name: "foo".to_string(),
+                partition_data: vec![requests::produce::PartitionProduceData {
+                    index: 0,
+                    records,
+                    tagged_fields: model::TaggedFields::default(),
+                }],
+                tagged_fields: model::TaggedFields::default(),
+            }],
+            tagged_fields: model::TaggedFields::default(),
+        })
+    }
+
+    async fn produce_partition_response(
+        broker: &Broker,
+        request: &requests::Request,
+    ) -> responses::produce::PartitionProduceResponse {
+        let responses::Response::Produce(mut response) = process_request(broker, request).await
+        else {
+            panic!("expected a Produce response");
+        };
+        response.responses.remove(0).partition_responses.remove(0)
+    }
+
+    #[tokio::test]
+    async fn test_process_request_produce_should_append_to_the_partition_log() {
+        let broker = test_broker();
+        broker
+            .catalog
+            .lock()
+            .unwrap()
+            .create_topic("foo", 1, 1)
+            .unwrap();
+
+        let request = produce_request(1, Some(record::tests::record_batch(2)));
+        let response = produce_partition_response(&broker, &request).await;
+        assert_eq!(response.error_code, model::ErrorCode::Ok as i16);
+        assert_eq!(response.base_offset, 0);
+        assert_eq!(response.log_start_offset, 0);
+
+        let request = produce_request(-1, Some(record::tests::record_batch(1)));
+        let response = produce_partition_response(&broker, &request).await;
+        assert_eq!(response.error_code, model::ErrorCode::Ok as i16);
+        assert_eq!(response.base_offset, 2);
+    }
+
+    #[tokio::test]
+    async fn test_process_request_produce_should_fail_on_invalid_requests() {
+        let broker = test_broker();
+        let request = produce_request(1, Some(record::tests::record_batch(1)));
+        let response = produce_partition_response(&broker, &request).await;
+        assert_eq!(
+            response.error_code,
+            model::ErrorCode::UnknownTopicOrPartition as i16
+        );
+
+        broker
+            .catalog
+            .lock()
+            .unwrap()
+            .create_topic("foo", 1, 1)
+            .unwrap();
+
+        let request = produce_request(2, Some(record::tests::record_batch(1)));
+        let response = produce_partition_response(&broker, &request).await;
+        assert_eq!(
+            response.error_code,
+            model::ErrorCode::InvalidRequiredAcks as i16
+        );
+
+        let request = produce_request(1, None);
+        let response = produce_partition_response(&broker, &request).await;
+        assert_eq!(response.error_code, model::ErrorCode::CorruptMessage as i16);
+
+        let mut records = record::tests::record_batch(1);
+        records[16] = 1; // magic
+        let response =
+            produce_partition_response(&broker, &produce_request(1, Some(records))).await;
+        assert_eq!(
+            response.error_code,
+            model::ErrorCode::UnsupportedForMessageFormat as i16
+        );
+        assert_eq!(response.base_offset, -1);
+    }
+
+    #[tokio::test]
+    async fn test_process_request_produce_should_up_convert_messages() {
+        let broker = test_broker();
+        broker
+            .catalog
+            .lock()
+            .unwrap()
+            .create_topic("foo", 1, 1)
+            .unwrap();
+        let messages = [message(0, 1, b"foo"), message(1, 1, b"bar")];
+        let requests::Request::Produce(mut request) =
+            produce_request(1, Some(message_set(&messages)))
+        else {
+            unreachable!();
+        };
+        request.header.request_api_version = 2;
+
+        let request = requests::Request::Produce(request);
+        let response = produce_partition_response(&broker, &request).await;
+        assert_eq!(response.error_code, model::ErrorCode::Ok as i16);
+        let response = produce_partition_response(&broker, &request).await;
+        assert_eq!(response.base_offset, 2);
+    }
+
+    #[cfg(feature = "compression")]
+    #[tokio::test]
+    async fn test_process_request_produce_should_recompress_to_the_topic_codec() {
+        let log_dir = crate::log::tests::temp_log_dir();
+        let mut config = crate::config::Config::new(&log_dir);
+        config.compression_type = CompressionType::Zstd;
+        let broker = Broker::new(&config);
+        {
+            let mut catalog = broker.catalog.lock().unwrap();
+            catalog.create_topic("foo", 1, 1).unwrap();
+            let mut topic = catalog.topic("foo").unwrap().clone();
+            topic
+                .configs
+                .insert("compression.type".to_string(), "gzip".to_string());
+            catalog.add_topic(topic);
+        }
+
+        let request = produce_request(1, Some(record::tests::record_batch(3)));
+        let response = produce_partition_response(&broker, &request).await;
+        assert_eq!(response.error_code, model::ErrorCode::Ok as i16);
+
+        let path = log::PartitionLog::dir(&log_dir, "foo", 0).join(format!("{:020}.log", 0));
+        let batches = record::tests::read_batches(&std::fs::read(path).unwrap()).unwrap();
+        assert_eq!(batches[0].compression(), Compression::Gzip);
+        assert_eq!(batches[0].records.len(), 3);
+    }
+
+    #[test]
+    fn test_fetch_magic() {
+        assert_eq!(fetch_magic(0), 0);
+        assert_eq!(fetch_magic(3), 1);
+        assert_eq!(fetch_magic(4), record::CURRENT_MAGIC);
+    }
+
+    fn metadata_request(
+        topics: Option<Vec<requests::metadata::MetadataRequestTopic>>,
+        allow_auto_topic_creation: bool,
+    ) -> requests::Request {
+        requests::Request::Metadata(requests::Metadata {
+            header: RequestHeader {
+                request_api_key: ApiKey::Metadata,
+                request_api_version: 12,
+                correlation_id: 311908132,
+                client_id: None,
+                tagged_fields: model::TaggedFields::default(),
+            },
+            topics,
+            allow_auto_topic_creation,
+            include_cluster_authorized_operations: false,
+            include_topic_authorized_operations: true,
+            tagged_fields: model::TaggedFields::default(),
+        })
+    }
+
+    fn metadata_request_topic(
+        topic_id: model::Uuid,
+        name: Option<&str>,
+    ) -> requests::metadata::MetadataRequestTopic {
+        requests::metadata::MetadataRequestTopic {
+            topic_id,
+            name: name.map(str::to_string),
+            tagged_fields: model::TaggedFields::default(),
+        }
+    }
+
+    async fn metadata_response(
+        broker: &Broker,
+        request: &requests::Request,
+    ) -> responses::Metadata {
+        let responses::Response::Metadata(response) = process_request(broker, request).await else {
+            panic!("expected a Metadata response");
+        };
+        response
+    }
+
+    #[tokio::test]
+    async fn test_process_request_metadata_should_describe_the_broker() {
+        let broker = test_broker();
+        let response = metadata_response(&broker, &metadata_request(None, true)).await;
+
+        assert_eq!(response.brokers.len(), 1);
+        assert_eq!(response.brokers[0].node_id, broker.node_id);
+        assert_eq!(response.brokers[0].port, 9092);
+        assert_eq!(response.cluster_id, Some(broker.cluster_id.clone()));
+        assert_eq!(response.controller_id, broker.node_id);
+        assert!(response.topics.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_process_request_metadata_should_create_topics() {
+        let broker = test_broker();
+        let topics = vec![metadata_request_topic(0, Some("foo"))];
+
+        let response = metadata_response(&broker, &metadata_request(Some(topics), false)).await;
+        assert_eq!(
+            response.topics[0].error_code,
+            model::ErrorCode::UnknownTopicOrPartition as i16
+        );
+
+        let topics = vec![metadata_request_topic(0, Some("foo"))];
+        let response = metadata_response(&broker, &metadata_request(Some(topics), true)).await;
+        let topic = &response.topics[0];
+        assert_eq!(topic.error_code, model::ErrorCode::Ok as i16);
+        assert_ne!(topic.topic_id, 0);
+        assert_eq!(
+            topic.topic_authorized_operations,
+            TOPIC_AUTHORIZED_OPERATIONS
+        );
+        assert_eq!(topic.partitions.len(), 1);
+        assert_eq!(topic.partitions[0].leader_id, broker.node_id);
+        assert_eq!(topic.partitions[0].isr_nodes, vec![broker.node_id]);
+
+        // every topic, then by id
+        let response = metadata_response(&broker, &metadata_request(None, false)).await;
+        assert_eq!(response.topics.len(), 1);
+        let topics = vec![metadata_request_topic(topic.topic_id, None)];
+        let response = metadata_response(&broker, &metadata_request(Some(topics), false)).await;
+        assert_eq!(response.topics[0].name, Some("foo".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_process_request_should_serve_created_topics_after_a_restart() {
+        let config = crate::config::Config::new(crate::log::tests::temp_log_dir());
+        let broker = Broker::new(&config);
+        let topics = vec![metadata_request_topic(0, Some("foo"))];
+        let response = metadata_response(&broker, &metadata_request(Some(topics), true)).await;
+        let topic_id = response.topics[0].topic_id;
+        let request = produce_request(1, Some(record::tests::record_batch(2)));
+        let response = produce_partition_response(&broker, &request).await;
+        assert_eq!(response.error_code, model::ErrorCode::Ok as i16);
+        broker.shutdown().unwrap();
+        drop(broker);
+
+        let broker = Broker::new(&config);
+        assert_eq!(broker.load_logs().unwrap(), 1);
+        let request = produce_request(1, Some(record::tests::record_batch(1)));
+        let response = produce_partition_response(&broker, &request).await;
+        assert_eq!(response.error_code, model::ErrorCode::Ok as i16);
+        assert_eq!(response.base_offset, 2);
+
+        // by name, then by the id the topic had before the restart
+        let request = fetch_request(12, vec![fetch_topic("foo", 0, &[0])]);
+        let partitions = fetch_partitions(&broker, request).await;
+        assert_eq!(base_offsets(&partitions[0].records), vec![0, 2]);
+        let request = fetch_request(13, vec![fetch_topic("", topic_id, &[0])]);
+        let partitions = fetch_partitions(&broker, request).await;
+        assert_eq!(partitions[0].error_code, model::ErrorCode::Ok as i16);
+        assert_eq!(base_offsets(&partitions[0].records), vec![0, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_process_request_metadata_should_fail_on_unknown_or_invalid_topics() {
+        let broker = test_broker();
+        let topics = vec![
+            metadata_request_topic(17, None),
+            metadata_request_topic(0, Some("foo bar")),
+        ];
+
+        let response = metadata_response(&broker, &metadata_request(Some(topics), true)).await;
+        assert_eq!(
+            response.topics[0].error_code,
+            model::ErrorCode::UnknownTopicId as i16
+        );
+        assert_eq!(
+            response.topics[1].error_code,
+            model::ErrorCode::InvalidTopicException as i16
+        );
+        assert!(broker.catalog.lock().unwrap().topics().next().is_none());
+    }
+
+    fn describe_topic_partitions_request(
+        topics: &[&str],
+        response_partition_limit: i32,
+        cursor: Option<(&str, i32)>,
+    ) -> requests::Request {
+        requests::Request::DescribeTopicPartitions(requests::DescribeTopicPartitions {
+            header: RequestHeader {
+                request_api_key: ApiKey::DescribeTopicPartitions,
+                request_api_version: 0,
+                correlation_id: 311908132,
+                client_id: None,
+                tagged_fields: model::TaggedFields::default(),
+            },
+            topics: topics
+                .iter()
+                .map(|name| requests::describe_topic_partitions::TopicRequest {
+                    name: name.to_string(),
+                    tagged_fields: model::TaggedFields::default(),
+                })
+                .collect(),
+            response_partition_limit,
+            cursor: cursor.map(|(topic_name, partition_index)| {
+                requests::describe_topic_partitions::Cursor {
+                    topic_name: topic_name.to_string(),
+                    partition_index,
+                    tagged_fields: model::TaggedFields::default(),
+                }
+            }),
+            tagged_fields: model::TaggedFields::default(),
+        })
+    }
+
+    async fn describe_topic_partitions_response(
+        broker: &Broker,
+        request: &requests::Request,
+    ) -> responses::DescribeTopicPartitions {
+        let responses::Response::DescribeTopicPartitions(response) =
+            process_request(broker, request).await
+        else {
+            panic!("expected a DescribeTopicPartitions response");
+        };
+        response
+    }
+
+    #[tokio::test]
+    async fn test_process_request_describe_topic_partitions() {
+        let broker = test_broker();
+        let id = broker
+            .catalog
+            .lock()
+            .unwrap()
+            .create_topic("foo", 2, 1)
+            .unwrap()
+            .id;
+
+        let request = describe_topic_partitions_request(&["foo", "bar", "foo bar"], 2000, None);
+        let response = describe_topic_partitions_response(&broker, &request).await;
+
+        let errors: Vec<_> = response
+            .topics
+            .iter()
+            .map(|topic| (topic.name.as_deref().unwrap(), topic.error_code))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("bar", model::ErrorCode::UnknownTopicOrPartition as i16),
+                ("foo", model::ErrorCode::Ok as i16),
+                ("foo bar", model::ErrorCode::InvalidTopicException as i16),
+            ]
+        );
+        let topic = &response.topics[1];
+        assert_eq!(topic.topic_id, id);
+        assert_eq!(topic.partitions.len(), 2);
+        assert_eq!(topic.partitions[1].partition_index, 1);
+        assert_eq!(topic.partitions[1].isr_nodes, vec![1]);
+        assert_eq!(topic.partitions[1].eligible_leader_replicas, Some(vec![]));
+        assert_eq!(response.next_cursor, None);
+    }
+
+    #[tokio::test]
+    async fn test_process_request_describe_topic_partitions_should_paginate() {
+        let broker = test_broker();
+        {
+            let mut catalog = broker.catalog.lock().unwrap();
+            catalog.create_topic("foo", 3, 1).unwrap();
+            catalog.create_topic("bar", 2, 1).unwrap();
+            catalog.create_topic("baz", 1, 1).unwrap();
+        }
+        let broker = &broker;
+        let page = |cursor| async move {
+            let request = describe_topic_partitions_request(&[], 2, cursor);
+            let response = describe_topic_partitions_response(broker, &request).await;
+            let partitions: Vec<_> = response
+                .topics
+                .iter()
+                .flat_map(|topic| {
+                    let name = topic.name.clone().unwrap();
+                    topic
+                        .partitions
+                        .iter()
+                        .map(move |partition| (name.clone(), partition.partition_index))
+                })
+                .collect();
+            let next_cursor = response
+                .next_cursor
+                .map(|cursor| (cursor.topic_name, cursor.partition_index));
+            (partitions, next_cursor)
+        };
+        let partition = |name: &str, index| (name.to_string(), index);
+
+        assert_eq!(
+            page(None).await,
+            (
+                vec![partition("bar", 0), partition("bar", 1)],
+                Some(("baz".to_string(), 0))
+            )
+        );
+        assert_eq!(
+            page(Some(("baz", 0))).await,
+            (
+                vec![partition("baz", 0), partition("foo", 0)],
+                Some(("foo".to_string(), 1))
+            )
+        );
+        assert_eq!(
+            page(Some(("foo", 1))).await,
+            (vec![partition("foo", 1), partition("foo", 2)], None)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_request_describe_topic_partitions_should_reject_an_invalid_cursor() {
+        let broker = test_broker();
+        broker
+            .catalog
+            .lock()
+            .unwrap()
+            .create_topic("foo", 1, 1)
+            .unwrap();
+
+        let request = describe_topic_partitions_request(&["foo"], 10, Some(("bar", 0)));
+        let response = describe_topic_partitions_response(&broker, &request).await;
+        assert_eq!(
+            response.topics[0].error_code,
+            model::ErrorCode::InvalidRequest as i16
+        );
+        assert!(response.topics[0].partitions.is_empty());
     }
 }
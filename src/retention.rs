@@ -0,0 +1,122 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::{task, time};
+
+use crate::broker::Broker;
+
+// Deletes the segments past the retention of their topic, every `check_interval`
+pub async fn run(broker: Arc<Broker>, check_interval: Duration) {
+    let mut interval = time::interval(check_interval);
+    loop {
+        interval.tick().await;
+        let broker = broker.clone();
+        // the logs are locked, and the segment files read and renamed, with blocking calls
+        match task::spawn_blocking(move || delete_old_segments(&broker, now_ms())).await {
+            Ok(deleted) => {
+                for (files, delay) in deleted {
+                    tokio::spawn(remove_later(files, delay));
+                }
+            }
+            Err(e) => eprintln!("Error deleting old segments: {}", e),
+        }
+    }
+}
+
+// Takes the old segments out of each log, then renames their files once the log is unlocked.
+// Returns the renamed files of each log, with the delay after which they can be removed.
+fn delete_old_segments(broker: &Broker, now_ms: i64) -> Vec<(Vec<PathBuf>, Duration)> {
+    let mut deleted = vec![];
+    for (_, log) in broker.partition_logs() {
+        let segments = log.lock().unwrap().delete_old_segments(now_ms);
+        match segments {
+            Ok(segments) if segments.is_empty() => {}
+            Ok(segments) => {
+                let delay = segments.delay;
+                match segments.rename() {
+                    Ok(files) => deleted.push((files, delay)),
+                    Err(e) => eprintln!("Error renaming deleted segments: {}", e),
+                }
+            }
+            Err(e) => eprintln!("Error deleting old segments: {}", e),
+        }
+    }
+    deleted
+}
+
+pub fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as i64)
+}
+
+// Removes the files of deleted segments, once the reads that could still use them are done
+pub async fn remove_later(files: Vec<PathBuf>, delay: Duration) {
+    time::sleep(delay).await;
+    let removed = task::spawn_blocking(move || {
+        for file in files {
+            if let Err(e) = fs::remove_file(&file) {
+                eprintln!("Error removing {}: {}", file.display(), e);
+            }
+        }
+    });
+    if let Err(e) = removed.await {
+        eprintln!("Error removing deleted segments: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+    use crate::log::{self, LogConfig};
+    use crate::record::tests::record_batch;
+
+    #[tokio::test]
+    async fn test_run_should_delete_the_oldest_segments() {
+        let log_dir = log::tests::temp_log_dir();
+        let batch_size = record_batch(1).len() as u64;
+        let mut config = Config::new(&log_dir);
+        config.log_config = LogConfig {
+            segment_bytes: batch_size,
+            retention_ms: -1,
+            retention_bytes: batch_size as i64,
+            file_delete_delay_ms: 0,
+            ..LogConfig::default()
+        };
+        let broker = Arc::new(Broker::new(&config));
+        let topic = broker
+            .catalog
+            .lock()
+            .unwrap()
+            .create_topic("foo", 1, 1)
+            .unwrap()
+            .clone();
+        let log = broker.partition_log(&topic, 0).unwrap();
+        for _ in 0..3 {
+            log.lock().unwrap().append(&record_batch(1)).unwrap();
+        }
+
+        tokio::spawn(run(broker.clone(), Duration::from_millis(10)));
+        let dir = log::PartitionLog::dir(&log_dir, "foo", 0);
+        let segment_count = || {
+            fs::read_dir(&dir)
+                .unwrap()
+                .filter(|entry| entry.as_ref().unwrap().path().extension().unwrap() == "log")
+                .count()
+        };
+        // the files of the last segment, and partition.metadata
+        for _ in 0..100 {
+            if fs::read_dir(&dir).unwrap().count() == 4 {
+                break;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(segment_count(), 1);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+        assert_eq!(log.lock().unwrap().log_start_offset(), 2);
+    }
+}
@@ -1,131 +1,681 @@
+use crate::broker::Broker;
 use crate::request_handler;
 use requests::HasRequestHeader;
 use requests::Request;
 use tokio::{
-    io::AsyncWriteExt,
-    net::{TcpListener, TcpStream},
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+    sync::mpsc,
+    task::JoinHandle,
 };
 
 use model::WireSerialization;
 
 use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 use bytes::BufMut;
 
+pub mod codec;
 pub mod model;
 pub mod requests;
 pub mod responses;
 
-pub async fn start_server(address: &str) -> Result<(), Box<dyn Error>> {
+pub async fn start_server(address: &str, broker: Arc<Broker>) -> Result<(), Box<dyn Error>> {
     let listener = TcpListener::bind(address).await.unwrap();
     println!("Server listening on {}", address);
 
+    let metrics = Arc::new(Metrics::default());
+
     loop {
-        let (mut stream, _) = listener.accept().await?;
+        let (mut stream, peer) = listener.accept().await?;
+        let metrics = metrics.clone();
+        let broker = broker.clone();
 
         tokio::spawn(async move {
+            let mut connection = Connection::default();
+            let reason = connection.serve(&mut stream, &metrics, &broker).await;
+            if let Err(e) = stream.shutdown().await {
+                eprintln!("Error shutting down connection from {}: {}", peer, e);
+            }
+            println!("Connection from {} closed: {}", peer, reason);
+        });
+    }
+}
+
+#[derive(Debug)]
+enum CloseReason {
+    // the client closed the stream between two requests
+    EndOfStream,
+    Protocol(codec::DecodeError),
+    UnsupportedVersion {
+        api_key: model::ApiKey,
+        version: i16,
+    },
+    Io(io::Error),
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloseReason::EndOfStream => write!(f, "closed by the client"),
+            CloseReason::Protocol(e) => write!(f, "protocol error: {}", e),
+            CloseReason::UnsupportedVersion { api_key, version } => {
+                write!(f, "unsupported version {} of {:?}", version, api_key)
+            }
+            CloseReason::Io(e) => write!(f, "i/o error: {}", e),
+        }
+    }
+}
+
+impl From<codec::DecodeError> for CloseReason {
+    fn from(e: codec::DecodeError) -> Self {
+        CloseReason::Protocol(e)
+    }
+}
+
+impl From<io::Error> for CloseReason {
+    fn from(e: io::Error) -> Self {
+        // invalid frames are reported by `Request::read_frame` as invalid data
+        if e.get_ref()
+            .is_some_and(|inner| inner.is::<codec::DecodeError>())
+        {
+            let inner = e
+                .into_inner()
+                .unwrap()
+                .downcast::<codec::DecodeError>()
+                .unwrap();
+            return CloseReason::Protocol(*inner);
+        }
+        CloseReason::Io(e)
+    }
+}
+
+// Requests read ahead of the response being written, like the clients default
+// max.in.flight.requests.per.connection
+const MAX_IN_FLIGHT_REQUESTS: usize = 5;
+
+#[derive(Debug, Default)]
+struct Metrics {
+    unknown_api_keys: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct Connection {
+    // sent by clients in ApiVersions v3+
+    client_software: Option<ClientSoftware>,
+}
+
+#[derive(Debug, PartialEq)]
+struct ClientSoftware {
+    name: String,
+    version: String,
+}
+
+impl Connection {
+    // Serves requests until the connection has to be closed, and returns why.
+    // Requests are read ahead while Fetch requests wait for records, the responses are written in
+    // the order of the requests as clients match them by correlation id.
+    async fn serve<S>(
+        &mut self,
+        stream: &mut S,
+        metrics: &Metrics,
+        broker: &Arc<Broker>,
+    ) -> CloseReason
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let (pending_responses, mut responses) = mpsc::channel(MAX_IN_FLIGHT_REQUESTS);
+
+        let read = async move {
             loop {
-                if let Err(e) = process(&mut stream).await {
-                    eprintln!("Error processing connection: {}", e)
+                let frame =
+                    match Request::read_frame(&mut reader, broker.socket_request_max_bytes).await {
+                        Ok(Some(frame)) => frame,
+                        Ok(None) => return CloseReason::EndOfStream,
+                        Err(e) => return e.into(),
+                    };
+                let response = match handle_frame(&frame, self, metrics, broker).await {
+                    Ok(response) => response,
+                    Err(reason) => return reason,
+                };
+                // the writer only stops on errors, which are reported instead
+                if pending_responses.send(response).await.is_err() {
+                    return CloseReason::EndOfStream;
                 }
             }
-        });
+        };
+
+        // dropping the receiver on errors stops the reader
+        let write = async move {
+            while let Some(response) = responses.recv().await {
+                if let Some(response) = response.wait().await? {
+                    writer.write_all(&response).await?;
+                }
+            }
+            Ok::<(), io::Error>(())
+        };
+
+        // the responses of the requests already read are still written when reading fails
+        let (read_reason, write_result) = tokio::join!(read, write);
+        match write_result {
+            Err(e) => e.into(),
+            Ok(()) => read_reason,
+        }
+    }
+
+    fn record(&mut self, request: &Request) {
+        if let Request::ApiVersions(api_versions_request) = request {
+            if api_versions_request.header.request_api_version >= 3 {
+                self.client_software = Some(ClientSoftware {
+                    name: api_versions_request.client_software_name.clone(),
+                    version: api_versions_request.client_software_version.clone(),
+                });
+            }
+        }
     }
 }
 
-async fn process(stream: &mut TcpStream) -> Result<(), Box<dyn Error>> {
-    let request = Request::parse_request(stream).await?;
-    let response: Vec<u8> = handle_request(&request);
-    stream.write(&response[..]).await?;
-    Ok(())
+enum PendingResponse {
+    // `None` for requests without response
+    Ready(Option<Vec<u8>>),
+    // Fetch requests waiting for records
+    Waiting(JoinHandle<Vec<u8>>),
 }
 
-fn handle_request(request: &Request) -> Vec<u8> {
-    let mut response: Vec<u8> = vec![];
+impl PendingResponse {
+    async fn wait(self) -> io::Result<Option<Vec<u8>>> {
+        match self {
+            PendingResponse::Ready(response) => Ok(response),
+            PendingResponse::Waiting(handle) => handle.await.map(Some).map_err(io::Error::other),
+        }
+    }
+}
 
-    let error_code = if !request.is_request_api_version_header_valid() {
-        ErrorCode::UnsupportedVersion
-    } else {
-        ErrorCode::Ok
+// Only Fetch requests are processed concurrently, as they can wait for records. The others are
+// processed in the order they are read, so that pipelined Produce requests are appended in order.
+// A frame with an unknown api key is skipped without a response, as no response schema could
+// answer it, and the connection stays usable: the whole frame has been read already. Unsupported
+// versions close the connection like Kafka does, except for ApiVersions whose response is
+// readable by every client.
+async fn handle_frame(
+    frame: &[u8],
+    connection: &mut Connection,
+    metrics: &Metrics,
+    broker: &Arc<Broker>,
+) -> Result<PendingResponse, CloseReason> {
+    let request = match Request::parse_frame(frame) {
+        Ok(request) => request,
+        Err(codec::DecodeError::UnknownApiKey(request_api_key)) => {
+            let unknown_api_keys = metrics.unknown_api_keys.fetch_add(1, Ordering::Relaxed) + 1;
+            eprintln!(
+                "Unknown api key {} received ({} so far)",
+                request_api_key, unknown_api_keys
+            );
+            return Ok(PendingResponse::Ready(None));
+        }
+        Err(e) => return Err(e.into()),
     };
+    if !request.is_request_api_version_header_valid() && !matches!(request, Request::ApiVersions(_))
+    {
+        let header = request.header();
+        return Err(CloseReason::UnsupportedVersion {
+            api_key: header.request_api_key,
+            version: header.request_api_version,
+        });
+    }
 
-    if error_code != ErrorCode::Ok {
-        response.put_i32(4 + 2); // correlation_id and error_code
-        response.put_i32(request.header().correlation_id);
-        response.put_i16(error_code as i16);
-        return response;
+    connection.record(&request);
+    if let Request::Fetch(_) = request {
+        let broker = broker.clone();
+        return Ok(PendingResponse::Waiting(tokio::spawn(async move {
+            handle_request(&broker, &request).await
+        })));
     }
+    // completes without waiting, as only Fetch requests can wait
+    let response_body = request_handler::process_request(broker, &request).await;
+    Ok(PendingResponse::Ready(
+        request
+            .expects_response()
+            .then(|| write_response(&request, &response_body)),
+    ))
+}
+
+async fn handle_request(broker: &Broker, request: &Request) -> Vec<u8> {
+    let response_body = request_handler::process_request(broker, request).await;
+    write_response(request, &response_body)
+}
+
+// Frames the response to `request`
+fn write_response(request: &Request, response_body: &responses::Response) -> Vec<u8> {
+    let is_version_supported = request.is_request_api_version_header_valid();
+    let header = request.header();
+    let response_header = responses::ResponseHeader {
+        correlation_id: header.correlation_id,
+        tagged_fields: model::TaggedFields::default(),
+    };
+
+    // an unsupported ApiVersions request is answered with v0, which every client can read
+    let response_version = if is_version_supported {
+        header.request_api_version
+    } else {
+        0
+    };
 
     let mut data: Vec<u8> = vec![];
-    request_handler::process_request(request).to_wire_format(&mut data);
+    response_header.write(
+        &mut data,
+        header
+            .request_api_key
+            .response_header_version(response_version),
+    );
+    response_body.to_wire_format(&mut data, response_version);
 
-    let length = 4 + data.len(); // correlation id + error code
-    response.put_i32(length as i32);
-    response.put_i32(request.header().correlation_id);
+    let mut response: Vec<u8> = vec![];
+    response.put_i32(codec::checked_length(data.len()));
     response.put(&data[..]);
     response
 }
 
-#[repr(i16)]
-#[derive(Copy, Clone, Debug, PartialEq)]
-enum ErrorCode {
-    Ok = 0,
-    UnsupportedVersion = 35,
-}
-
 #[cfg(test)]
 mod tests {
 
+    use bytes::Buf;
     use requests::ApiVersions;
+    use tokio::io::AsyncReadExt;
 
     use super::*;
 
-    use super::model::ApiKey;
+    use super::model::{ApiKey, TaggedFields};
     use super::requests::RequestHeader;
 
-    #[test]
-    fn test_handle_versions_request() {
-        let result = handle_request(&requests::Request::ApiVersions(ApiVersions {
+    fn test_broker() -> Arc<Broker> {
+        Arc::new(crate::broker::tests::test_broker())
+    }
+
+    // Produce v3 request of a batch of one record for foo-0
+    fn produce_request(correlation_id: i32, acks: i16) -> requests::Request {
+        requests::Request::Produce(requests::Produce {
             header: RequestHeader {
-                request_api_key: ApiKey::Versions,
-                request_api_version: 4,
+                request_api_key: ApiKey::Produce,
+                request_api_version: 3,
+                correlation_id,
+                client_id: None,
+                tagged_fields: TaggedFields::default(),
+            },
+            transactional_id: None,
+            acks,
+            timeout_ms: 1000,
+            topic_data: vec![requests::produce::TopicProduceData {
+                name: "foo".to_string(),
+                partition_data: vec![requests::produce::PartitionProduceData {
+                    index: 0,
+                    records: Some(crate::record::tests::record_batch(1)),
+                    tagged_fields: TaggedFields::default(),
+                }],
+                tagged_fields: TaggedFields::default(),
+            }],
+            tagged_fields: TaggedFields::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_handle_versions_request() {
+        let result = handle_request(
+            &test_broker(),
+            &requests::Request::ApiVersions(ApiVersions {
+                header: RequestHeader {
+                    request_api_key: ApiKey::Versions,
+                    request_api_version: 4,
+                    correlation_id: 311908132,
+                    client_id: None,
+                    tagged_fields: TaggedFields::default(),
+                },
+                client_software_name: String::new(),
+                client_software_version: String::new(),
+                tagged_fields: TaggedFields::default(),
+            }),
+        )
+        .await;
+
+        let mut expected = vec![0, 0, 0, 72, 18, 151, 87, 36, 0, 0, 6];
+        expected.extend_from_slice(&[0, 0, 0, 0, 0, 11, 0]); // Produce
+        expected.extend_from_slice(&[0, 1, 0, 0, 0, 16, 0]); // Fetch
+        expected.extend_from_slice(&[0, 3, 0, 0, 0, 12, 0]); // Metadata
+        expected.extend_from_slice(&[0, 18, 0, 0, 0, 4, 0]); // ApiVersions
+        expected.extend_from_slice(&[0, 75, 0, 0, 0, 0, 0]); // DescribeTopicPartitions
+        expected.extend_from_slice(&[0, 0, 0, 0]);
+        expected.extend_from_slice(&[1, 0, 23, 2, 17]);
+        expected.extend_from_slice(b"metadata.version");
+        expected.extend_from_slice(&[0, 1, 0, 20, 0]);
+        assert_eq!(result, expected);
+    }
+
+    #[tokio::test]
+    async fn test_handle_versions_request_should_answer_with_v0_if_the_api_version_is_unsupported()
+    {
+        let result = handle_request(
+            &test_broker(),
+            &requests::Request::ApiVersions(ApiVersions {
+                header: RequestHeader {
+                    request_api_key: ApiKey::Versions,
+                    request_api_version: 5,
+                    correlation_id: 311908132,
+                    client_id: None,
+                    tagged_fields: TaggedFields::default(),
+                },
+                client_software_name: String::new(),
+                client_software_version: String::new(),
+                tagged_fields: TaggedFields::default(),
+            }),
+        )
+        .await;
+
+        let mut expected = vec![0, 0, 0, 40, 18, 151, 87, 36, 0, 35, 0, 0, 0, 5];
+        expected.extend_from_slice(&[0, 0, 0, 0, 0, 11]); // Produce
+        expected.extend_from_slice(&[0, 1, 0, 0, 0, 16]); // Fetch
+        expected.extend_from_slice(&[0, 3, 0, 0, 0, 12]); // Metadata
+        expected.extend_from_slice(&[0, 18, 0, 0, 0, 4]); // ApiVersions
+        expected.extend_from_slice(&[0, 75, 0, 0, 0, 0]); // DescribeTopicPartitions
+        assert_eq!(result, expected);
+    }
+
+    #[tokio::test]
+    async fn test_handle_frame_should_close_the_connection_on_unsupported_versions() {
+        let mut frame = vec![];
+        requests::Request::Metadata(requests::Metadata {
+            header: RequestHeader {
+                request_api_key: ApiKey::Metadata,
+                request_api_version: 13,
                 correlation_id: 311908132,
+                client_id: None,
+                tagged_fields: TaggedFields::default(),
             },
-        }));
+            topics: None,
+            allow_auto_topic_creation: false,
+            include_cluster_authorized_operations: false,
+            include_topic_authorized_operations: false,
+            tagged_fields: TaggedFields::default(),
+        })
+        .to_wire_format(&mut frame, 13);
 
-        assert_eq!(
+        let result = handle_frame(
+            &frame,
+            &mut Connection::default(),
+            &Metrics::default(),
+            &test_broker(),
+        )
+        .await;
+        assert!(matches!(
             result,
-            vec![
-                0, 0, 0, 26, 18, 151, 87, 36, 0, 0, 3, 0, 1, 0, 0, 0, 16, 0, 0, 18, 0, 1, 0, 4, 0,
-                0, 0, 0, 0, 0
-            ]
-        );
+            Err(CloseReason::UnsupportedVersion {
+                api_key: ApiKey::Metadata,
+                version: 13
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_handle_frame_should_skip_unknown_api_keys_and_count_them() {
+        let metrics = Metrics::default();
+        let mut connection = Connection::default();
+        let frame = vec![0, 99, 0, 0, 18, 151, 87, 36, 0, 0];
+
+        let result = handle_frame(&frame, &mut connection, &metrics, &test_broker()).await;
+
+        assert!(matches!(result, Ok(PendingResponse::Ready(None))));
+        assert_eq!(metrics.unknown_api_keys.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_handle_frame_should_append_produce_requests_in_order() {
+        let broker = test_broker();
+        let topic = broker
+            .catalog
+            .lock()
+            .unwrap()
+            .create_topic("foo", 1, 1)
+            .unwrap()
+            .clone();
+        let mut connection = Connection::default();
+        for correlation_id in 0..2 {
+            let mut frame = vec![];
+            produce_request(correlation_id, 1).to_wire_format(&mut frame, 3);
+
+            let result = handle_frame(&frame, &mut connection, &Metrics::default(), &broker).await;
+
+            // appended before the next request is read
+            assert!(matches!(result, Ok(PendingResponse::Ready(Some(_)))));
+            let log = broker.partition_log(&topic, 0).unwrap();
+            assert_eq!(
+                log.lock().unwrap().log_end_offset(),
+                correlation_id as i64 + 1
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serve_should_stop_at_the_end_of_the_stream() {
+        let (mut client, mut server) = tokio::io::duplex(512);
+        // ApiVersions v0 request without client id
+        client
+            .write_all(&[0, 0, 0, 10, 0, 18, 0, 0, 0, 0, 0, 7, 255, 255])
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+
+        let reason = Connection::default()
+            .serve(&mut server, &Metrics::default(), &test_broker())
+            .await;
+        assert!(matches!(reason, CloseReason::EndOfStream));
+        drop(server);
+
+        let mut response = vec![];
+        client.read_to_end(&mut response).await.unwrap();
+        assert_eq!(&response[4..10], &[0, 0, 0, 7, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_serve_should_write_pipelined_responses_in_order() {
+        let (mut client, mut server) = tokio::io::duplex(4096);
+        for correlation_id in 0..20u8 {
+            // ApiVersions v0 requests
+            client
+                .write_all(&[0, 0, 0, 10, 0, 18, 0, 0, 0, 0, 0, correlation_id, 255, 255])
+                .await
+                .unwrap();
+        }
+        client.shutdown().await.unwrap();
+
+        let reason = Connection::default()
+            .serve(&mut server, &Metrics::default(), &test_broker())
+            .await;
+        assert!(matches!(reason, CloseReason::EndOfStream));
+        drop(server);
+
+        let mut responses = vec![];
+        client.read_to_end(&mut responses).await.unwrap();
+        let mut responses = responses.as_slice();
+        for correlation_id in 0..20 {
+            let length = responses.get_i32() as usize;
+            assert_eq!(responses.get_i32(), correlation_id);
+            responses.advance(length - 4);
+        }
+        assert!(responses.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_serve_should_not_answer_produce_requests_without_acks() {
+        let (mut client, mut server) = tokio::io::duplex(4096);
+        let mut request = vec![];
+        produce_request(1, 0).to_wire_format(&mut request, 3);
+        client.write_u32(request.len() as u32).await.unwrap();
+        client.write_all(&request).await.unwrap();
+        // ApiVersions v0 request, answered right away
+        client
+            .write_all(&[0, 0, 0, 10, 0, 18, 0, 0, 0, 0, 0, 2, 255, 255])
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+
+        let reason = Connection::default()
+            .serve(&mut server, &Metrics::default(), &test_broker())
+            .await;
+        assert!(matches!(reason, CloseReason::EndOfStream));
+        drop(server);
+
+        let mut responses = vec![];
+        client.read_to_end(&mut responses).await.unwrap();
+        assert_eq!(&responses[4..8], &[0, 0, 0, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_serve_should_answer_the_requests_around_an_unknown_api_key() {
+        let (mut client, mut server) = tokio::io::duplex(512);
+        // ApiVersions v0 request, then a request with an unknown api key and another ApiVersions
+        client
+            .write_all(&[0, 0, 0, 10, 0, 18, 0, 0, 0, 0, 0, 1, 255, 255])
+            .await
+            .unwrap();
+        client
+            .write_all(&[0, 0, 0, 10, 0, 99, 0, 0, 0, 0, 0, 2, 255, 255])
+            .await
+            .unwrap();
+        client
+            .write_all(&[0, 0, 0, 10, 0, 18, 0, 0, 0, 0, 0, 3, 255, 255])
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+
+        let reason = Connection::default()
+            .serve(&mut server, &Metrics::default(), &test_broker())
+            .await;
+        assert!(matches!(reason, CloseReason::EndOfStream));
+        drop(server);
+
+        let mut responses = vec![];
+        client.read_to_end(&mut responses).await.unwrap();
+        let mut responses = responses.as_slice();
+        for correlation_id in [1, 3] {
+            let length = responses.get_i32() as usize;
+            assert_eq!(responses.get_i32(), correlation_id);
+            responses.advance(length - 4);
+        }
+        assert!(responses.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_serve_should_report_truncated_frames_as_io_errors() {
+        let (mut client, mut server) = tokio::io::duplex(512);
+        client.write_all(&[0, 0, 0, 10, 0, 18]).await.unwrap();
+        client.shutdown().await.unwrap();
+
+        let reason = Connection::default()
+            .serve(&mut server, &Metrics::default(), &test_broker())
+            .await;
+        assert!(matches!(reason, CloseReason::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof));
+    }
+
+    #[tokio::test]
+    async fn test_serve_should_report_invalid_requests_as_protocol_errors() {
+        let (mut client, mut server) = tokio::io::duplex(512);
+        client.write_all(&[255, 255, 255, 255]).await.unwrap();
+        client.shutdown().await.unwrap();
+
+        let reason = Connection::default()
+            .serve(&mut server, &Metrics::default(), &test_broker())
+            .await;
+        assert!(matches!(
+            reason,
+            CloseReason::Protocol(codec::DecodeError::InvalidLength(-1))
+        ));
+
+        // a length over socket.request.max.bytes, 100 MiB by default
+        let (mut client, mut server) = tokio::io::duplex(512);
+        client.write_all(&[127, 255, 255, 255]).await.unwrap();
+
+        let reason = Connection::default()
+            .serve(&mut server, &Metrics::default(), &test_broker())
+            .await;
+        assert!(matches!(
+            reason,
+            CloseReason::Protocol(codec::DecodeError::InvalidLength(2147483647))
+        ));
+
+        let (mut client, mut server) = tokio::io::duplex(512);
+        // ApiVersions v0 request whose header is cut after the api version
+        client.write_all(&[0, 0, 0, 4, 0, 18, 0, 0]).await.unwrap();
+        client.shutdown().await.unwrap();
+
+        let reason = Connection::default()
+            .serve(&mut server, &Metrics::default(), &test_broker())
+            .await;
+        assert!(matches!(
+            reason,
+            CloseReason::Protocol(codec::DecodeError::NotEnoughBytes { .. })
+        ));
     }
 
     #[test]
-    fn test_handle_connection_should_fail_with_body_35_if_the_api_version_is_incorrect() {
-        let result = handle_request(&&requests::Request::ApiVersions(ApiVersions {
+    fn test_connection_should_record_the_client_software() {
+        let mut connection = Connection::default();
+        connection.record(&requests::Request::ApiVersions(ApiVersions {
             header: RequestHeader {
                 request_api_key: ApiKey::Versions,
-                request_api_version: -1,
-                correlation_id: 311908132,
+                request_api_version: 3,
+                correlation_id: 1,
+                client_id: None,
+                tagged_fields: TaggedFields::default(),
             },
+            client_software_name: "kafka-cli".to_string(),
+            client_software_version: "0.1".to_string(),
+            tagged_fields: TaggedFields::default(),
         }));
 
         assert_eq!(
-            result,
-            vec![
-                0,
-                0,
-                0,
-                6,
-                18,
-                151,
-                87,
-                36,
-                0,
-                ErrorCode::UnsupportedVersion as u8
-            ]
+            connection.client_software,
+            Some(ClientSoftware {
+                name: "kafka-cli".to_string(),
+                version: "0.1".to_string(),
+            })
         );
     }
+
+    #[tokio::test]
+    async fn test_handle_fetch_request_should_write_a_flexible_response_header() {
+        let result = handle_request(
+            &test_broker(),
+            &requests::Request::Fetch(requests::Fetch {
+                header: RequestHeader {
+                    request_api_key: ApiKey::Fetch,
+                    request_api_version: 16,
+                    correlation_id: 7,
+                    client_id: None,
+                    tagged_fields: TaggedFields::default(),
+                },
+                replica_id: -1,
+                max_wait_ms: 500,
+                min_bytes: 1,
+                max_bytes: 1024,
+                isolation_level: 0,
+                session_id: 0,
+                session_epoch: 0,
+                topics: vec![],
+                forgotten_topics_data: vec![],
+                rack_id: String::new(),
+                cluster_id: None,
+                replica_state: None,
+                tagged_fields: TaggedFields::default(),
+            }),
+        )
+        .await;
+
+        // correlation id followed by the empty tagged fields of the response header v1
+        assert_eq!(&result[4..9], &[0, 0, 0, 7, 0]);
+        assert_eq!(result.len(), 4 + 5 + 12);
+    }
 }
@@ -0,0 +1,618 @@
+// Primitive types of the Kafka protocol
+// https://kafka.apache.org/protocol.html#protocol_types
+use bytes::{Buf, BufMut};
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum DecodeError {
+    #[error("not enough bytes: needed {needed}, {remaining} remaining")]
+    NotEnoughBytes { needed: usize, remaining: usize },
+    #[error("varint is longer than {0} bytes")]
+    VarintTooLong(usize),
+    #[error("invalid length {0}")]
+    InvalidLength(i64),
+    #[error("unexpected null value")]
+    UnexpectedNull,
+    #[error("string is not valid utf-8")]
+    InvalidString,
+    #[error("tagged field {0} is duplicated or out of order")]
+    TaggedFieldOutOfOrder(u32),
+    #[error("api key {0} not recognized")]
+    UnknownApiKey(i16),
+}
+
+pub type Result<T> = std::result::Result<T, DecodeError>;
+
+// A length that does not fit in its prefix would wrap, and the frame would be silently corrupt
+pub fn checked_length<T: TryFrom<usize>>(length: usize) -> T {
+    T::try_from(length)
+        .unwrap_or_else(|_| panic!("length {} does not fit in its length prefix", length))
+}
+
+// The whole set of protocol types is exposed even if the broker does not use all of them yet
+#[allow(dead_code)]
+pub trait Decoder: Buf {
+    fn ensure_remaining(&self, needed: usize) -> Result<()> {
+        if self.remaining() < needed {
+            return Err(DecodeError::NotEnoughBytes {
+                needed,
+                remaining: self.remaining(),
+            });
+        }
+        Ok(())
+    }
+
+    fn read_int8(&mut self) -> Result<i8> {
+        self.ensure_remaining(1)?;
+        Ok(self.get_i8())
+    }
+
+    fn read_int16(&mut self) -> Result<i16> {
+        self.ensure_remaining(2)?;
+        Ok(self.get_i16())
+    }
+
+    fn read_int32(&mut self) -> Result<i32> {
+        self.ensure_remaining(4)?;
+        Ok(self.get_i32())
+    }
+
+    fn read_int64(&mut self) -> Result<i64> {
+        self.ensure_remaining(8)?;
+        Ok(self.get_i64())
+    }
+
+    fn read_uint32(&mut self) -> Result<u32> {
+        self.ensure_remaining(4)?;
+        Ok(self.get_u32())
+    }
+
+    fn read_boolean(&mut self) -> Result<bool> {
+        Ok(self.read_int8()? != 0)
+    }
+
+    fn read_uuid(&mut self) -> Result<u128> {
+        self.ensure_remaining(16)?;
+        Ok(self.get_u128())
+    }
+
+    fn read_unsigned_varint(&mut self) -> Result<u32> {
+        let mut value: u32 = 0;
+        for i in 0..5 {
+            let byte = self.read_int8()? as u8;
+            value |= ((byte & 0x7f) as u32) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::VarintTooLong(5))
+    }
+
+    fn read_unsigned_varlong(&mut self) -> Result<u64> {
+        let mut value: u64 = 0;
+        for i in 0..10 {
+            let byte = self.read_int8()? as u8;
+            value |= ((byte & 0x7f) as u64) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::VarintTooLong(10))
+    }
+
+    // zig-zag encoded, as in protocol buffers
+    fn read_varint(&mut self) -> Result<i32> {
+        let value = self.read_unsigned_varint()?;
+        Ok(((value >> 1) as i32) ^ -((value & 1) as i32))
+    }
+
+    fn read_varlong(&mut self) -> Result<i64> {
+        let value = self.read_unsigned_varlong()?;
+        Ok(((value >> 1) as i64) ^ -((value & 1) as i64))
+    }
+
+    fn read_string(&mut self) -> Result<String> {
+        self.read_nullable_string()?
+            .ok_or(DecodeError::UnexpectedNull)
+    }
+
+    fn read_nullable_string(&mut self) -> Result<Option<String>> {
+        let length = self.read_int16()?;
+        self.read_string_of_length(length as i64)
+    }
+
+    fn read_compact_string(&mut self) -> Result<String> {
+        self.read_compact_nullable_string()?
+            .ok_or(DecodeError::UnexpectedNull)
+    }
+
+    fn read_compact_nullable_string(&mut self) -> Result<Option<String>> {
+        let length = self.read_unsigned_varint()? as i64 - 1; // 0 is reserved for null
+        self.read_string_of_length(length)
+    }
+
+    fn read_string_of_length(&mut self, length: i64) -> Result<Option<String>> {
+        match self.read_bytes_of_length(length)? {
+            None => Ok(None),
+            Some(bytes) => String::from_utf8(bytes)
+                .map(Some)
+                .map_err(|_| DecodeError::InvalidString),
+        }
+    }
+
+    fn read_bytes(&mut self) -> Result<Vec<u8>> {
+        self.read_nullable_bytes()?
+            .ok_or(DecodeError::UnexpectedNull)
+    }
+
+    fn read_nullable_bytes(&mut self) -> Result<Option<Vec<u8>>> {
+        let length = self.read_int32()?;
+        self.read_bytes_of_length(length as i64)
+    }
+
+    fn read_compact_bytes(&mut self) -> Result<Vec<u8>> {
+        self.read_compact_nullable_bytes()?
+            .ok_or(DecodeError::UnexpectedNull)
+    }
+
+    fn read_compact_nullable_bytes(&mut self) -> Result<Option<Vec<u8>>> {
+        let length = self.read_unsigned_varint()? as i64 - 1; // 0 is reserved for null
+        self.read_bytes_of_length(length)
+    }
+
+    fn read_bytes_of_length(&mut self, length: i64) -> Result<Option<Vec<u8>>> {
+        if length == -1 {
+            return Ok(None);
+        }
+        if length < 0 {
+            return Err(DecodeError::InvalidLength(length));
+        }
+        let length = length as usize;
+        self.ensure_remaining(length)?;
+        let mut bytes = vec![0; length];
+        self.copy_to_slice(&mut bytes);
+        Ok(Some(bytes))
+    }
+
+    // A null array is read as an empty one, clients are not always strict about nullability
+    fn read_array<T>(&mut self, read_item: impl FnMut(&mut Self) -> Result<T>) -> Result<Vec<T>> {
+        Ok(self.read_nullable_array(read_item)?.unwrap_or_default())
+    }
+
+    fn read_nullable_array<T>(
+        &mut self,
+        read_item: impl FnMut(&mut Self) -> Result<T>,
+    ) -> Result<Option<Vec<T>>> {
+        let length = self.read_int32()?;
+        self.read_array_of_length(length as i64, read_item)
+    }
+
+    fn read_compact_array<T>(
+        &mut self,
+        read_item: impl FnMut(&mut Self) -> Result<T>,
+    ) -> Result<Vec<T>> {
+        Ok(self
+            .read_compact_nullable_array(read_item)?
+            .unwrap_or_default())
+    }
+
+    fn read_compact_nullable_array<T>(
+        &mut self,
+        read_item: impl FnMut(&mut Self) -> Result<T>,
+    ) -> Result<Option<Vec<T>>> {
+        let length = self.read_unsigned_varint()? as i64 - 1; // 0 is reserved for null
+        self.read_array_of_length(length, read_item)
+    }
+
+    // Flexible versions use the compact encoding of strings, bytes and arrays
+    fn read_flexible_string(&mut self, flexible: bool) -> Result<String> {
+        if flexible {
+            self.read_compact_string()
+        } else {
+            self.read_string()
+        }
+    }
+
+    fn read_flexible_nullable_string(&mut self, flexible: bool) -> Result<Option<String>> {
+        if flexible {
+            self.read_compact_nullable_string()
+        } else {
+            self.read_nullable_string()
+        }
+    }
+
+    fn read_flexible_nullable_bytes(&mut self, flexible: bool) -> Result<Option<Vec<u8>>> {
+        if flexible {
+            self.read_compact_nullable_bytes()
+        } else {
+            self.read_nullable_bytes()
+        }
+    }
+
+    fn read_flexible_array<T>(
+        &mut self,
+        flexible: bool,
+        read_item: impl FnMut(&mut Self) -> Result<T>,
+    ) -> Result<Vec<T>> {
+        if flexible {
+            self.read_compact_array(read_item)
+        } else {
+            self.read_array(read_item)
+        }
+    }
+
+    fn read_flexible_nullable_array<T>(
+        &mut self,
+        flexible: bool,
+        read_item: impl FnMut(&mut Self) -> Result<T>,
+    ) -> Result<Option<Vec<T>>> {
+        if flexible {
+            self.read_compact_nullable_array(read_item)
+        } else {
+            self.read_nullable_array(read_item)
+        }
+    }
+
+    fn read_array_of_length<T>(
+        &mut self,
+        length: i64,
+        mut read_item: impl FnMut(&mut Self) -> Result<T>,
+    ) -> Result<Option<Vec<T>>> {
+        if length == -1 {
+            return Ok(None);
+        }
+        if length < 0 {
+            return Err(DecodeError::InvalidLength(length));
+        }
+        // every item takes at least one byte, do not trust the length for the allocation
+        let mut items = Vec::with_capacity((length as usize).min(self.remaining()));
+        for _ in 0..length {
+            items.push(read_item(self)?);
+        }
+        Ok(Some(items))
+    }
+}
+
+impl<B: Buf + ?Sized> Decoder for B {}
+
+#[allow(dead_code)]
+pub trait Encoder: BufMut {
+    fn write_int8(&mut self, value: i8) {
+        self.put_i8(value);
+    }
+
+    fn write_int16(&mut self, value: i16) {
+        self.put_i16(value);
+    }
+
+    fn write_int32(&mut self, value: i32) {
+        self.put_i32(value);
+    }
+
+    fn write_int64(&mut self, value: i64) {
+        self.put_i64(value);
+    }
+
+    fn write_uint32(&mut self, value: u32) {
+        self.put_u32(value);
+    }
+
+    fn write_boolean(&mut self, value: bool) {
+        self.put_i8(value as i8);
+    }
+
+    fn write_uuid(&mut self, value: u128) {
+        self.put_u128(value);
+    }
+
+    fn write_unsigned_varint(&mut self, mut value: u32) {
+        while value >= 0x80 {
+            self.put_u8((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        self.put_u8(value as u8);
+    }
+
+    fn write_unsigned_varlong(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.put_u8((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        self.put_u8(value as u8);
+    }
+
+    fn write_varint(&mut self, value: i32) {
+        self.write_unsigned_varint(((value << 1) ^ (value >> 31)) as u32);
+    }
+
+    fn write_varlong(&mut self, value: i64) {
+        self.write_unsigned_varlong(((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    fn write_string(&mut self, value: &str) {
+        self.write_nullable_string(Some(value));
+    }
+
+    fn write_nullable_string(&mut self, value: Option<&str>) {
+        match value {
+            None => self.put_i16(-1),
+            Some(value) => {
+                self.put_i16(checked_length(value.len()));
+                self.put_slice(value.as_bytes());
+            }
+        }
+    }
+
+    fn write_compact_string(&mut self, value: &str) {
+        self.write_compact_nullable_string(Some(value));
+    }
+
+    fn write_compact_nullable_string(&mut self, value: Option<&str>) {
+        self.write_compact_nullable_bytes(value.map(str::as_bytes));
+    }
+
+    fn write_bytes(&mut self, value: &[u8]) {
+        self.write_nullable_bytes(Some(value));
+    }
+
+    fn write_nullable_bytes(&mut self, value: Option<&[u8]>) {
+        match value {
+            None => self.put_i32(-1),
+            Some(value) => {
+                self.put_i32(checked_length(value.len()));
+                self.put_slice(value);
+            }
+        }
+    }
+
+    fn write_compact_bytes(&mut self, value: &[u8]) {
+        self.write_compact_nullable_bytes(Some(value));
+    }
+
+    fn write_compact_nullable_bytes(&mut self, value: Option<&[u8]>) {
+        match value {
+            None => self.write_unsigned_varint(0),
+            Some(value) => {
+                self.write_unsigned_varint(checked_length(value.len() + 1));
+                self.put_slice(value);
+            }
+        }
+    }
+
+    fn write_array<T>(&mut self, items: &[T], write_item: impl FnMut(&mut Self, &T)) {
+        self.write_nullable_array(Some(items), write_item);
+    }
+
+    fn write_nullable_array<T>(
+        &mut self,
+        items: Option<&[T]>,
+        mut write_item: impl FnMut(&mut Self, &T),
+    ) {
+        match items {
+            None => self.put_i32(-1),
+            Some(items) => {
+                self.put_i32(checked_length(items.len()));
+                for item in items {
+                    write_item(self, item);
+                }
+            }
+        }
+    }
+
+    fn write_compact_array<T>(&mut self, items: &[T], write_item: impl FnMut(&mut Self, &T)) {
+        self.write_compact_nullable_array(Some(items), write_item);
+    }
+
+    fn write_compact_nullable_array<T>(
+        &mut self,
+        items: Option<&[T]>,
+        mut write_item: impl FnMut(&mut Self, &T),
+    ) {
+        match items {
+            None => self.write_unsigned_varint(0),
+            Some(items) => {
+                self.write_unsigned_varint(checked_length(items.len() + 1));
+                for item in items {
+                    write_item(self, item);
+                }
+            }
+        }
+    }
+
+    fn write_flexible_string(&mut self, flexible: bool, value: &str) {
+        self.write_flexible_nullable_string(flexible, Some(value));
+    }
+
+    fn write_flexible_nullable_string(&mut self, flexible: bool, value: Option<&str>) {
+        if flexible {
+            self.write_compact_nullable_string(value)
+        } else {
+            self.write_nullable_string(value)
+        }
+    }
+
+    fn write_flexible_nullable_bytes(&mut self, flexible: bool, value: Option<&[u8]>) {
+        if flexible {
+            self.write_compact_nullable_bytes(value)
+        } else {
+            self.write_nullable_bytes(value)
+        }
+    }
+
+    fn write_flexible_array<T>(
+        &mut self,
+        flexible: bool,
+        items: &[T],
+        write_item: impl FnMut(&mut Self, &T),
+    ) {
+        self.write_flexible_nullable_array(flexible, Some(items), write_item);
+    }
+
+    fn write_flexible_nullable_array<T>(
+        &mut self,
+        flexible: bool,
+        items: Option<&[T]>,
+        write_item: impl FnMut(&mut Self, &T),
+    ) {
+        if flexible {
+            self.write_compact_nullable_array(items, write_item)
+        } else {
+            self.write_nullable_array(items, write_item)
+        }
+    }
+}
+
+impl<B: BufMut + ?Sized> Encoder for B {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unsigned_varint_round_trip() {
+        for value in [0, 1, 127, 128, 300, 16383, 16384, u32::MAX] {
+            let mut buffer = vec![];
+            buffer.write_unsigned_varint(value);
+            assert_eq!(buffer.as_slice().read_unsigned_varint(), Ok(value));
+        }
+    }
+
+    #[test]
+    fn test_unsigned_varint_encoding() {
+        let mut buffer = vec![];
+        buffer.write_unsigned_varint(300);
+        assert_eq!(buffer, vec![0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_varint_zig_zag_encoding() {
+        let mut buffer = vec![];
+        buffer.write_varint(-1);
+        buffer.write_varint(1);
+        buffer.write_varint(-2);
+        assert_eq!(buffer, vec![1, 2, 3]);
+
+        let mut buffer = buffer.as_slice();
+        assert_eq!(buffer.read_varint(), Ok(-1));
+        assert_eq!(buffer.read_varint(), Ok(1));
+        assert_eq!(buffer.read_varint(), Ok(-2));
+    }
+
+    #[test]
+    fn test_varlong_round_trip() {
+        for value in [0, -1, i64::MIN, i64::MAX, 1 << 40] {
+            let mut buffer = vec![];
+            buffer.write_varlong(value);
+            assert_eq!(buffer.as_slice().read_varlong(), Ok(value));
+        }
+    }
+
+    #[test]
+    fn test_unsigned_varint_too_long() {
+        let buffer = vec![0xff; 6];
+        assert_eq!(
+            buffer.as_slice().read_unsigned_varint(),
+            Err(DecodeError::VarintTooLong(5))
+        );
+    }
+
+    #[test]
+    fn test_strings_round_trip() {
+        let mut buffer = vec![];
+        buffer.write_string("kafka");
+        buffer.write_nullable_string(None);
+        buffer.write_compact_string("kafka");
+        buffer.write_compact_nullable_string(None);
+
+        let mut buffer = buffer.as_slice();
+        assert_eq!(buffer.read_string(), Ok("kafka".to_string()));
+        assert_eq!(buffer.read_nullable_string(), Ok(None));
+        assert_eq!(buffer.read_compact_string(), Ok("kafka".to_string()));
+        assert_eq!(buffer.read_compact_nullable_string(), Ok(None));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_read_string_should_fail_on_null() {
+        let buffer = vec![0];
+        assert_eq!(
+            buffer.as_slice().read_compact_string(),
+            Err(DecodeError::UnexpectedNull)
+        );
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let mut buffer = vec![];
+        buffer.write_bytes(&[1, 2, 3]);
+        buffer.write_compact_bytes(&[4]);
+        buffer.write_compact_nullable_bytes(None);
+
+        let mut buffer = buffer.as_slice();
+        assert_eq!(buffer.read_bytes(), Ok(vec![1, 2, 3]));
+        assert_eq!(buffer.read_compact_bytes(), Ok(vec![4]));
+        assert_eq!(buffer.read_compact_nullable_bytes(), Ok(None));
+    }
+
+    #[test]
+    fn test_compact_array_longer_than_a_single_varint_byte() {
+        let items: Vec<i32> = (0..200).collect();
+        let mut buffer = vec![];
+        buffer.write_compact_array(&items, |buffer, item| buffer.put_i32(*item));
+        assert_eq!(&buffer[..2], &[0xc9, 0x01]);
+
+        let decoded = buffer
+            .as_slice()
+            .read_compact_array(|buffer| buffer.read_int32());
+        assert_eq!(decoded, Ok(items));
+    }
+
+    #[test]
+    fn test_arrays_round_trip() {
+        let mut buffer = vec![];
+        buffer.write_array(&[1i16, 2], |buffer, item| buffer.put_i16(*item));
+        buffer.write_nullable_array::<i16>(None, |buffer, item| buffer.put_i16(*item));
+        buffer.write_compact_nullable_array::<i16>(None, |buffer, item| buffer.put_i16(*item));
+
+        let mut buffer = buffer.as_slice();
+        assert_eq!(
+            buffer.read_array(|buffer| buffer.read_int16()),
+            Ok(vec![1, 2])
+        );
+        assert_eq!(
+            buffer.read_nullable_array(|buffer| buffer.read_int16()),
+            Ok(None)
+        );
+        assert_eq!(
+            buffer.read_compact_array(|buffer| buffer.read_int16()),
+            Ok(vec![])
+        );
+    }
+
+    #[test]
+    fn test_read_should_fail_when_buffer_is_too_short() {
+        let buffer = vec![0, 0, 0];
+        assert_eq!(
+            buffer.as_slice().read_int32(),
+            Err(DecodeError::NotEnoughBytes {
+                needed: 4,
+                remaining: 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_checked_length() {
+        assert_eq!(checked_length::<i16>(32767), 32767);
+        assert_eq!(checked_length::<u32>(u32::MAX as usize), u32::MAX);
+
+        let mut buffer = vec![];
+        buffer.write_string(&"a".repeat(32767));
+        assert_eq!(&buffer[..2], &[127, 255]);
+    }
+
+    #[test]
+    #[should_panic(expected = "length 32768 does not fit")]
+    fn test_write_string_should_not_wrap_the_length() {
+        vec![].write_string(&"a".repeat(32768));
+    }
+}
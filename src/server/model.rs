@@ -1,27 +1,97 @@
-use bytes::BufMut;
-use std::error::Error;
+use super::codec;
+use super::codec::{Decoder, Encoder};
+use bytes::{Buf, BufMut, Bytes};
+use std::collections::BTreeMap;
 
+// `version` is always the api version of the request or response the value belongs to
 pub trait WireSerialization {
-    fn to_wire_format(&self, buffer: &mut Vec<u8>) -> ();
+    fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16);
+}
+
+pub trait WireDeserialization: Sized {
+    fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self>;
+}
+
+#[repr(i16)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ErrorCode {
+    UnknownServerError = -1,
+    Ok = 0,
+    OffsetOutOfRange = 1,
+    CorruptMessage = 2,
+    UnknownTopicOrPartition = 3,
+    InvalidTopicException = 17,
+    InvalidRequiredAcks = 21,
+    UnsupportedVersion = 35,
+    InvalidRequest = 42,
+    UnsupportedForMessageFormat = 43,
+    KafkaStorageError = 56,
+    FetchSessionIdNotFound = 70,
+    InvalidFetchSessionEpoch = 71,
+    UnknownTopicId = 100,
 }
 
 #[repr(i16)]
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum ApiKey {
+    Produce = 0,
     Fetch = 1,
+    Metadata = 3,
     Versions = 18,
+    DescribeTopicPartitions = 75,
 }
 
 impl ApiKey {
-    pub fn parse(value: i16) -> Result<ApiKey, Box<dyn Error>> {
+    pub fn parse(value: i16) -> codec::Result<ApiKey> {
         match value {
+            0 => Ok(ApiKey::Produce),
             1 => Ok(ApiKey::Fetch),
+            3 => Ok(ApiKey::Metadata),
             18 => Ok(ApiKey::Versions),
-            _ => Err(Box::from("api key not recognized")),
+            75 => Ok(ApiKey::DescribeTopicPartitions),
+            _ => Err(codec::DecodeError::UnknownApiKey(value)),
+        }
+    }
+
+    // First version using compact types and tagged fields
+    fn first_flexible_version(&self) -> i16 {
+        match self {
+            ApiKey::Produce => 9,
+            ApiKey::Fetch => 12,
+            ApiKey::Metadata => 9,
+            ApiKey::Versions => 3,
+            ApiKey::DescribeTopicPartitions => 0,
+        }
+    }
+
+    pub fn is_flexible(&self, api_version: i16) -> bool {
+        api_version >= self.first_flexible_version()
+    }
+
+    // https://kafka.apache.org/protocol.html#protocol_messages
+    pub fn request_header_version(&self, api_version: i16) -> HeaderVersion {
+        if self.is_flexible(api_version) {
+            HeaderVersion(2)
+        } else {
+            HeaderVersion(1)
+        }
+    }
+
+    pub fn response_header_version(&self, api_version: i16) -> HeaderVersion {
+        match self {
+            // clients read the ApiVersions response before knowing which versions the broker
+            // supports, its header never has tagged fields
+            ApiKey::Versions => HeaderVersion(0),
+            _ if self.is_flexible(api_version) => HeaderVersion(1),
+            _ => HeaderVersion(0),
         }
     }
 }
 
+// Version of a request or response header, derived from the api version of its message
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub struct HeaderVersion(pub i16);
+
 // TODO: should not be public
 pub struct ApiKeyVersions {
     pub api_key: ApiKey,
@@ -37,16 +107,29 @@ impl ApiKeyVersions {
 
 #[derive(Debug, PartialEq)]
 pub enum ApiKeyVariant {
+    Produce,
     Fetch,
+    Metadata,
     Versions,
+    DescribeTopicPartitions,
 }
 
 impl ApiKeyVariant {
     pub fn versions(&self) -> ApiKeyVersions {
         match self {
+            ApiKeyVariant::Produce => ApiKeyVersions {
+                api_key: ApiKey::Produce,
+                min_version: 0,
+                max_version: 11,
+            },
+            ApiKeyVariant::Metadata => ApiKeyVersions {
+                api_key: ApiKey::Metadata,
+                min_version: 0,
+                max_version: 12,
+            },
             ApiKeyVariant::Versions => ApiKeyVersions {
                 api_key: ApiKey::Versions,
-                min_version: 1,
+                min_version: 0,
                 max_version: 4,
             },
             ApiKeyVariant::Fetch => ApiKeyVersions {
@@ -54,23 +137,141 @@ impl ApiKeyVariant {
                 min_version: 0,
                 max_version: 16,
             },
+            ApiKeyVariant::DescribeTopicPartitions => ApiKeyVersions {
+                api_key: ApiKey::DescribeTopicPartitions,
+                min_version: 0,
+                max_version: 0,
+            },
         }
     }
 }
 
-impl WireSerialization for ApiKeyVariant {
-    fn to_wire_format(&self, buffer: &mut Vec<u8>) -> () {
-        let version_info = self.versions();
-        buffer.put_i16(version_info.api_key as i16);
-        buffer.put_i16(version_info.min_version);
-        buffer.put_i16(version_info.max_version);
-        buffer.put_i8(0); // no tagged fields, null marker
+pub type Uuid = u128;
+
+pub struct Feature {
+    pub name: &'static str,
+    pub min_version: i16,
+    pub max_version: i16,
+}
+
+// Advertised to clients in ApiVersions v3+
+pub const SUPPORTED_FEATURES: [Feature; 1] = [Feature {
+    name: "metadata.version",
+    min_version: 1,
+    max_version: 20,
+}];
+
+// https://cwiki.apache.org/confluence/display/KAFKA/KIP-482%3A+The+Kafka+Protocol+should+Support+Optional+Tagged+Fields
+// Holds the raw value of every tag, structs take the tags they know about and keep the others
+// untouched so that they are written back as is.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TaggedFields {
+    fields: BTreeMap<u32, Vec<u8>>,
+}
+
+impl TaggedFields {
+    pub fn take<T>(
+        &mut self,
+        tag: u32,
+        read: impl FnOnce(&mut Bytes) -> codec::Result<T>,
+    ) -> codec::Result<Option<T>> {
+        match self.fields.remove(&tag) {
+            None => Ok(None),
+            Some(value) => read(&mut Bytes::from(value)).map(Some),
+        }
+    }
+
+    pub fn insert(&mut self, tag: u32, write: impl FnOnce(&mut Vec<u8>)) {
+        let mut value = vec![];
+        write(&mut value);
+        self.fields.insert(tag, value);
+    }
+
+    // Tagged fields only exist in flexible versions
+    pub fn read_flexible<B: Buf>(buffer: &mut B, flexible: bool) -> codec::Result<Self> {
+        if flexible {
+            TaggedFields::read(buffer)
+        } else {
+            Ok(TaggedFields::default())
+        }
+    }
+
+    pub fn write_flexible(&self, buffer: &mut Vec<u8>, flexible: bool) {
+        if flexible {
+            self.write(buffer);
+        }
+    }
+
+    // The encoding does not depend on the version of the message holding the fields
+    pub fn write(&self, buffer: &mut Vec<u8>) {
+        buffer.write_unsigned_varint(codec::checked_length(self.fields.len()));
+        for (tag, value) in &self.fields {
+            buffer.write_unsigned_varint(*tag);
+            buffer.write_unsigned_varint(codec::checked_length(value.len()));
+            buffer.put_slice(value);
+        }
+    }
+
+    pub fn read<B: Buf>(buffer: &mut B) -> codec::Result<Self> {
+        let mut fields = BTreeMap::new();
+        let mut previous_tag = None;
+        for _ in 0..buffer.read_unsigned_varint()? {
+            let tag = buffer.read_unsigned_varint()?;
+            if previous_tag.is_some_and(|previous_tag| tag <= previous_tag) {
+                return Err(codec::DecodeError::TaggedFieldOutOfOrder(tag));
+            }
+            previous_tag = Some(tag);
+            let size = buffer.read_unsigned_varint()?;
+            let value = buffer
+                .read_bytes_of_length(size as i64)?
+                .unwrap_or_default();
+            fields.insert(tag, value);
+        }
+        Ok(TaggedFields { fields })
     }
 }
 
-type Uuid = u128;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-#[derive(Debug, PartialEq)]
-pub struct Topic {
-    pub id: Uuid,
+    #[test]
+    fn test_header_versions() {
+        assert_eq!(ApiKey::Fetch.request_header_version(11), HeaderVersion(1));
+        assert_eq!(ApiKey::Fetch.request_header_version(12), HeaderVersion(2));
+        assert_eq!(ApiKey::Fetch.response_header_version(11), HeaderVersion(0));
+        assert_eq!(ApiKey::Fetch.response_header_version(16), HeaderVersion(1));
+        assert_eq!(ApiKey::Versions.request_header_version(3), HeaderVersion(2));
+        assert_eq!(
+            ApiKey::Versions.response_header_version(4),
+            HeaderVersion(0)
+        );
+    }
+
+    #[test]
+    fn test_tagged_fields_should_skip_unknown_tags() {
+        // two tags: 0 with a compact string "id", 5 with 3 opaque bytes, followed by an int16
+        let buffer = vec![2, 0, 3, 3, b'i', b'd', 5, 3, 1, 2, 3, 0, 42];
+        let mut buffer = buffer.as_slice();
+
+        let mut tagged_fields = TaggedFields::read(&mut buffer).unwrap();
+        assert_eq!(buffer.read_int16(), Ok(42));
+
+        let known = tagged_fields.take(0, |buffer| buffer.read_compact_string());
+        assert_eq!(known, Ok(Some("id".to_string())));
+        assert_eq!(tagged_fields.take(1, |buffer| buffer.read_int8()), Ok(None));
+
+        let mut written = vec![];
+        tagged_fields.write(&mut written);
+        assert_eq!(written, vec![1, 5, 3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_tagged_fields_should_fail_on_out_of_order_tags() {
+        let buffer = vec![2, 1, 0, 0, 0];
+        assert_eq!(
+            TaggedFields::read(&mut buffer.as_slice()),
+            Err(codec::DecodeError::TaggedFieldOutOfOrder(0))
+        );
+    }
 }
@@ -1,6 +1,9 @@
+use super::codec;
+use super::codec::{Decoder, Encoder};
 use super::model;
-use bytes::Buf;
-use std::error::Error;
+use super::model::{WireDeserialization, WireSerialization};
+use bytes::{Buf, BufMut};
+use std::io;
 use std::io::Cursor;
 use tokio::io::{AsyncRead, AsyncReadExt};
 
@@ -8,18 +11,67 @@ use tokio::io::{AsyncRead, AsyncReadExt};
 pub enum Request {
     ApiVersions(ApiVersions),
     Fetch(Fetch),
+    Produce(Produce),
+    Metadata(Metadata),
+    DescribeTopicPartitions(DescribeTopicPartitions),
 }
 
 #[derive(Debug, PartialEq)]
 pub struct ApiVersions {
     pub header: RequestHeader,
+    pub client_software_name: String,
+    pub client_software_version: String,
+    pub tagged_fields: model::TaggedFields,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Fetch {
     pub header: RequestHeader,
+    pub replica_id: i32,
+    pub max_wait_ms: i32,
+    pub min_bytes: i32,
+    pub max_bytes: i32,
+    pub isolation_level: i8,
     pub session_id: i32,
-    pub topics: Vec<model::Topic>,
+    pub session_epoch: i32,
+    pub topics: Vec<fetch::FetchTopic>,
+    pub forgotten_topics_data: Vec<fetch::ForgottenTopic>,
+    pub rack_id: String,
+    pub cluster_id: Option<String>,
+    pub replica_state: Option<fetch::ReplicaState>,
+    pub tagged_fields: model::TaggedFields,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Produce {
+    pub header: RequestHeader,
+    pub transactional_id: Option<String>,
+    pub acks: i16,
+    pub timeout_ms: i32,
+    pub topic_data: Vec<produce::TopicProduceData>,
+    pub tagged_fields: model::TaggedFields,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Metadata {
+    pub header: RequestHeader,
+    // `None` asks for every topic
+    pub topics: Option<Vec<metadata::MetadataRequestTopic>>,
+    pub allow_auto_topic_creation: bool,
+    pub include_cluster_authorized_operations: bool,
+    pub include_topic_authorized_operations: bool,
+    pub tagged_fields: model::TaggedFields,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DescribeTopicPartitions {
+    pub header: RequestHeader,
+    // an empty array asks for every topic
+    pub topics: Vec<describe_topic_partitions::TopicRequest>,
+    pub response_partition_limit: i32,
+    // where the previous response stopped
+    pub cursor: Option<describe_topic_partitions::Cursor>,
+    pub tagged_fields: model::TaggedFields,
 }
 
 #[derive(Debug, PartialEq)]
@@ -27,6 +79,8 @@ pub struct RequestHeader {
     pub request_api_key: model::ApiKey,
     pub request_api_version: i16,
     pub correlation_id: i32,
+    pub client_id: Option<String>,
+    pub tagged_fields: model::TaggedFields,
 }
 
 pub trait HasRequestHeader {
@@ -38,91 +92,131 @@ impl HasRequestHeader for Request {
         match self {
             Request::ApiVersions(api_versions_request) => &api_versions_request.header,
             Request::Fetch(fetch_request) => &fetch_request.header,
+            Request::Produce(produce_request) => &produce_request.header,
+            Request::Metadata(metadata_request) => &metadata_request.header,
+            Request::DescribeTopicPartitions(describe_request) => &describe_request.header,
         }
     }
 }
 
-impl Request {
-    pub async fn parse_request<R>(stream: &mut R) -> Result<Request, Box<dyn Error>>
-    where
-        R: AsyncRead + Unpin,
-    {
-        let mut len = [0; 4];
-        stream.read_exact(&mut len).await?;
-        let len = i32::from_be_bytes(len) as usize;
-
-        let mut request = vec![0; len];
-        stream.read_exact(&mut request).await?;
-
-        let mut request = Cursor::new(request);
+// Headers are versioned apart from their message, see `model::ApiKey::request_header_version`
+impl RequestHeader {
+    pub fn write(&self, buffer: &mut Vec<u8>, version: model::HeaderVersion) {
+        buffer.put_i16(self.request_api_key as i16);
+        buffer.put_i16(self.request_api_version);
+        buffer.put_i32(self.correlation_id);
+        if version >= model::HeaderVersion(1) {
+            buffer.write_nullable_string(self.client_id.as_deref());
+        }
+        if version >= model::HeaderVersion(2) {
+            self.tagged_fields.write(buffer);
+        }
+    }
 
-        let request_header = RequestHeader {
-            request_api_key: model::ApiKey::parse(request.get_i16())?,
-            request_api_version: request.get_i16(),
-            correlation_id: request.get_i32(),
+    pub fn read<B: Buf>(buffer: &mut B, version: model::HeaderVersion) -> codec::Result<Self> {
+        let request_api_key = model::ApiKey::parse(buffer.read_int16()?)?;
+        let request_api_version = buffer.read_int16()?;
+        let correlation_id = buffer.read_int32()?;
+        let client_id = if version >= model::HeaderVersion(1) {
+            buffer.read_nullable_string()?
+        } else {
+            None
+        };
+        let tagged_fields = if version >= model::HeaderVersion(2) {
+            model::TaggedFields::read(buffer)?
+        } else {
+            model::TaggedFields::default()
         };
+        Ok(RequestHeader {
+            request_api_key,
+            request_api_version,
+            correlation_id,
+            client_id,
+            tagged_fields,
+        })
+    }
+}
 
-        dbg!(&request_header);
+impl WireSerialization for Request {
+    fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+        match self {
+            Request::ApiVersions(api_versions_request) => {
+                api_versions_request.to_wire_format(buffer, version)
+            }
+            Request::Fetch(fetch_request) => fetch_request.to_wire_format(buffer, version),
+            Request::Produce(produce_request) => produce_request.to_wire_format(buffer, version),
+            Request::Metadata(metadata_request) => metadata_request.to_wire_format(buffer, version),
+            Request::DescribeTopicPartitions(describe_request) => {
+                describe_request.to_wire_format(buffer, version)
+            }
+        }
+    }
+}
 
-        Ok(match request_header.request_api_key {
+// `version` is the api version of the request, the header version is derived from it
+impl WireDeserialization for Request {
+    fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+        // the api key is the first field of the header, peek it to know which request to parse
+        let request_api_key = model::ApiKey::parse(buffer.chunk().read_int16()?)?;
+        Ok(match request_api_key {
             model::ApiKey::Versions => {
-                Request::ApiVersions(Request::parse_api_versions(request_header, &mut request))
+                Request::ApiVersions(ApiVersions::from_wire_format(buffer, version)?)
             }
-            model::ApiKey::Fetch => {
-                Request::Fetch(Request::parse_fetch(request_header, &mut request))
+            model::ApiKey::Fetch => Request::Fetch(Fetch::from_wire_format(buffer, version)?),
+            model::ApiKey::Produce => Request::Produce(Produce::from_wire_format(buffer, version)?),
+            model::ApiKey::Metadata => {
+                Request::Metadata(Metadata::from_wire_format(buffer, version)?)
             }
+            model::ApiKey::DescribeTopicPartitions => Request::DescribeTopicPartitions(
+                DescribeTopicPartitions::from_wire_format(buffer, version)?,
+            ),
         })
     }
+}
 
-    fn parse_fetch(header: RequestHeader, request: &mut Cursor<Vec<u8>>) -> Fetch {
-        let client_id_len = request.get_u16() as usize;
-        request.advance(client_id_len);
-        request.advance(1); // TAG_BUFFER
-
-        let _max_wait_ms = request.get_i32();
-        let _min_bytes = request.get_i32();
-        let _max_bytes = request.get_i32();
-        let _isolation_level = request.get_i8();
-        let session_id = request.get_i32();
-        let _session_epoch = request.get_i32();
-
-        let topic_length = request.get_u8().saturating_sub(1); // might be buggy, should read varint instead
-        let mut topics: Vec<model::Topic> = Vec::with_capacity(topic_length as usize);
-        for _ in 0..topic_length {
-            let topic = Request::parse_topic(request);
-            topics.push(topic);
-        }
-        let _forgotten_topics_data_count = request.get_u8().saturating_sub(1);
-        let _rack_id_len = request.get_u8() - 1;
-        request.advance(1); // TAG_BUFFER
-
-        let request = Fetch {
-            header,
-            session_id: session_id,
-            topics: topics,
-        };
-        dbg!(&request);
-        request
+impl Request {
+    // Reads a whole length-prefixed request, so that the stream stays aligned on the next
+    // request even if this one cannot be parsed. `None` means the client closed the stream
+    // between two requests. Like Kafka's socket.request.max.bytes, a frame longer than
+    // `max_bytes` is rejected before anything is allocated for it.
+    pub async fn read_frame<R>(stream: &mut R, max_bytes: usize) -> io::Result<Option<Vec<u8>>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut len = [0; 4];
+        let mut read = 0;
+        while read < len.len() {
+            match stream.read(&mut len[read..]).await? {
+                0 if read == 0 => return Ok(None),
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => read += n,
+            }
+        }
+        let len = i32::from_be_bytes(len);
+        if len < 0 || len as usize > max_bytes {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                codec::DecodeError::InvalidLength(len as i64),
+            ));
+        }
+
+        let mut frame = vec![0; len as usize];
+        stream.read_exact(&mut frame).await?;
+        Ok(Some(frame))
     }
 
-    fn parse_topic(request: &mut Cursor<Vec<u8>>) -> model::Topic {
-        let uuid = request.get_u128();
-        let partition_count = request.get_u8().saturating_sub(1); // Might be buggy, use varint
-        for _ in 0..partition_count {
-            let _partition = request.get_u32();
-            let _current_leader_epoch = request.get_u32();
-            let _fetch_offset = request.get_u64();
-            let _last_fetched_epoch = request.get_u32();
-            let _log_start_offset = request.get_u64();
-            let _partition_max_bytes = request.get_u32();
-            request.advance(1); // TAG_BUFFER
-        }
-        request.advance(1); // TAG_BUFFER
-        model::Topic { id: uuid }
+    pub fn parse_frame(frame: &[u8]) -> codec::Result<Request> {
+        // the api version is needed to know the header version, peek it
+        let mut peek = frame;
+        let _request_api_key = peek.read_int16()?;
+        let request_api_version = peek.read_int16()?;
+
+        Request::from_wire_format(&mut Cursor::new(frame), request_api_version)
     }
 
-    fn parse_api_versions(header: RequestHeader, _buffer: &mut Cursor<Vec<u8>>) -> ApiVersions {
-        ApiVersions { header: header }
+    // Produce requests with acks=0 are never answered
+    pub fn expects_response(&self) -> bool {
+        !matches!(self, Request::Produce(produce_request) if produce_request.acks == 0)
     }
 
     // TODO: API key should not be matched at all
@@ -136,6 +230,20 @@ impl Request {
                 fetch_request.header.request_api_key,
                 fetch_request.header.request_api_version,
             ),
+            Request::Produce(produce_request) => Self::is_request_api_version_valid(
+                produce_request.header.request_api_key,
+                produce_request.header.request_api_version,
+            ),
+            Request::Metadata(metadata_request) => Self::is_request_api_version_valid(
+                metadata_request.header.request_api_key,
+                metadata_request.header.request_api_version,
+            ),
+            Request::DescribeTopicPartitions(describe_request) => {
+                Self::is_request_api_version_valid(
+                    describe_request.header.request_api_key,
+                    describe_request.header.request_api_version,
+                )
+            }
         }
     }
 
@@ -147,6 +255,725 @@ impl Request {
             model::ApiKey::Fetch => (model::ApiKeyVariant::Fetch)
                 .versions()
                 .is_version_valid(version),
+            model::ApiKey::Produce => (model::ApiKeyVariant::Produce)
+                .versions()
+                .is_version_valid(version),
+            model::ApiKey::Metadata => (model::ApiKeyVariant::Metadata)
+                .versions()
+                .is_version_valid(version),
+            model::ApiKey::DescribeTopicPartitions => {
+                (model::ApiKeyVariant::DescribeTopicPartitions)
+                    .versions()
+                    .is_version_valid(version)
+            }
+        }
+    }
+}
+
+pub mod api_versions {
+    use bytes::Buf;
+
+    use crate::server::codec;
+    use crate::server::codec::{Decoder, Encoder};
+    use crate::server::model;
+    use crate::server::model::{TaggedFields, WireDeserialization, WireSerialization};
+
+    use super::RequestHeader;
+
+    impl WireSerialization for super::ApiVersions {
+        // https://kafka.apache.org/protocol#The_Messages_ApiVersions
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let header_version = model::ApiKey::Versions.request_header_version(version);
+            self.header.write(buffer, header_version);
+            if version >= 3 {
+                buffer.write_compact_string(&self.client_software_name);
+                buffer.write_compact_string(&self.client_software_version);
+                self.tagged_fields.write(buffer);
+            }
+        }
+    }
+
+    impl WireDeserialization for super::ApiVersions {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let header_version = model::ApiKey::Versions.request_header_version(version);
+            let header = RequestHeader::read(buffer, header_version)?;
+            if version < 3 {
+                return Ok(super::ApiVersions {
+                    header,
+                    client_software_name: String::new(),
+                    client_software_version: String::new(),
+                    tagged_fields: TaggedFields::default(),
+                });
+            }
+            Ok(super::ApiVersions {
+                header,
+                client_software_name: buffer.read_compact_string()?,
+                client_software_version: buffer.read_compact_string()?,
+                tagged_fields: TaggedFields::read(buffer)?,
+            })
+        }
+    }
+}
+
+pub mod fetch {
+    use bytes::{Buf, BufMut};
+
+    use crate::server::codec;
+    use crate::server::codec::{Decoder, Encoder};
+    use crate::server::model;
+    use crate::server::model::{TaggedFields, WireDeserialization, WireSerialization};
+
+    use super::RequestHeader;
+
+    // Topics are identified by name up to v12 and by id from v13
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct FetchTopic {
+        pub topic: String,
+        pub topic_id: model::Uuid,
+        pub partitions: Vec<FetchPartition>,
+        pub tagged_fields: TaggedFields,
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct FetchPartition {
+        pub partition: i32,
+        pub current_leader_epoch: i32,
+        pub fetch_offset: i64,
+        pub last_fetched_epoch: i32,
+        pub log_start_offset: i64,
+        pub partition_max_bytes: i32,
+        pub tagged_fields: TaggedFields,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct ForgottenTopic {
+        pub topic: String,
+        pub topic_id: model::Uuid,
+        pub partitions: Vec<i32>,
+        pub tagged_fields: TaggedFields,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct ReplicaState {
+        pub replica_id: i32,
+        pub replica_epoch: i64,
+        pub tagged_fields: TaggedFields,
+    }
+
+    const CLUSTER_ID_TAG: u32 = 0;
+    const REPLICA_STATE_TAG: u32 = 1;
+
+    fn is_flexible(version: i16) -> bool {
+        model::ApiKey::Fetch.is_flexible(version)
+    }
+
+    impl WireSerialization for super::Fetch {
+        // https://kafka.apache.org/protocol.html#The_Messages_Fetch
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let flexible = is_flexible(version);
+            let header_version = model::ApiKey::Fetch.request_header_version(version);
+            self.header.write(buffer, header_version);
+
+            if version <= 14 {
+                buffer.put_i32(self.replica_id);
+            }
+            buffer.put_i32(self.max_wait_ms);
+            buffer.put_i32(self.min_bytes);
+            if version >= 3 {
+                buffer.put_i32(self.max_bytes);
+            }
+            if version >= 4 {
+                buffer.put_i8(self.isolation_level);
+            }
+            if version >= 7 {
+                buffer.put_i32(self.session_id);
+                buffer.put_i32(self.session_epoch);
+            }
+            buffer.write_flexible_array(flexible, &self.topics, |buffer, topic| {
+                topic.to_wire_format(buffer, version)
+            });
+            if version >= 7 {
+                buffer.write_flexible_array(
+                    flexible,
+                    &self.forgotten_topics_data,
+                    |buffer, topic| topic.to_wire_format(buffer, version),
+                );
+            }
+            if version >= 11 {
+                buffer.write_flexible_string(flexible, &self.rack_id);
+            }
+
+            let mut tagged_fields = self.tagged_fields.clone();
+            if let Some(cluster_id) = &self.cluster_id {
+                tagged_fields.insert(CLUSTER_ID_TAG, |buffer| {
+                    buffer.write_compact_nullable_string(Some(cluster_id))
+                });
+            }
+            if let Some(replica_state) = &self.replica_state {
+                tagged_fields.insert(REPLICA_STATE_TAG, |buffer| {
+                    replica_state.to_wire_format(buffer, version)
+                });
+            }
+            tagged_fields.write_flexible(buffer, flexible);
+        }
+    }
+
+    impl WireDeserialization for super::Fetch {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let flexible = is_flexible(version);
+            let header_version = model::ApiKey::Fetch.request_header_version(version);
+            let header = RequestHeader::read(buffer, header_version)?;
+
+            // the replica id moved to the replica state tagged field in v15
+            let replica_id = if version <= 14 {
+                buffer.read_int32()?
+            } else {
+                -1
+            };
+            let max_wait_ms = buffer.read_int32()?;
+            let min_bytes = buffer.read_int32()?;
+            let max_bytes = if version >= 3 {
+                buffer.read_int32()?
+            } else {
+                i32::MAX
+            };
+            let isolation_level = if version >= 4 { buffer.read_int8()? } else { 0 };
+            let (session_id, session_epoch) = if version >= 7 {
+                (buffer.read_int32()?, buffer.read_int32()?)
+            } else {
+                (0, -1)
+            };
+            let topics = buffer.read_flexible_array(flexible, |buffer| {
+                FetchTopic::from_wire_format(buffer, version)
+            })?;
+            let forgotten_topics_data = if version >= 7 {
+                buffer.read_flexible_array(flexible, |buffer| {
+                    ForgottenTopic::from_wire_format(buffer, version)
+                })?
+            } else {
+                vec![]
+            };
+            let rack_id = if version >= 11 {
+                buffer.read_flexible_string(flexible)?
+            } else {
+                String::new()
+            };
+
+            let mut tagged_fields = TaggedFields::read_flexible(buffer, flexible)?;
+            let cluster_id = tagged_fields
+                .take(CLUSTER_ID_TAG, |buffer| {
+                    buffer.read_compact_nullable_string()
+                })?
+                .flatten();
+            let replica_state = tagged_fields.take(REPLICA_STATE_TAG, |buffer| {
+                ReplicaState::from_wire_format(buffer, version)
+            })?;
+
+            Ok(super::Fetch {
+                header,
+                replica_id,
+                max_wait_ms,
+                min_bytes,
+                max_bytes,
+                isolation_level,
+                session_id,
+                session_epoch,
+                topics,
+                forgotten_topics_data,
+                rack_id,
+                cluster_id,
+                replica_state,
+                tagged_fields,
+            })
+        }
+    }
+
+    impl WireSerialization for FetchTopic {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let flexible = is_flexible(version);
+            if version <= 12 {
+                buffer.write_flexible_string(flexible, &self.topic);
+            } else {
+                buffer.write_uuid(self.topic_id);
+            }
+            buffer.write_flexible_array(flexible, &self.partitions, |buffer, partition| {
+                partition.to_wire_format(buffer, version)
+            });
+            self.tagged_fields.write_flexible(buffer, flexible);
+        }
+    }
+
+    impl WireDeserialization for FetchTopic {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let flexible = is_flexible(version);
+            let (topic, topic_id) = if version <= 12 {
+                (buffer.read_flexible_string(flexible)?, 0)
+            } else {
+                (String::new(), buffer.read_uuid()?)
+            };
+            Ok(FetchTopic {
+                topic,
+                topic_id,
+                partitions: buffer.read_flexible_array(flexible, |buffer| {
+                    FetchPartition::from_wire_format(buffer, version)
+                })?,
+                tagged_fields: TaggedFields::read_flexible(buffer, flexible)?,
+            })
+        }
+    }
+
+    impl WireSerialization for FetchPartition {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            buffer.put_i32(self.partition);
+            if version >= 9 {
+                buffer.put_i32(self.current_leader_epoch);
+            }
+            buffer.put_i64(self.fetch_offset);
+            if version >= 12 {
+                buffer.put_i32(self.last_fetched_epoch);
+            }
+            if version >= 5 {
+                buffer.put_i64(self.log_start_offset);
+            }
+            buffer.put_i32(self.partition_max_bytes);
+            self.tagged_fields
+                .write_flexible(buffer, is_flexible(version));
+        }
+    }
+
+    impl WireDeserialization for FetchPartition {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let partition = buffer.read_int32()?;
+            let current_leader_epoch = if version >= 9 {
+                buffer.read_int32()?
+            } else {
+                -1
+            };
+            let fetch_offset = buffer.read_int64()?;
+            let last_fetched_epoch = if version >= 12 {
+                buffer.read_int32()?
+            } else {
+                -1
+            };
+            let log_start_offset = if version >= 5 {
+                buffer.read_int64()?
+            } else {
+                -1
+            };
+            Ok(FetchPartition {
+                partition,
+                current_leader_epoch,
+                fetch_offset,
+                last_fetched_epoch,
+                log_start_offset,
+                partition_max_bytes: buffer.read_int32()?,
+                tagged_fields: TaggedFields::read_flexible(buffer, is_flexible(version))?,
+            })
+        }
+    }
+
+    impl WireSerialization for ForgottenTopic {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let flexible = is_flexible(version);
+            if version <= 12 {
+                buffer.write_flexible_string(flexible, &self.topic);
+            } else {
+                buffer.write_uuid(self.topic_id);
+            }
+            buffer.write_flexible_array(flexible, &self.partitions, |buffer, partition| {
+                buffer.put_i32(*partition)
+            });
+            self.tagged_fields.write_flexible(buffer, flexible);
+        }
+    }
+
+    impl WireDeserialization for ForgottenTopic {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let flexible = is_flexible(version);
+            let (topic, topic_id) = if version <= 12 {
+                (buffer.read_flexible_string(flexible)?, 0)
+            } else {
+                (String::new(), buffer.read_uuid()?)
+            };
+            Ok(ForgottenTopic {
+                topic,
+                topic_id,
+                partitions: buffer.read_flexible_array(flexible, |buffer| buffer.read_int32())?,
+                tagged_fields: TaggedFields::read_flexible(buffer, flexible)?,
+            })
+        }
+    }
+
+    // Only sent as a tagged field, hence always flexible
+    impl WireSerialization for ReplicaState {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, _version: i16) {
+            buffer.put_i32(self.replica_id);
+            buffer.put_i64(self.replica_epoch);
+            self.tagged_fields.write(buffer);
+        }
+    }
+
+    impl WireDeserialization for ReplicaState {
+        fn from_wire_format<B: Buf>(buffer: &mut B, _version: i16) -> codec::Result<Self> {
+            Ok(ReplicaState {
+                replica_id: buffer.read_int32()?,
+                replica_epoch: buffer.read_int64()?,
+                tagged_fields: TaggedFields::read(buffer)?,
+            })
+        }
+    }
+}
+
+pub mod produce {
+    use bytes::{Buf, BufMut};
+
+    use crate::server::codec;
+    use crate::server::codec::{Decoder, Encoder};
+    use crate::server::model;
+    use crate::server::model::{TaggedFields, WireDeserialization, WireSerialization};
+
+    use super::RequestHeader;
+
+    #[derive(Debug, PartialEq)]
+    pub struct TopicProduceData {
+        pub name: String,
+        pub partition_data: Vec<PartitionProduceData>,
+        pub tagged_fields: TaggedFields,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct PartitionProduceData {
+        pub index: i32,
+        pub records: Option<Vec<u8>>,
+        pub tagged_fields: TaggedFields,
+    }
+
+    fn is_flexible(version: i16) -> bool {
+        model::ApiKey::Produce.is_flexible(version)
+    }
+
+    impl WireSerialization for super::Produce {
+        // https://kafka.apache.org/protocol.html#The_Messages_Produce
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let flexible = is_flexible(version);
+            let header_version = model::ApiKey::Produce.request_header_version(version);
+            self.header.write(buffer, header_version);
+
+            if version >= 3 {
+                buffer.write_flexible_nullable_string(flexible, self.transactional_id.as_deref());
+            }
+            buffer.put_i16(self.acks);
+            buffer.put_i32(self.timeout_ms);
+            buffer.write_flexible_array(flexible, &self.topic_data, |buffer, topic| {
+                topic.to_wire_format(buffer, version)
+            });
+            self.tagged_fields.write_flexible(buffer, flexible);
+        }
+    }
+
+    impl WireDeserialization for super::Produce {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let flexible = is_flexible(version);
+            let header_version = model::ApiKey::Produce.request_header_version(version);
+            let header = RequestHeader::read(buffer, header_version)?;
+
+            let transactional_id = if version >= 3 {
+                buffer.read_flexible_nullable_string(flexible)?
+            } else {
+                None
+            };
+            Ok(super::Produce {
+                header,
+                transactional_id,
+                acks: buffer.read_int16()?,
+                timeout_ms: buffer.read_int32()?,
+                topic_data: buffer.read_flexible_array(flexible, |buffer| {
+                    TopicProduceData::from_wire_format(buffer, version)
+                })?,
+                tagged_fields: TaggedFields::read_flexible(buffer, flexible)?,
+            })
+        }
+    }
+
+    impl WireSerialization for TopicProduceData {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let flexible = is_flexible(version);
+            buffer.write_flexible_string(flexible, &self.name);
+            buffer.write_flexible_array(flexible, &self.partition_data, |buffer, partition| {
+                partition.to_wire_format(buffer, version)
+            });
+            self.tagged_fields.write_flexible(buffer, flexible);
+        }
+    }
+
+    impl WireDeserialization for TopicProduceData {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let flexible = is_flexible(version);
+            Ok(TopicProduceData {
+                name: buffer.read_flexible_string(flexible)?,
+                partition_data: buffer.read_flexible_array(flexible, |buffer| {
+                    PartitionProduceData::from_wire_format(buffer, version)
+                })?,
+                tagged_fields: TaggedFields::read_flexible(buffer, flexible)?,
+            })
+        }
+    }
+
+    impl WireSerialization for PartitionProduceData {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let flexible = is_flexible(version);
+            buffer.put_i32(self.index);
+            buffer.write_flexible_nullable_bytes(flexible, self.records.as_deref());
+            self.tagged_fields.write_flexible(buffer, flexible);
+        }
+    }
+
+    impl WireDeserialization for PartitionProduceData {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let flexible = is_flexible(version);
+            Ok(PartitionProduceData {
+                index: buffer.read_int32()?,
+                records: buffer.read_flexible_nullable_bytes(flexible)?,
+                tagged_fields: TaggedFields::read_flexible(buffer, flexible)?,
+            })
+        }
+    }
+}
+
+pub mod metadata {
+    use bytes::Buf;
+
+    use crate::server::codec;
+    use crate::server::codec::{Decoder, Encoder};
+    use crate::server::model;
+    use crate::server::model::{TaggedFields, WireDeserialization, WireSerialization};
+
+    use super::RequestHeader;
+
+    // Topics are identified by name, or by id from v10 when the name is null
+    #[derive(Debug, PartialEq)]
+    pub struct MetadataRequestTopic {
+        pub topic_id: model::Uuid,
+        pub name: Option<String>,
+        pub tagged_fields: TaggedFields,
+    }
+
+    fn is_flexible(version: i16) -> bool {
+        model::ApiKey::Metadata.is_flexible(version)
+    }
+
+    impl WireSerialization for super::Metadata {
+        // https://kafka.apache.org/protocol.html#The_Messages_Metadata
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let flexible = is_flexible(version);
+            let header_version = model::ApiKey::Metadata.request_header_version(version);
+            self.header.write(buffer, header_version);
+
+            // v0 asks for every topic with an empty array
+            let topics = match &self.topics {
+                None if version == 0 => Some(&[][..]),
+                topics => topics.as_deref(),
+            };
+            buffer.write_flexible_nullable_array(flexible, topics, |buffer, topic| {
+                topic.to_wire_format(buffer, version)
+            });
+            if version >= 4 {
+                buffer.write_boolean(self.allow_auto_topic_creation);
+            }
+            if (8..=10).contains(&version) {
+                buffer.write_boolean(self.include_cluster_authorized_operations);
+            }
+            if version >= 8 {
+                buffer.write_boolean(self.include_topic_authorized_operations);
+            }
+            self.tagged_fields.write_flexible(buffer, flexible);
+        }
+    }
+
+    impl WireDeserialization for super::Metadata {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let flexible = is_flexible(version);
+            let header_version = model::ApiKey::Metadata.request_header_version(version);
+            let header = RequestHeader::read(buffer, header_version)?;
+
+            let topics = buffer.read_flexible_nullable_array(flexible, |buffer| {
+                MetadataRequestTopic::from_wire_format(buffer, version)
+            })?;
+            let topics = match topics {
+                Some(topics) if version == 0 && topics.is_empty() => None,
+                topics => topics,
+            };
+            let allow_auto_topic_creation = if version >= 4 {
+                buffer.read_boolean()?
+            } else {
+                true
+            };
+            let include_cluster_authorized_operations = if (8..=10).contains(&version) {
+                buffer.read_boolean()?
+            } else {
+                false
+            };
+            let include_topic_authorized_operations = if version >= 8 {
+                buffer.read_boolean()?
+            } else {
+                false
+            };
+
+            Ok(super::Metadata {
+                header,
+                topics,
+                allow_auto_topic_creation,
+                include_cluster_authorized_operations,
+                include_topic_authorized_operations,
+                tagged_fields: TaggedFields::read_flexible(buffer, flexible)?,
+            })
+        }
+    }
+
+    impl WireSerialization for MetadataRequestTopic {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let flexible = is_flexible(version);
+            if version >= 10 {
+                buffer.write_uuid(self.topic_id);
+                buffer.write_flexible_nullable_string(flexible, self.name.as_deref());
+            } else {
+                buffer.write_flexible_string(flexible, self.name.as_deref().unwrap_or_default());
+            }
+            self.tagged_fields.write_flexible(buffer, flexible);
+        }
+    }
+
+    impl WireDeserialization for MetadataRequestTopic {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let flexible = is_flexible(version);
+            let (topic_id, name) = if version >= 10 {
+                (
+                    buffer.read_uuid()?,
+                    buffer.read_flexible_nullable_string(flexible)?,
+                )
+            } else {
+                (0, Some(buffer.read_flexible_string(flexible)?))
+            };
+            Ok(MetadataRequestTopic {
+                topic_id,
+                name,
+                tagged_fields: TaggedFields::read_flexible(buffer, flexible)?,
+            })
+        }
+    }
+}
+
+pub mod describe_topic_partitions {
+    use bytes::{Buf, BufMut};
+
+    use crate::server::codec;
+    use crate::server::codec::{Decoder, Encoder};
+    use crate::server::model;
+    use crate::server::model::{TaggedFields, WireDeserialization, WireSerialization};
+
+    use super::RequestHeader;
+
+    #[derive(Debug, PartialEq)]
+    pub struct TopicRequest {
+        pub name: String,
+        pub tagged_fields: TaggedFields,
+    }
+
+    // First partition to describe, also sent back in the response when the partition limit is hit
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Cursor {
+        pub topic_name: String,
+        pub partition_index: i32,
+        pub tagged_fields: TaggedFields,
+    }
+
+    impl Cursor {
+        // Nullable structs are prefixed with -1 when null and 1 otherwise
+        pub fn write_nullable(cursor: Option<&Cursor>, buffer: &mut Vec<u8>, version: i16) {
+            match cursor {
+                Some(cursor) => {
+                    buffer.put_i8(1);
+                    cursor.to_wire_format(buffer, version);
+                }
+                None => buffer.put_i8(-1),
+            }
+        }
+
+        pub fn read_nullable<B: Buf>(
+            buffer: &mut B,
+            version: i16,
+        ) -> codec::Result<Option<Cursor>> {
+            if buffer.read_int8()? < 0 {
+                return Ok(None);
+            }
+            Ok(Some(Cursor::from_wire_format(buffer, version)?))
+        }
+    }
+
+    impl WireSerialization for super::DescribeTopicPartitions {
+        // https://kafka.apache.org/protocol.html#The_Messages_DescribeTopicPartitions
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let header_version =
+                model::ApiKey::DescribeTopicPartitions.request_header_version(version);
+            self.header.write(buffer, header_version);
+
+            buffer.write_compact_array(&self.topics, |buffer, topic| {
+                topic.to_wire_format(buffer, version)
+            });
+            buffer.put_i32(self.response_partition_limit);
+            Cursor::write_nullable(self.cursor.as_ref(), buffer, version);
+            self.tagged_fields.write(buffer);
+        }
+    }
+
+    impl WireDeserialization for super::DescribeTopicPartitions {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let header_version =
+                model::ApiKey::DescribeTopicPartitions.request_header_version(version);
+            let header = RequestHeader::read(buffer, header_version)?;
+
+            Ok(super::DescribeTopicPartitions {
+                header,
+                topics: buffer
+                    .read_compact_array(|buffer| TopicRequest::from_wire_format(buffer, version))?,
+                response_partition_limit: buffer.read_int32()?,
+                cursor: Cursor::read_nullable(buffer, version)?,
+                tagged_fields: TaggedFields::read(buffer)?,
+            })
+        }
+    }
+
+    impl WireSerialization for TopicRequest {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, _version: i16) {
+            buffer.write_compact_string(&self.name);
+            self.tagged_fields.write(buffer);
+        }
+    }
+
+    impl WireDeserialization for TopicRequest {
+        fn from_wire_format<B: Buf>(buffer: &mut B, _version: i16) -> codec::Result<Self> {
+            Ok(TopicRequest {
+                name: buffer.read_compact_string()?,
+                tagged_fields: TaggedFields::read(buffer)?,
+            })
+        }
+    }
+
+    impl WireSerialization for Cursor {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, _version: i16) {
+            buffer.write_compact_string(&self.topic_name);
+            buffer.put_i32(self.partition_index);
+            self.tagged_fields.write(buffer);
+        }
+    }
+
+    impl WireDeserialization for Cursor {
+        fn from_wire_format<B: Buf>(buffer: &mut B, _version: i16) -> codec::Result<Self> {
+            Ok(Cursor {
+                topic_name: buffer.read_compact_string()?,
+                partition_index: buffer.read_int32()?,
+                tagged_fields: TaggedFields::read(buffer)?,
+            })
         }
     }
 }
@@ -154,33 +981,45 @@ impl Request {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::server::codec::Encoder;
     use bytes::BufMut;
     use tokio::io::AsyncWriteExt;
     #[tokio::test]
     async fn test_parse_request_api_versions_request() {
         let (mut client, mut server) = tokio::io::duplex(512);
 
-        let request_body_length = 8;
+        let request_body_length = 19;
         let request_api_key: model::ApiKey = model::ApiKey::Versions;
         let request_api_version: i16 = 2;
         let correlation_id: i32 = 42;
 
         let mut request_data = vec![];
-        request_data.put_i32(request_body_length as i32);
+        request_data.put_i32(request_body_length);
         request_data.put_i16(request_api_key as i16);
         request_data.put_i16(request_api_version);
         request_data.put_i32(correlation_id);
+        request_data.put_i16(9);
+        request_data.put_slice(b"kafka-cli");
 
         client.write_all(&request_data).await.unwrap();
         client.shutdown().await.unwrap();
 
-        let request = Request::parse_request(&mut server).await.unwrap();
+        let frame = Request::read_frame(&mut server, usize::MAX)
+            .await
+            .unwrap()
+            .unwrap();
+        let request = Request::parse_frame(&frame).unwrap();
         let expected_request = Request::ApiVersions(super::ApiVersions {
             header: RequestHeader {
-                request_api_key: request_api_key,
-                request_api_version: request_api_version,
-                correlation_id: correlation_id,
+                request_api_key,
+                request_api_version,
+                correlation_id,
+                client_id: Some("kafka-cli".to_string()),
+                tagged_fields: model::TaggedFields::default(),
             },
+            client_software_name: String::new(),
+            client_software_version: String::new(),
+            tagged_fields: model::TaggedFields::default(),
         });
 
         assert_eq!(request, expected_request);
@@ -192,20 +1031,19 @@ mod tests {
         let (mut client, mut server) = tokio::io::duplex(512);
         let mut request_data = vec![];
 
-        let request_body_length = 89;
+        let request_body_length = 96;
         let request_api_key: model::ApiKey = model::ApiKey::Fetch;
         let request_api_version: i16 = 18;
         let correlation_id: i32 = 42;
 
-        request_data.put_i32(request_body_length as i32);
+        request_data.put_i32(request_body_length);
         request_data.put_i16(request_api_key as i16);
         request_data.put_i16(request_api_version);
         request_data.put_i32(correlation_id);
 
-        let client_id_len = 2;
-        request_data.put_u16(client_id_len);
-        request_data.put_bytes(0, client_id_len as usize);
-        request_data.put_u8(0);
+        request_data.put_i16(9);
+        request_data.put_slice(b"kafka-cli");
+        request_data.put_u8(0); // TAG_BUFFER
 
         let max_wait_ms = 1;
         let min_bytes = 2;
@@ -256,17 +1094,473 @@ mod tests {
         client.write_all(&request_data).await.unwrap();
         client.shutdown().await.unwrap();
 
-        let request = Request::parse_request(&mut server).await.unwrap();
+        let frame = Request::read_frame(&mut server, usize::MAX)
+            .await
+            .unwrap()
+            .unwrap();
+        let request = Request::parse_frame(&frame).unwrap();
         let expected_request = Request::Fetch(super::Fetch {
             header: RequestHeader {
-                request_api_key: request_api_key,
-                request_api_version: request_api_version,
-                correlation_id: correlation_id,
+                request_api_key,
+                request_api_version,
+                correlation_id,
+                client_id: Some("kafka-cli".to_string()),
+                tagged_fields: model::TaggedFields::default(),
             },
-            session_id: 5,
-            topics: vec![model::Topic { id: 7 }],
+            replica_id: -1,
+            max_wait_ms,
+            min_bytes,
+            max_bytes,
+            isolation_level,
+            session_id,
+            session_epoch,
+            topics: vec![fetch::FetchTopic {
+                topic: String::new(),
+                topic_id: 7,
+                partitions: vec![fetch::FetchPartition {
+                    partition: 8,
+                    current_leader_epoch: 9,
+                    fetch_offset: 10,
+                    last_fetched_epoch: 11,
+                    log_start_offset: 12,
+                    partition_max_bytes: 13,
+                    tagged_fields: model::TaggedFields::default(),
+                }],
+                tagged_fields: model::TaggedFields::default(),
+            }],
+            forgotten_topics_data: vec![],
+            rack_id: String::new(),
+            cluster_id: None,
+            replica_state: None,
+            tagged_fields: model::TaggedFields::default(),
         });
 
         assert_eq!(request, expected_request);
     }
+
+    fn unknown_tagged_fields() -> model::TaggedFields {
+        let mut tagged_fields = model::TaggedFields::default();
+        tagged_fields.insert(42, |buffer| buffer.put_i32(7));
+        tagged_fields
+    }
+
+    #[test]
+    fn test_fetch_request_round_trip() {
+        let request = Request::Fetch(super::Fetch {
+            header: RequestHeader {
+                request_api_key: model::ApiKey::Fetch,
+                request_api_version: 16,
+                correlation_id: 7,
+                client_id: Some("kafka-cli".to_string()),
+                tagged_fields: model::TaggedFields::default(),
+            },
+            replica_id: -1,
+            max_wait_ms: 500,
+            min_bytes: 1,
+            max_bytes: 52428800,
+            isolation_level: 1,
+            session_id: 0,
+            session_epoch: -1,
+            topics: vec![fetch::FetchTopic {
+                topic: String::new(),
+                topic_id: 3,
+                partitions: vec![],
+                tagged_fields: model::TaggedFields::default(),
+            }],
+            forgotten_topics_data: vec![fetch::ForgottenTopic {
+                topic: String::new(),
+                topic_id: 4,
+                partitions: vec![0, 1],
+                tagged_fields: model::TaggedFields::default(),
+            }],
+            rack_id: "rack".to_string(),
+            cluster_id: Some("cluster".to_string()),
+            replica_state: Some(fetch::ReplicaState {
+                replica_id: 1,
+                replica_epoch: 2,
+                tagged_fields: model::TaggedFields::default(),
+            }),
+            tagged_fields: unknown_tagged_fields(),
+        });
+
+        let mut buffer = vec![];
+        request.to_wire_format(&mut buffer, 16);
+
+        assert_eq!(
+            Request::from_wire_format(&mut buffer.as_slice(), 16),
+            Ok(request)
+        );
+    }
+
+    #[test]
+    fn test_fetch_request_by_topic_name_round_trip() {
+        for version in [0, 3, 4, 5, 7, 9, 11, 12] {
+            let request = Request::Fetch(super::Fetch {
+                header: RequestHeader {
+                    request_api_key: model::ApiKey::Fetch,
+                    request_api_version: version,
+                    correlation_id: 7,
+                    client_id: Some("kafka-cli".to_string()),
+                    tagged_fields: model::TaggedFields::default(),
+                },
+                replica_id: -1,
+                max_wait_ms: 500,
+                min_bytes: 1,
+                max_bytes: if version >= 3 { 1024 } else { i32::MAX },
+                isolation_level: if version >= 4 { 1 } else { 0 },
+                session_id: if version >= 7 { 12 } else { 0 },
+                session_epoch: if version >= 7 { 1 } else { -1 },
+                topics: vec![fetch::FetchTopic {
+                    topic: "orders".to_string(),
+                    topic_id: 0,
+                    partitions: vec![fetch::FetchPartition {
+                        partition: 2,
+                        current_leader_epoch: if version >= 9 { 3 } else { -1 },
+                        fetch_offset: 42,
+                        last_fetched_epoch: if version >= 12 { 3 } else { -1 },
+                        log_start_offset: if version >= 5 { 0 } else { -1 },
+                        partition_max_bytes: 512,
+                        tagged_fields: model::TaggedFields::default(),
+                    }],
+                    tagged_fields: model::TaggedFields::default(),
+                }],
+                forgotten_topics_data: vec![],
+                rack_id: if version >= 11 {
+                    "rack".to_string()
+                } else {
+                    String::new()
+                },
+                cluster_id: None,
+                replica_state: None,
+                tagged_fields: model::TaggedFields::default(),
+            });
+
+            let mut buffer = vec![];
+            request.to_wire_format(&mut buffer, version);
+
+            assert_eq!(
+                Request::from_wire_format(&mut buffer.as_slice(), version),
+                Ok(request),
+                "version {}",
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn test_fetch_request_v0_layout() {
+        let mut buffer = vec![];
+        buffer.put_i16(model::ApiKey::Fetch as i16);
+        buffer.put_i16(0);
+        buffer.put_i32(7);
+        buffer.write_nullable_string(None);
+        buffer.put_i32(-1); // replica id
+        buffer.put_i32(100); // max wait ms
+        buffer.put_i32(1); // min bytes
+        buffer.write_array(&["orders"], |buffer, topic| {
+            buffer.write_string(topic);
+            buffer.write_array(&[0], |buffer, partition| {
+                buffer.put_i32(*partition);
+                buffer.put_i64(5); // fetch offset
+                buffer.put_i32(1024); // partition max bytes
+            });
+        });
+
+        let Ok(Request::Fetch(request)) = Request::from_wire_format(&mut buffer.as_slice(), 0)
+        else {
+            panic!("fetch v0 request should be parsed");
+        };
+        assert_eq!(request.topics[0].topic, "orders");
+        assert_eq!(request.topics[0].partitions[0].fetch_offset, 5);
+        assert_eq!(request.topics[0].partitions[0].partition_max_bytes, 1024);
+        assert_eq!(request.max_bytes, i32::MAX);
+    }
+
+    fn metadata_request(
+        version: i16,
+        topics: Option<Vec<metadata::MetadataRequestTopic>>,
+    ) -> Request {
+        Request::Metadata(super::Metadata {
+            header: RequestHeader {
+                request_api_key: model::ApiKey::Metadata,
+                request_api_version: version,
+                correlation_id: 7,
+                client_id: Some("kafka-cli".to_string()),
+                tagged_fields: model::TaggedFields::default(),
+            },
+            topics,
+            allow_auto_topic_creation: true,
+            include_cluster_authorized_operations: (8..=10).contains(&version),
+            include_topic_authorized_operations: version >= 8,
+            tagged_fields: model::TaggedFields::default(),
+        })
+    }
+
+    #[test]
+    fn test_metadata_request_round_trip() {
+        for version in 0..=12 {
+            let topics = vec![metadata::MetadataRequestTopic {
+                topic_id: if version >= 10 { 17 } else { 0 },
+                name: Some("foo".to_string()),
+                tagged_fields: model::TaggedFields::default(),
+            }];
+            for topics in [None, Some(topics)] {
+                let request = metadata_request(version, topics);
+                let mut buffer = vec![];
+                request.to_wire_format(&mut buffer, version);
+                assert_eq!(
+                    Request::from_wire_format(&mut buffer.as_slice(), version),
+                    Ok(request),
+                    "version {}",
+                    version
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_metadata_request_should_ask_for_every_topic_with_an_empty_array_in_v0() {
+        let mut buffer = vec![];
+        metadata_request(0, None).to_wire_format(&mut buffer, 0);
+        assert_eq!(&buffer[buffer.len() - 4..], &[0, 0, 0, 0]);
+
+        let mut buffer = vec![];
+        metadata_request(1, None).to_wire_format(&mut buffer, 1);
+        assert_eq!(&buffer[buffer.len() - 4..], &[255, 255, 255, 255]);
+
+        let mut buffer = vec![];
+        metadata_request(1, Some(vec![])).to_wire_format(&mut buffer, 1);
+        assert_eq!(
+            Request::from_wire_format(&mut buffer.as_slice(), 1),
+            Ok(metadata_request(1, Some(vec![])))
+        );
+    }
+
+    fn describe_topic_partitions_request(
+        cursor: Option<describe_topic_partitions::Cursor>,
+    ) -> Request {
+        Request::DescribeTopicPartitions(DescribeTopicPartitions {
+            header: RequestHeader {
+                request_api_key: model::ApiKey::DescribeTopicPartitions,
+                request_api_version: 0,
+                correlation_id: 7,
+                client_id: Some("cli".to_string()),
+                tagged_fields: model::TaggedFields::default(),
+            },
+            topics: vec![describe_topic_partitions::TopicRequest {
+                name: "foo".to_string(),
+                tagged_fields: model::TaggedFields::default(),
+            }],
+            response_partition_limit: 2000,
+            cursor,
+            tagged_fields: model::TaggedFields::default(),
+        })
+    }
+
+    #[test]
+    fn test_describe_topic_partitions_request_layout() {
+        let mut buffer = vec![];
+        describe_topic_partitions_request(None).to_wire_format(&mut buffer, 0);
+
+        let mut expected = vec![0, 75, 0, 0, 0, 0, 0, 7, 0, 3];
+        expected.extend_from_slice(b"cli");
+        expected.extend_from_slice(&[0, 2, 4]);
+        expected.extend_from_slice(b"foo");
+        expected.extend_from_slice(&[0, 0, 0, 7, 208, 255, 0]);
+        assert_eq!(buffer, expected);
+        assert_eq!(
+            Request::parse_frame(&buffer),
+            Ok(describe_topic_partitions_request(None))
+        );
+    }
+
+    #[test]
+    fn test_describe_topic_partitions_request_with_cursor_round_trip() {
+        let cursor = describe_topic_partitions::Cursor {
+            topic_name: "foo".to_string(),
+            partition_index: 3,
+            tagged_fields: model::TaggedFields::default(),
+        };
+        let mut buffer = vec![];
+        describe_topic_partitions_request(Some(cursor.clone())).to_wire_format(&mut buffer, 0);
+
+        assert_eq!(
+            Request::from_wire_format(&mut buffer.as_slice(), 0),
+            Ok(describe_topic_partitions_request(Some(cursor)))
+        );
+    }
+
+    #[test]
+    fn test_api_versions_request_v3_round_trip() {
+        let request = Request::ApiVersions(super::ApiVersions {
+            header: RequestHeader {
+                request_api_key: model::ApiKey::Versions,
+                request_api_version: 3,
+                correlation_id: 7,
+                client_id: Some("kafka-cli".to_string()),
+                tagged_fields: model::TaggedFields::default(),
+            },
+            client_software_name: "kafka-cli".to_string(),
+            client_software_version: "0.1".to_string(),
+            tagged_fields: unknown_tagged_fields(),
+        });
+
+        let mut buffer = vec![];
+        request.to_wire_format(&mut buffer, 3);
+        assert_eq!(
+            Request::from_wire_format(&mut buffer.as_slice(), 3),
+            Ok(request)
+        );
+    }
+
+    fn produce_request(version: i16) -> Request {
+        Request::Produce(super::Produce {
+            header: RequestHeader {
+                request_api_key: model::ApiKey::Produce,
+                request_api_version: version,
+                correlation_id: 7,
+                client_id: Some("kafka-cli".to_string()),
+                tagged_fields: model::TaggedFields::default(),
+            },
+            transactional_id: None,
+            acks: -1,
+            timeout_ms: 30000,
+            topic_data: vec![produce::TopicProduceData {
+                name: "foo".to_string(),
+                partition_data: vec![
+                    produce::PartitionProduceData {
+                        index: 0,
+                        records: Some(vec![1, 2, 3]),
+                        tagged_fields: model::TaggedFields::default(),
+                    },
+                    produce::PartitionProduceData {
+                        index: 1,
+                        records: None,
+                        tagged_fields: model::TaggedFields::default(),
+                    },
+                ],
+                tagged_fields: model::TaggedFields::default(),
+            }],
+            tagged_fields: model::TaggedFields::default(),
+        })
+    }
+
+    #[test]
+    fn test_produce_request_round_trip() {
+        for version in 3..=11 {
+            let request = produce_request(version);
+            let mut buffer = vec![];
+            request.to_wire_format(&mut buffer, version);
+            assert_eq!(
+                Request::from_wire_format(&mut buffer.as_slice(), version),
+                Ok(request),
+                "version {}",
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn test_produce_request_v3_layout() {
+        let mut buffer = vec![];
+        produce_request(3).to_wire_format(&mut buffer, 3);
+
+        let mut expected = vec![0, 0, 0, 3, 0, 0, 0, 7, 0, 9];
+        expected.extend_from_slice(b"kafka-cli");
+        expected.extend_from_slice(&[255, 255, 255, 255, 0, 0, 117, 48, 0, 0, 0, 1, 0, 3]);
+        expected.extend_from_slice(b"foo");
+        expected.extend_from_slice(&[0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 3, 1, 2, 3]);
+        expected.extend_from_slice(&[0, 0, 0, 1, 255, 255, 255, 255]);
+        assert_eq!(buffer, expected);
+    }
+
+    #[test]
+    fn test_request_header_versions_round_trip() {
+        let header = RequestHeader {
+            request_api_key: model::ApiKey::Fetch,
+            request_api_version: 11,
+            correlation_id: 7,
+            client_id: Some("kafka-cli".to_string()),
+            tagged_fields: unknown_tagged_fields(),
+        };
+
+        let mut buffer = vec![];
+        header.write(&mut buffer, model::HeaderVersion(2));
+        assert_eq!(
+            RequestHeader::read(&mut buffer.as_slice(), model::HeaderVersion(2)),
+            Ok(header)
+        );
+
+        let header = RequestHeader {
+            request_api_key: model::ApiKey::Fetch,
+            request_api_version: 11,
+            correlation_id: 7,
+            client_id: Some("kafka-cli".to_string()),
+            tagged_fields: model::TaggedFields::default(),
+        };
+        let mut buffer = vec![];
+        header.write(&mut buffer, model::HeaderVersion(1));
+        assert_eq!(buffer.len(), 8 + 2 + 9);
+        assert_eq!(
+            RequestHeader::read(&mut buffer.as_slice(), model::HeaderVersion(1)),
+            Ok(header)
+        );
+    }
+
+    #[test]
+    fn test_parse_request_should_fail_on_unknown_api_key() {
+        let buffer = vec![0, 99, 0, 0, 0, 0, 0, 1];
+        assert_eq!(
+            Request::from_wire_format(&mut buffer.as_slice(), 16),
+            Err(codec::DecodeError::UnknownApiKey(99))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_should_consume_the_whole_request() {
+        let data: Vec<u8> = vec![0, 0, 0, 8, 0, 99, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0];
+        let mut stream = data.as_slice();
+
+        let frame = Request::read_frame(&mut stream, 64).await.unwrap();
+        assert_eq!(frame, Some(vec![0, 99, 0, 0, 0, 0, 0, 1]));
+        assert_eq!(stream, &[0, 0, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_should_distinguish_end_of_stream_and_truncated_frames() {
+        let mut stream: &[u8] = &[];
+        assert!(Request::read_frame(&mut stream, 64)
+            .await
+            .unwrap()
+            .is_none());
+
+        let mut stream: &[u8] = &[0, 0];
+        let error = Request::read_frame(&mut stream, 64).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut stream: &[u8] = &[0, 0, 0, 8, 0, 18];
+        let error = Request::read_frame(&mut stream, 64).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut stream: &[u8] = &[255, 255, 255, 255];
+        let error = Request::read_frame(&mut stream, 64).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_should_reject_frames_over_the_max_size() {
+        let mut stream: &[u8] = &[0, 0, 0, 64, 0, 18];
+        let frame = Request::read_frame(&mut stream, 64).await;
+        assert!(matches!(frame, Err(e) if e.kind() == io::ErrorKind::UnexpectedEof));
+
+        // a 2 GiB length prefix
+        let mut stream: &[u8] = &[127, 255, 255, 255, 0, 18];
+        let error = Request::read_frame(&mut stream, 64).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            error.into_inner().unwrap().downcast_ref(),
+            Some(&codec::DecodeError::InvalidLength(i32::MAX as i64))
+        );
+        assert_eq!(stream, &[0, 18]);
+    }
 }
@@ -1,19 +1,46 @@
 use super::model;
 use super::model::WireSerialization;
+use bytes::BufMut;
 
 #[derive(Debug, PartialEq)]
 pub enum Response {
     ApiVersions(ApiVersions),
     Fetch(Fetch),
+    Produce(Produce),
+    Metadata(Metadata),
+    DescribeTopicPartitions(DescribeTopicPartitions),
 }
 
 impl WireSerialization for Response {
-    fn to_wire_format(&self, buffer: &mut Vec<u8>) {
+    fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
         match self {
             Response::ApiVersions(api_versions_response) => {
-                api_versions_response.to_wire_format(buffer)
+                api_versions_response.to_wire_format(buffer, version)
             }
-            Response::Fetch(fetch_response) => fetch_response.to_wire_format(buffer),
+            Response::Fetch(fetch_response) => fetch_response.to_wire_format(buffer, version),
+            Response::Produce(produce_response) => produce_response.to_wire_format(buffer, version),
+            Response::Metadata(metadata_response) => {
+                metadata_response.to_wire_format(buffer, version)
+            }
+            Response::DescribeTopicPartitions(describe_response) => {
+                describe_response.to_wire_format(buffer, version)
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ResponseHeader {
+    pub correlation_id: i32,
+    pub tagged_fields: model::TaggedFields,
+}
+
+// Headers are versioned apart from their message, see `model::ApiKey::response_header_version`
+impl ResponseHeader {
+    pub fn write(&self, buffer: &mut Vec<u8>, version: model::HeaderVersion) {
+        buffer.put_i32(self.correlation_id);
+        if version >= model::HeaderVersion(1) {
+            self.tagged_fields.write(buffer);
         }
     }
 }
@@ -21,122 +48,1610 @@ impl WireSerialization for Response {
 #[derive(Debug, PartialEq)]
 pub struct Fetch {
     pub throttle_time_in_ms: i32,
+    pub error_code: i16,
     pub session_id: i32,
     pub topics: Vec<fetch::FetchTopicResponse>,
+    pub node_endpoints: Option<Vec<fetch::NodeEndpoint>>,
+    pub tagged_fields: model::TaggedFields,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Produce {
+    pub responses: Vec<produce::TopicProduceResponse>,
+    pub throttle_time_in_ms: i32,
+    pub node_endpoints: Option<Vec<fetch::NodeEndpoint>>,
+    pub tagged_fields: model::TaggedFields,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Metadata {
+    pub throttle_time_in_ms: i32,
+    pub brokers: Vec<metadata::MetadataResponseBroker>,
+    pub cluster_id: Option<String>,
+    pub controller_id: i32,
+    pub topics: Vec<metadata::MetadataResponseTopic>,
+    pub cluster_authorized_operations: i32,
+    pub tagged_fields: model::TaggedFields,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DescribeTopicPartitions {
+    pub throttle_time_in_ms: i32,
+    pub topics: Vec<describe_topic_partitions::DescribeTopicPartitionsResponseTopic>,
+    // set when the partition limit was hit, to be sent back in the next request
+    pub next_cursor: Option<describe_topic_partitions::Cursor>,
+    pub tagged_fields: model::TaggedFields,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct ApiVersions {
-    pub api_key_versions: Vec<model::ApiKeyVariant>,
+    pub error_code: i16,
+    pub api_key_versions: Vec<api_versions::ApiKeyVersion>,
     pub throttle_time_in_ms: i32,
+    pub supported_features: Option<Vec<api_versions::SupportedFeatureKey>>,
+    pub finalized_features_epoch: Option<i64>,
+    pub finalized_features: Option<Vec<api_versions::FinalizedFeatureKey>>,
+    pub zk_migration_ready: Option<bool>,
+    pub tagged_fields: model::TaggedFields,
 }
 
 pub mod api_versions {
-    use bytes::BufMut;
+    use bytes::{Buf, BufMut};
 
-    use crate::server::ErrorCode;
+    use crate::server::codec;
+    use crate::server::codec::{Decoder, Encoder};
+    use crate::server::model;
+    use crate::server::model::{TaggedFields, WireDeserialization, WireSerialization};
 
-    impl super::WireSerialization for super::ApiVersions {
-        // https://kafka.apache.org/protocol#The_Messages_ApiVersions
-        fn to_wire_format(&self, buffer: &mut Vec<u8>) -> () {
-            buffer.put_i16(ErrorCode::Ok as i16);
+    #[derive(Debug, PartialEq)]
+    pub struct ApiKeyVersion {
+        pub api_key: i16,
+        pub min_version: i16,
+        pub max_version: i16,
+        pub tagged_fields: TaggedFields,
+    }
 
-            let number_of_tagged_fields = self.api_key_versions.len() + 1; // verint offset by 1 to reserve 0 for null values
-            buffer.put_i8(number_of_tagged_fields as i8); // might be buggy for number > 7 as this is encoded as variable length encoding TODO: fix
+    #[derive(Debug, PartialEq)]
+    pub struct SupportedFeatureKey {
+        pub name: String,
+        pub min_version: i16,
+        pub max_version: i16,
+        pub tagged_fields: TaggedFields,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct FinalizedFeatureKey {
+        pub name: String,
+        pub max_version_level: i16,
+        pub min_version_level: i16,
+        pub tagged_fields: TaggedFields,
+    }
+
+    const SUPPORTED_FEATURES_TAG: u32 = 0;
+    const FINALIZED_FEATURES_EPOCH_TAG: u32 = 1;
+    const FINALIZED_FEATURES_TAG: u32 = 2;
+    const ZK_MIGRATION_READY_TAG: u32 = 3;
 
-            for api_key_version in &self.api_key_versions {
-                api_key_version.to_wire_format(buffer);
+    impl From<model::ApiKeyVariant> for ApiKeyVersion {
+        fn from(api_key_variant: model::ApiKeyVariant) -> Self {
+            let versions = api_key_variant.versions();
+            ApiKeyVersion {
+                api_key: versions.api_key as i16,
+                min_version: versions.min_version,
+                max_version: versions.max_version,
+                tagged_fields: TaggedFields::default(),
             }
-            buffer.put_i32(self.throttle_time_in_ms); // throttle time in ms
-            buffer.put_i8(0); // no tagged fields, null marker
+        }
+    }
+
+    fn is_flexible(version: i16) -> bool {
+        model::ApiKey::Versions.is_flexible(version)
+    }
+
+    impl WireSerialization for super::ApiVersions {
+        // https://kafka.apache.org/protocol#The_Messages_ApiVersions
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let flexible = is_flexible(version);
+            buffer.put_i16(self.error_code);
+            buffer.write_flexible_array(
+                flexible,
+                &self.api_key_versions,
+                |buffer, api_key_version| api_key_version.to_wire_format(buffer, version),
+            );
+            if version >= 1 {
+                buffer.put_i32(self.throttle_time_in_ms);
+            }
+
+            let mut tagged_fields = self.tagged_fields.clone();
+            if let Some(supported_features) = &self.supported_features {
+                tagged_fields.insert(SUPPORTED_FEATURES_TAG, |buffer| {
+                    buffer.write_compact_array(supported_features, |buffer, feature| {
+                        feature.to_wire_format(buffer, version)
+                    })
+                });
+            }
+            if let Some(finalized_features_epoch) = self.finalized_features_epoch {
+                tagged_fields.insert(FINALIZED_FEATURES_EPOCH_TAG, |buffer| {
+                    buffer.put_i64(finalized_features_epoch)
+                });
+            }
+            if let Some(finalized_features) = &self.finalized_features {
+                tagged_fields.insert(FINALIZED_FEATURES_TAG, |buffer| {
+                    buffer.write_compact_array(finalized_features, |buffer, feature| {
+                        feature.to_wire_format(buffer, version)
+                    })
+                });
+            }
+            if let Some(zk_migration_ready) = self.zk_migration_ready {
+                tagged_fields.insert(ZK_MIGRATION_READY_TAG, |buffer| {
+                    buffer.write_boolean(zk_migration_ready)
+                });
+            }
+            tagged_fields.write_flexible(buffer, flexible);
+        }
+    }
+
+    impl WireDeserialization for super::ApiVersions {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let flexible = is_flexible(version);
+            let error_code = buffer.read_int16()?;
+            let api_key_versions = buffer.read_flexible_array(flexible, |buffer| {
+                ApiKeyVersion::from_wire_format(buffer, version)
+            })?;
+            let throttle_time_in_ms = if version >= 1 {
+                buffer.read_int32()?
+            } else {
+                0
+            };
+
+            let mut tagged_fields = TaggedFields::read_flexible(buffer, flexible)?;
+            let supported_features = tagged_fields.take(SUPPORTED_FEATURES_TAG, |buffer| {
+                buffer.read_compact_array(|buffer| {
+                    SupportedFeatureKey::from_wire_format(buffer, version)
+                })
+            })?;
+            let finalized_features_epoch =
+                tagged_fields.take(FINALIZED_FEATURES_EPOCH_TAG, |buffer| buffer.read_int64())?;
+            let finalized_features = tagged_fields.take(FINALIZED_FEATURES_TAG, |buffer| {
+                buffer.read_compact_array(|buffer| {
+                    FinalizedFeatureKey::from_wire_format(buffer, version)
+                })
+            })?;
+            let zk_migration_ready =
+                tagged_fields.take(ZK_MIGRATION_READY_TAG, |buffer| buffer.read_boolean())?;
+
+            Ok(super::ApiVersions {
+                error_code,
+                api_key_versions,
+                throttle_time_in_ms,
+                supported_features,
+                finalized_features_epoch,
+                finalized_features,
+                zk_migration_ready,
+                tagged_fields,
+            })
+        }
+    }
+
+    impl WireSerialization for ApiKeyVersion {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            buffer.put_i16(self.api_key);
+            buffer.put_i16(self.min_version);
+            buffer.put_i16(self.max_version);
+            self.tagged_fields
+                .write_flexible(buffer, is_flexible(version));
+        }
+    }
+
+    impl WireDeserialization for ApiKeyVersion {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            Ok(ApiKeyVersion {
+                api_key: buffer.read_int16()?,
+                min_version: buffer.read_int16()?,
+                max_version: buffer.read_int16()?,
+                tagged_fields: TaggedFields::read_flexible(buffer, is_flexible(version))?,
+            })
+        }
+    }
+
+    // Features are only sent as tagged fields, hence always flexible
+    impl WireSerialization for SupportedFeatureKey {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, _version: i16) {
+            buffer.write_compact_string(&self.name);
+            buffer.put_i16(self.min_version);
+            buffer.put_i16(self.max_version);
+            self.tagged_fields.write(buffer);
+        }
+    }
+
+    impl WireDeserialization for SupportedFeatureKey {
+        fn from_wire_format<B: Buf>(buffer: &mut B, _version: i16) -> codec::Result<Self> {
+            Ok(SupportedFeatureKey {
+                name: buffer.read_compact_string()?,
+                min_version: buffer.read_int16()?,
+                max_version: buffer.read_int16()?,
+                tagged_fields: TaggedFields::read(buffer)?,
+            })
+        }
+    }
+
+    impl WireSerialization for FinalizedFeatureKey {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, _version: i16) {
+            buffer.write_compact_string(&self.name);
+            buffer.put_i16(self.max_version_level);
+            buffer.put_i16(self.min_version_level);
+            self.tagged_fields.write(buffer);
+        }
+    }
+
+    impl WireDeserialization for FinalizedFeatureKey {
+        fn from_wire_format<B: Buf>(buffer: &mut B, _version: i16) -> codec::Result<Self> {
+            Ok(FinalizedFeatureKey {
+                name: buffer.read_compact_string()?,
+                max_version_level: buffer.read_int16()?,
+                min_version_level: buffer.read_int16()?,
+                tagged_fields: TaggedFields::read(buffer)?,
+            })
         }
     }
 }
 
 pub mod fetch {
-    use bytes::BufMut;
+    use bytes::{Buf, BufMut};
 
+    use crate::server::codec;
+    use crate::server::codec::{Decoder, Encoder};
+    use crate::server::model;
+    use crate::server::model::{TaggedFields, WireDeserialization, WireSerialization};
+
+    // Topics are identified by name up to v12 and by id from v13
     #[derive(Debug, PartialEq)]
     pub struct FetchTopicResponse {
-        pub topic_id: u128,
+        pub topic: String,
+        pub topic_id: model::Uuid,
+        pub partitions: Vec<PartitionData>,
+        pub tagged_fields: TaggedFields,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct PartitionData {
+        pub partition_index: i32,
+        pub error_code: i16,
+        pub high_watermark: i64,
+        pub last_stable_offset: i64,
+        pub log_start_offset: i64,
+        pub diverging_epoch: Option<EpochEndOffset>,
+        pub current_leader: Option<LeaderIdAndEpoch>,
+        pub snapshot_id: Option<SnapshotId>,
+        pub aborted_transactions: Option<Vec<AbortedTransaction>>,
+        pub preferred_read_replica: i32,
+        pub records: Option<Vec<u8>>,
+        pub tagged_fields: TaggedFields,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct EpochEndOffset {
+        pub epoch: i32,
+        pub end_offset: i64,
+        pub tagged_fields: TaggedFields,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct LeaderIdAndEpoch {
+        pub leader_id: i32,
+        pub leader_epoch: i32,
+        pub tagged_fields: TaggedFields,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct SnapshotId {
+        pub end_offset: i64,
+        pub epoch: i32,
+        pub tagged_fields: TaggedFields,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct AbortedTransaction {
+        pub producer_id: i64,
+        pub first_offset: i64,
+        pub tagged_fields: TaggedFields,
     }
 
-    impl super::WireSerialization for super::Fetch {
+    #[derive(Debug, PartialEq)]
+    pub struct NodeEndpoint {
+        pub node_id: i32,
+        pub host: String,
+        pub port: i32,
+        pub rack: Option<String>,
+        pub tagged_fields: TaggedFields,
+    }
+
+    const NODE_ENDPOINTS_TAG: u32 = 0;
+
+    const DIVERGING_EPOCH_TAG: u32 = 0;
+    const CURRENT_LEADER_TAG: u32 = 1;
+    const SNAPSHOT_ID_TAG: u32 = 2;
+
+    fn is_flexible(version: i16) -> bool {
+        model::ApiKey::Fetch.is_flexible(version)
+    }
+
+    impl WireSerialization for super::Fetch {
         // https://kafka.apache.org/protocol.html#The_Messages_Fetch
-        fn to_wire_format(&self, buffer: &mut Vec<u8>) -> () {
-            buffer.put_i8(0); // no tagged fields null marker // TODO: this should not be here?
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let flexible = is_flexible(version);
+            if version >= 1 {
+                buffer.put_i32(self.throttle_time_in_ms);
+            }
+            if version >= 7 {
+                buffer.put_i16(self.error_code);
+                buffer.put_i32(self.session_id);
+            }
+            buffer.write_flexible_array(flexible, &self.topics, |buffer, topic| {
+                topic.to_wire_format(buffer, version)
+            });
+
+            let mut tagged_fields = self.tagged_fields.clone();
+            if let Some(node_endpoints) = &self.node_endpoints {
+                tagged_fields.insert(NODE_ENDPOINTS_TAG, |buffer| {
+                    buffer.write_compact_array(node_endpoints, |buffer, node_endpoint| {
+                        node_endpoint.to_wire_format(buffer, version)
+                    })
+                });
+            }
+            tagged_fields.write_flexible(buffer, flexible);
+        }
+    }
+
+    impl WireDeserialization for super::Fetch {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let flexible = is_flexible(version);
+            let throttle_time_in_ms = if version >= 1 {
+                buffer.read_int32()?
+            } else {
+                0
+            };
+            let (error_code, session_id) = if version >= 7 {
+                (buffer.read_int16()?, buffer.read_int32()?)
+            } else {
+                (0, 0)
+            };
+            let topics = buffer.read_flexible_array(flexible, |buffer| {
+                FetchTopicResponse::from_wire_format(buffer, version)
+            })?;
+
+            let mut tagged_fields = TaggedFields::read_flexible(buffer, flexible)?;
+            let node_endpoints = tagged_fields.take(NODE_ENDPOINTS_TAG, |buffer| {
+                buffer.read_compact_array(|buffer| NodeEndpoint::from_wire_format(buffer, version))
+            })?;
+
+            Ok(super::Fetch {
+                throttle_time_in_ms,
+                error_code,
+                session_id,
+                topics,
+                node_endpoints,
+                tagged_fields,
+            })
+        }
+    }
+
+    impl WireSerialization for FetchTopicResponse {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let flexible = is_flexible(version);
+            if version <= 12 {
+                buffer.write_flexible_string(flexible, &self.topic);
+            } else {
+                buffer.write_uuid(self.topic_id);
+            }
+            buffer.write_flexible_array(flexible, &self.partitions, |buffer, partition| {
+                partition.to_wire_format(buffer, version)
+            });
+            self.tagged_fields.write_flexible(buffer, flexible);
+        }
+    }
+
+    impl WireDeserialization for FetchTopicResponse {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let flexible = is_flexible(version);
+            let (topic, topic_id) = if version <= 12 {
+                (buffer.read_flexible_string(flexible)?, 0)
+            } else {
+                (String::new(), buffer.read_uuid()?)
+            };
+            Ok(FetchTopicResponse {
+                topic,
+                topic_id,
+                partitions: buffer.read_flexible_array(flexible, |buffer| {
+                    PartitionData::from_wire_format(buffer, version)
+                })?,
+                tagged_fields: TaggedFields::read_flexible(buffer, flexible)?,
+            })
+        }
+    }
+
+    impl WireSerialization for PartitionData {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let flexible = is_flexible(version);
+            buffer.put_i32(self.partition_index);
+            buffer.put_i16(self.error_code);
+            buffer.put_i64(self.high_watermark);
+            if version >= 4 {
+                buffer.put_i64(self.last_stable_offset);
+            }
+            if version >= 5 {
+                buffer.put_i64(self.log_start_offset);
+            }
+            if version >= 4 {
+                buffer.write_flexible_nullable_array(
+                    flexible,
+                    self.aborted_transactions.as_deref(),
+                    |buffer, aborted_transaction| {
+                        aborted_transaction.to_wire_format(buffer, version)
+                    },
+                );
+            }
+            if version >= 11 {
+                buffer.put_i32(self.preferred_read_replica);
+            }
+            buffer.write_flexible_nullable_bytes(flexible, self.records.as_deref());
+
+            let mut tagged_fields = self.tagged_fields.clone();
+            if let Some(diverging_epoch) = &self.diverging_epoch {
+                tagged_fields.insert(DIVERGING_EPOCH_TAG, |buffer| {
+                    diverging_epoch.to_wire_format(buffer, version)
+                });
+            }
+            if let Some(current_leader) = &self.current_leader {
+                tagged_fields.insert(CURRENT_LEADER_TAG, |buffer| {
+                    current_leader.to_wire_format(buffer, version)
+                });
+            }
+            if let Some(snapshot_id) = &self.snapshot_id {
+                tagged_fields.insert(SNAPSHOT_ID_TAG, |buffer| {
+                    snapshot_id.to_wire_format(buffer, version)
+                });
+            }
+            tagged_fields.write_flexible(buffer, flexible);
+        }
+    }
+
+    impl WireDeserialization for PartitionData {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let flexible = is_flexible(version);
+            let partition_index = buffer.read_int32()?;
+            let error_code = buffer.read_int16()?;
+            let high_watermark = buffer.read_int64()?;
+            let last_stable_offset = if version >= 4 {
+                buffer.read_int64()?
+            } else {
+                -1
+            };
+            let log_start_offset = if version >= 5 {
+                buffer.read_int64()?
+            } else {
+                -1
+            };
+            let aborted_transactions = if version >= 4 {
+                buffer.read_flexible_nullable_array(flexible, |buffer| {
+                    AbortedTransaction::from_wire_format(buffer, version)
+                })?
+            } else {
+                None
+            };
+            let preferred_read_replica = if version >= 11 {
+                buffer.read_int32()?
+            } else {
+                -1
+            };
+            let records = buffer.read_flexible_nullable_bytes(flexible)?;
+
+            let mut tagged_fields = TaggedFields::read_flexible(buffer, flexible)?;
+            let diverging_epoch = tagged_fields.take(DIVERGING_EPOCH_TAG, |buffer| {
+                EpochEndOffset::from_wire_format(buffer, version)
+            })?;
+            let current_leader = tagged_fields.take(CURRENT_LEADER_TAG, |buffer| {
+                LeaderIdAndEpoch::from_wire_format(buffer, version)
+            })?;
+            let snapshot_id = tagged_fields.take(SNAPSHOT_ID_TAG, |buffer| {
+                SnapshotId::from_wire_format(buffer, version)
+            })?;
+
+            Ok(PartitionData {
+                partition_index,
+                error_code,
+                high_watermark,
+                last_stable_offset,
+                log_start_offset,
+                diverging_epoch,
+                current_leader,
+                snapshot_id,
+                aborted_transactions,
+                preferred_read_replica,
+                records,
+                tagged_fields,
+            })
+        }
+    }
+
+    impl WireSerialization for EpochEndOffset {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, _version: i16) {
+            buffer.put_i32(self.epoch);
+            buffer.put_i64(self.end_offset);
+            self.tagged_fields.write(buffer);
+        }
+    }
+
+    impl WireDeserialization for EpochEndOffset {
+        fn from_wire_format<B: Buf>(buffer: &mut B, _version: i16) -> codec::Result<Self> {
+            Ok(EpochEndOffset {
+                epoch: buffer.read_int32()?,
+                end_offset: buffer.read_int64()?,
+                tagged_fields: TaggedFields::read(buffer)?,
+            })
+        }
+    }
+
+    impl WireSerialization for LeaderIdAndEpoch {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, _version: i16) {
+            buffer.put_i32(self.leader_id);
+            buffer.put_i32(self.leader_epoch);
+            self.tagged_fields.write(buffer);
+        }
+    }
+
+    impl WireDeserialization for LeaderIdAndEpoch {
+        fn from_wire_format<B: Buf>(buffer: &mut B, _version: i16) -> codec::Result<Self> {
+            Ok(LeaderIdAndEpoch {
+                leader_id: buffer.read_int32()?,
+                leader_epoch: buffer.read_int32()?,
+                tagged_fields: TaggedFields::read(buffer)?,
+            })
+        }
+    }
+
+    impl WireSerialization for SnapshotId {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, _version: i16) {
+            buffer.put_i64(self.end_offset);
+            buffer.put_i32(self.epoch);
+            self.tagged_fields.write(buffer);
+        }
+    }
+
+    impl WireDeserialization for SnapshotId {
+        fn from_wire_format<B: Buf>(buffer: &mut B, _version: i16) -> codec::Result<Self> {
+            Ok(SnapshotId {
+                end_offset: buffer.read_int64()?,
+                epoch: buffer.read_int32()?,
+                tagged_fields: TaggedFields::read(buffer)?,
+            })
+        }
+    }
+
+    impl WireSerialization for AbortedTransaction {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            buffer.put_i64(self.producer_id);
+            buffer.put_i64(self.first_offset);
+            self.tagged_fields
+                .write_flexible(buffer, is_flexible(version));
+        }
+    }
+
+    impl WireDeserialization for AbortedTransaction {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            Ok(AbortedTransaction {
+                producer_id: buffer.read_int64()?,
+                first_offset: buffer.read_int64()?,
+                tagged_fields: TaggedFields::read_flexible(buffer, is_flexible(version))?,
+            })
+        }
+    }
+
+    impl WireSerialization for NodeEndpoint {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, _version: i16) {
+            buffer.put_i32(self.node_id);
+            buffer.write_compact_string(&self.host);
+            buffer.put_i32(self.port);
+            buffer.write_compact_nullable_string(self.rack.as_deref());
+            self.tagged_fields.write(buffer);
+        }
+    }
+
+    impl WireDeserialization for NodeEndpoint {
+        fn from_wire_format<B: Buf>(buffer: &mut B, _version: i16) -> codec::Result<Self> {
+            Ok(NodeEndpoint {
+                node_id: buffer.read_int32()?,
+                host: buffer.read_compact_string()?,
+                port: buffer.read_int32()?,
+                rack: buffer.read_compact_nullable_string()?,
+                tagged_fields: TaggedFields::read(buffer)?,
+            })
+        }
+    }
+}
+
+pub mod produce {
+    use bytes::{Buf, BufMut};
+
+    use crate::server::codec;
+    use crate::server::codec::{Decoder, Encoder};
+    use crate::server::model;
+    use crate::server::model::{TaggedFields, WireDeserialization, WireSerialization};
+
+    use super::fetch::{LeaderIdAndEpoch, NodeEndpoint};
+
+    #[derive(Debug, PartialEq)]
+    pub struct TopicProduceResponse {
+        pub name: String,
+        pub partition_responses: Vec<PartitionProduceResponse>,
+        pub tagged_fields: TaggedFields,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct PartitionProduceResponse {
+        pub index: i32,
+        pub error_code: i16,
+        pub base_offset: i64,
+        pub log_append_time_ms: i64,
+        pub log_start_offset: i64,
+        pub record_errors: Vec<BatchIndexAndErrorMessage>,
+        pub error_message: Option<String>,
+        pub current_leader: Option<LeaderIdAndEpoch>,
+        pub tagged_fields: TaggedFields,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct BatchIndexAndErrorMessage {
+        pub batch_index: i32,
+        pub batch_index_error_message: Option<String>,
+        pub tagged_fields: TaggedFields,
+    }
+
+    const NODE_ENDPOINTS_TAG: u32 = 0;
+
+    const CURRENT_LEADER_TAG: u32 = 0;
+
+    fn is_flexible(version: i16) -> bool {
+        model::ApiKey::Produce.is_flexible(version)
+    }
+
+    impl WireSerialization for super::Produce {
+        // https://kafka.apache.org/protocol.html#The_Messages_Produce
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let flexible = is_flexible(version);
+            buffer.write_flexible_array(flexible, &self.responses, |buffer, topic| {
+                topic.to_wire_format(buffer, version)
+            });
+            if version >= 1 {
+                buffer.put_i32(self.throttle_time_in_ms);
+            }
+
+            let mut tagged_fields = self.tagged_fields.clone();
+            if let Some(node_endpoints) = &self.node_endpoints {
+                tagged_fields.insert(NODE_ENDPOINTS_TAG, |buffer| {
+                    buffer.write_compact_array(node_endpoints, |buffer, node_endpoint| {
+                        node_endpoint.to_wire_format(buffer, version)
+                    })
+                });
+            }
+            tagged_fields.write_flexible(buffer, flexible);
+        }
+    }
+
+    impl WireDeserialization for super::Produce {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let flexible = is_flexible(version);
+            let responses = buffer.read_flexible_array(flexible, |buffer| {
+                TopicProduceResponse::from_wire_format(buffer, version)
+            })?;
+            let throttle_time_in_ms = if version >= 1 {
+                buffer.read_int32()?
+            } else {
+                0
+            };
+
+            let mut tagged_fields = TaggedFields::read_flexible(buffer, flexible)?;
+            let node_endpoints = tagged_fields.take(NODE_ENDPOINTS_TAG, |buffer| {
+                buffer.read_compact_array(|buffer| NodeEndpoint::from_wire_format(buffer, version))
+            })?;
+
+            Ok(super::Produce {
+                responses,
+                throttle_time_in_ms,
+                node_endpoints,
+                tagged_fields,
+            })
+        }
+    }
+
+    impl WireSerialization for TopicProduceResponse {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let flexible = is_flexible(version);
+            buffer.write_flexible_string(flexible, &self.name);
+            buffer.write_flexible_array(
+                flexible,
+                &self.partition_responses,
+                |buffer, partition| partition.to_wire_format(buffer, version),
+            );
+            self.tagged_fields.write_flexible(buffer, flexible);
+        }
+    }
+
+    impl WireDeserialization for TopicProduceResponse {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let flexible = is_flexible(version);
+            Ok(TopicProduceResponse {
+                name: buffer.read_flexible_string(flexible)?,
+                partition_responses: buffer.read_flexible_array(flexible, |buffer| {
+                    PartitionProduceResponse::from_wire_format(buffer, version)
+                })?,
+                tagged_fields: TaggedFields::read_flexible(buffer, flexible)?,
+            })
+        }
+    }
+
+    impl WireSerialization for PartitionProduceResponse {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let flexible = is_flexible(version);
+            buffer.put_i32(self.index);
+            buffer.put_i16(self.error_code);
+            buffer.put_i64(self.base_offset);
+            if version >= 2 {
+                buffer.put_i64(self.log_append_time_ms);
+            }
+            if version >= 5 {
+                buffer.put_i64(self.log_start_offset);
+            }
+            if version >= 8 {
+                buffer.write_flexible_array(flexible, &self.record_errors, |buffer, error| {
+                    error.to_wire_format(buffer, version)
+                });
+                buffer.write_flexible_nullable_string(flexible, self.error_message.as_deref());
+            }
+
+            let mut tagged_fields = self.tagged_fields.clone();
+            if let Some(current_leader) = &self.current_leader {
+                tagged_fields.insert(CURRENT_LEADER_TAG, |buffer| {
+                    current_leader.to_wire_format(buffer, version)
+                });
+            }
+            tagged_fields.write_flexible(buffer, flexible);
+        }
+    }
+
+    impl WireDeserialization for PartitionProduceResponse {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let flexible = is_flexible(version);
+            let index = buffer.read_int32()?;
+            let error_code = buffer.read_int16()?;
+            let base_offset = buffer.read_int64()?;
+            let log_append_time_ms = if version >= 2 {
+                buffer.read_int64()?
+            } else {
+                -1
+            };
+            let log_start_offset = if version >= 5 {
+                buffer.read_int64()?
+            } else {
+                -1
+            };
+            let (record_errors, error_message) = if version >= 8 {
+                (
+                    buffer.read_flexible_array(flexible, |buffer| {
+                        BatchIndexAndErrorMessage::from_wire_format(buffer, version)
+                    })?,
+                    buffer.read_flexible_nullable_string(flexible)?,
+                )
+            } else {
+                (vec![], None)
+            };
+
+            let mut tagged_fields = TaggedFields::read_flexible(buffer, flexible)?;
+            let current_leader = tagged_fields.take(CURRENT_LEADER_TAG, |buffer| {
+                LeaderIdAndEpoch::from_wire_format(buffer, version)
+            })?;
+
+            Ok(PartitionProduceResponse {
+                index,
+                error_code,
+                base_offset,
+                log_append_time_ms,
+                log_start_offset,
+                record_errors,
+                error_message,
+                current_leader,
+                tagged_fields,
+            })
+        }
+    }
+
+    impl WireSerialization for BatchIndexAndErrorMessage {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let flexible = is_flexible(version);
+            buffer.put_i32(self.batch_index);
+            buffer.write_flexible_nullable_string(
+                flexible,
+                self.batch_index_error_message.as_deref(),
+            );
+            self.tagged_fields.write_flexible(buffer, flexible);
+        }
+    }
+
+    impl WireDeserialization for BatchIndexAndErrorMessage {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let flexible = is_flexible(version);
+            Ok(BatchIndexAndErrorMessage {
+                batch_index: buffer.read_int32()?,
+                batch_index_error_message: buffer.read_flexible_nullable_string(flexible)?,
+                tagged_fields: TaggedFields::read_flexible(buffer, flexible)?,
+            })
+        }
+    }
+}
+
+pub mod metadata {
+    use bytes::{Buf, BufMut};
+
+    use crate::server::codec;
+    use crate::server::codec::{Decoder, Encoder};
+    use crate::server::model;
+    use crate::server::model::{TaggedFields, WireDeserialization, WireSerialization};
+
+    #[derive(Debug, PartialEq)]
+    pub struct MetadataResponseBroker {
+        pub node_id: i32,
+        pub host: String,
+        pub port: i32,
+        pub rack: Option<String>,
+        pub tagged_fields: TaggedFields,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct MetadataResponseTopic {
+        pub error_code: i16,
+        pub name: Option<String>,
+        pub topic_id: model::Uuid,
+        pub is_internal: bool,
+        pub partitions: Vec<MetadataResponsePartition>,
+        pub topic_authorized_operations: i32,
+        pub tagged_fields: TaggedFields,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct MetadataResponsePartition {
+        pub error_code: i16,
+        pub partition_index: i32,
+        pub leader_id: i32,
+        pub leader_epoch: i32,
+        pub replica_nodes: Vec<i32>,
+        pub isr_nodes: Vec<i32>,
+        pub offline_replicas: Vec<i32>,
+        pub tagged_fields: TaggedFields,
+    }
+
+    fn is_flexible(version: i16) -> bool {
+        model::ApiKey::Metadata.is_flexible(version)
+    }
+
+    impl WireSerialization for super::Metadata {
+        // https://kafka.apache.org/protocol.html#The_Messages_Metadata
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let flexible = is_flexible(version);
+            if version >= 3 {
+                buffer.put_i32(self.throttle_time_in_ms);
+            }
+            buffer.write_flexible_array(flexible, &self.brokers, |buffer, broker| {
+                broker.to_wire_format(buffer, version)
+            });
+            if version >= 2 {
+                buffer.write_flexible_nullable_string(flexible, self.cluster_id.as_deref());
+            }
+            if version >= 1 {
+                buffer.put_i32(self.controller_id);
+            }
+            buffer.write_flexible_array(flexible, &self.topics, |buffer, topic| {
+                topic.to_wire_format(buffer, version)
+            });
+            if (8..=10).contains(&version) {
+                buffer.put_i32(self.cluster_authorized_operations);
+            }
+            self.tagged_fields.write_flexible(buffer, flexible);
+        }
+    }
+
+    impl WireDeserialization for super::Metadata {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let flexible = is_flexible(version);
+            let throttle_time_in_ms = if version >= 3 {
+                buffer.read_int32()?
+            } else {
+                0
+            };
+            let brokers = buffer.read_flexible_array(flexible, |buffer| {
+                MetadataResponseBroker::from_wire_format(buffer, version)
+            })?;
+            let cluster_id = if version >= 2 {
+                buffer.read_flexible_nullable_string(flexible)?
+            } else {
+                None
+            };
+            let controller_id = if version >= 1 {
+                buffer.read_int32()?
+            } else {
+                -1
+            };
+            let topics = buffer.read_flexible_array(flexible, |buffer| {
+                MetadataResponseTopic::from_wire_format(buffer, version)
+            })?;
+            let cluster_authorized_operations = if (8..=10).contains(&version) {
+                buffer.read_int32()?
+            } else {
+                i32::MIN
+            };
+            Ok(super::Metadata {
+                throttle_time_in_ms,
+                brokers,
+                cluster_id,
+                controller_id,
+                topics,
+                cluster_authorized_operations,
+                tagged_fields: TaggedFields::read_flexible(buffer, flexible)?,
+            })
+        }
+    }
+
+    impl WireSerialization for MetadataResponseBroker {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let flexible = is_flexible(version);
+            buffer.put_i32(self.node_id);
+            buffer.write_flexible_string(flexible, &self.host);
+            buffer.put_i32(self.port);
+            if version >= 1 {
+                buffer.write_flexible_nullable_string(flexible, self.rack.as_deref());
+            }
+            self.tagged_fields.write_flexible(buffer, flexible);
+        }
+    }
+
+    impl WireDeserialization for MetadataResponseBroker {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let flexible = is_flexible(version);
+            let node_id = buffer.read_int32()?;
+            let host = buffer.read_flexible_string(flexible)?;
+            let port = buffer.read_int32()?;
+            let rack = if version >= 1 {
+                buffer.read_flexible_nullable_string(flexible)?
+            } else {
+                None
+            };
+            Ok(MetadataResponseBroker {
+                node_id,
+                host,
+                port,
+                rack,
+                tagged_fields: TaggedFields::read_flexible(buffer, flexible)?,
+            })
+        }
+    }
+
+    impl WireSerialization for MetadataResponseTopic {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let flexible = is_flexible(version);
+            buffer.put_i16(self.error_code);
+            if version >= 12 {
+                buffer.write_flexible_nullable_string(flexible, self.name.as_deref());
+            } else {
+                buffer.write_flexible_string(flexible, self.name.as_deref().unwrap_or_default());
+            }
+            if version >= 10 {
+                buffer.write_uuid(self.topic_id);
+            }
+            if version >= 1 {
+                buffer.write_boolean(self.is_internal);
+            }
+            buffer.write_flexible_array(flexible, &self.partitions, |buffer, partition| {
+                partition.to_wire_format(buffer, version)
+            });
+            if version >= 8 {
+                buffer.put_i32(self.topic_authorized_operations);
+            }
+            self.tagged_fields.write_flexible(buffer, flexible);
+        }
+    }
+
+    impl WireDeserialization for MetadataResponseTopic {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let flexible = is_flexible(version);
+            let error_code = buffer.read_int16()?;
+            let name = if version >= 12 {
+                buffer.read_flexible_nullable_string(flexible)?
+            } else {
+                Some(buffer.read_flexible_string(flexible)?)
+            };
+            let topic_id = if version >= 10 {
+                buffer.read_uuid()?
+            } else {
+                0
+            };
+            let is_internal = if version >= 1 {
+                buffer.read_boolean()?
+            } else {
+                false
+            };
+            let partitions = buffer.read_flexible_array(flexible, |buffer| {
+                MetadataResponsePartition::from_wire_format(buffer, version)
+            })?;
+            let topic_authorized_operations = if version >= 8 {
+                buffer.read_int32()?
+            } else {
+                i32::MIN
+            };
+            Ok(MetadataResponseTopic {
+                error_code,
+                name,
+                topic_id,
+                is_internal,
+                partitions,
+                topic_authorized_operations,
+                tagged_fields: TaggedFields::read_flexible(buffer, flexible)?,
+            })
+        }
+    }
+
+    impl WireSerialization for MetadataResponsePartition {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            let flexible = is_flexible(version);
+            buffer.put_i16(self.error_code);
+            buffer.put_i32(self.partition_index);
+            buffer.put_i32(self.leader_id);
+            if version >= 7 {
+                buffer.put_i32(self.leader_epoch);
+            }
+            for nodes in [&self.replica_nodes, &self.isr_nodes] {
+                buffer.write_flexible_array(flexible, nodes, |buffer, node| buffer.put_i32(*node));
+            }
+            if version >= 5 {
+                buffer.write_flexible_array(flexible, &self.offline_replicas, |buffer, node| {
+                    buffer.put_i32(*node)
+                });
+            }
+            self.tagged_fields.write_flexible(buffer, flexible);
+        }
+    }
+
+    impl WireDeserialization for MetadataResponsePartition {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            let flexible = is_flexible(version);
+            let error_code = buffer.read_int16()?;
+            let partition_index = buffer.read_int32()?;
+            let leader_id = buffer.read_int32()?;
+            let leader_epoch = if version >= 7 {
+                buffer.read_int32()?
+            } else {
+                -1
+            };
+            let replica_nodes =
+                buffer.read_flexible_array(flexible, |buffer| buffer.read_int32())?;
+            let isr_nodes = buffer.read_flexible_array(flexible, |buffer| buffer.read_int32())?;
+            let offline_replicas = if version >= 5 {
+                buffer.read_flexible_array(flexible, |buffer| buffer.read_int32())?
+            } else {
+                vec![]
+            };
+            Ok(MetadataResponsePartition {
+                error_code,
+                partition_index,
+                leader_id,
+                leader_epoch,
+                replica_nodes,
+                isr_nodes,
+                offline_replicas,
+                tagged_fields: TaggedFields::read_flexible(buffer, flexible)?,
+            })
+        }
+    }
+}
+
+pub mod describe_topic_partitions {
+    use bytes::{Buf, BufMut};
+
+    use crate::server::codec;
+    use crate::server::codec::{Decoder, Encoder};
+    use crate::server::model;
+    use crate::server::model::{TaggedFields, WireDeserialization, WireSerialization};
+
+    pub use crate::server::requests::describe_topic_partitions::Cursor;
+
+    #[derive(Debug, PartialEq)]
+    pub struct DescribeTopicPartitionsResponseTopic {
+        pub error_code: i16,
+        pub name: Option<String>,
+        pub topic_id: model::Uuid,
+        pub is_internal: bool,
+        pub partitions: Vec<DescribeTopicPartitionsResponsePartition>,
+        pub topic_authorized_operations: i32,
+        pub tagged_fields: TaggedFields,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct DescribeTopicPartitionsResponsePartition {
+        pub error_code: i16,
+        pub partition_index: i32,
+        pub leader_id: i32,
+        pub leader_epoch: i32,
+        pub replica_nodes: Vec<i32>,
+        pub isr_nodes: Vec<i32>,
+        pub eligible_leader_replicas: Option<Vec<i32>>,
+        pub last_known_elr: Option<Vec<i32>>,
+        pub offline_replicas: Vec<i32>,
+        pub tagged_fields: TaggedFields,
+    }
+
+    impl WireSerialization for super::DescribeTopicPartitions {
+        // https://kafka.apache.org/protocol.html#The_Messages_DescribeTopicPartitions
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
             buffer.put_i32(self.throttle_time_in_ms);
-            buffer.put_i16(0); // error code, 0 for now
-            buffer.put_i32(self.session_id);
-            buffer.put_u8(self.topics.len() as u8 + 1);
-            for topic in &self.topics {
-                buffer.put_u128(topic.topic_id);
-                let partition_length = 1;
-                for _ in 0..partition_length {
-                    buffer.put_i8(partition_length + 1);
-                    buffer.put_i32(0); // Index
-                    let error_code = if topic.topic_id >= 302240678275694148452352 {
-                        0 // hardcoded no error for stage11, TODO: Once instructions are there, I should create a real logic
-                    } else {
-                        100
-                    };
-                    buffer.put_i16(error_code);
-                    buffer.put_i64(0);
-                    buffer.put_i64(0);
-                    buffer.put_i64(0);
-                    buffer.put_i8(0);
-                    buffer.put_i32(0);
-                    buffer.put_i8(1);
-                    buffer.put_i8(0); // TAG_BUFFER partitions
-                }
-                buffer.put_i8(0); // TAG_BUFFER topic
-            }
-            buffer.put_i8(0); // no tagged fields, null marker
+            buffer.write_compact_array(&self.topics, |buffer, topic| {
+                topic.to_wire_format(buffer, version)
+            });
+            Cursor::write_nullable(self.next_cursor.as_ref(), buffer, version);
+            self.tagged_fields.write(buffer);
+        }
+    }
+
+    impl WireDeserialization for super::DescribeTopicPartitions {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            Ok(super::DescribeTopicPartitions {
+                throttle_time_in_ms: buffer.read_int32()?,
+                topics: buffer.read_compact_array(|buffer| {
+                    DescribeTopicPartitionsResponseTopic::from_wire_format(buffer, version)
+                })?,
+                next_cursor: Cursor::read_nullable(buffer, version)?,
+                tagged_fields: TaggedFields::read(buffer)?,
+            })
+        }
+    }
+
+    impl WireSerialization for DescribeTopicPartitionsResponseTopic {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, version: i16) {
+            buffer.put_i16(self.error_code);
+            buffer.write_compact_nullable_string(self.name.as_deref());
+            buffer.write_uuid(self.topic_id);
+            buffer.write_boolean(self.is_internal);
+            buffer.write_compact_array(&self.partitions, |buffer, partition| {
+                partition.to_wire_format(buffer, version)
+            });
+            buffer.put_i32(self.topic_authorized_operations);
+            self.tagged_fields.write(buffer);
+        }
+    }
+
+    impl WireDeserialization for DescribeTopicPartitionsResponseTopic {
+        fn from_wire_format<B: Buf>(buffer: &mut B, version: i16) -> codec::Result<Self> {
+            Ok(DescribeTopicPartitionsResponseTopic {
+                error_code: buffer.read_int16()?,
+                name: buffer.read_compact_nullable_string()?,
+                topic_id: buffer.read_uuid()?,
+                is_internal: buffer.read_boolean()?,
+                partitions: buffer.read_compact_array(|buffer| {
+                    DescribeTopicPartitionsResponsePartition::from_wire_format(buffer, version)
+                })?,
+                topic_authorized_operations: buffer.read_int32()?,
+                tagged_fields: TaggedFields::read(buffer)?,
+            })
+        }
+    }
+
+    impl WireSerialization for DescribeTopicPartitionsResponsePartition {
+        fn to_wire_format(&self, buffer: &mut Vec<u8>, _version: i16) {
+            buffer.put_i16(self.error_code);
+            buffer.put_i32(self.partition_index);
+            buffer.put_i32(self.leader_id);
+            buffer.put_i32(self.leader_epoch);
+            for nodes in [&self.replica_nodes, &self.isr_nodes] {
+                buffer.write_compact_array(nodes, |buffer, node| buffer.put_i32(*node));
+            }
+            for nodes in [&self.eligible_leader_replicas, &self.last_known_elr] {
+                buffer.write_compact_nullable_array(nodes.as_deref(), |buffer, node| {
+                    buffer.put_i32(*node)
+                });
+            }
+            buffer
+                .write_compact_array(&self.offline_replicas, |buffer, node| buffer.put_i32(*node));
+            self.tagged_fields.write(buffer);
+        }
+    }
+
+    impl WireDeserialization for DescribeTopicPartitionsResponsePartition {
+        fn from_wire_format<B: Buf>(buffer: &mut B, _version: i16) -> codec::Result<Self> {
+            Ok(DescribeTopicPartitionsResponsePartition {
+                error_code: buffer.read_int16()?,
+                partition_index: buffer.read_int32()?,
+                leader_id: buffer.read_int32()?,
+                leader_epoch: buffer.read_int32()?,
+                replica_nodes: buffer.read_compact_array(|buffer| buffer.read_int32())?,
+                isr_nodes: buffer.read_compact_array(|buffer| buffer.read_int32())?,
+                eligible_leader_replicas: buffer
+                    .read_compact_nullable_array(|buffer| buffer.read_int32())?,
+                last_known_elr: buffer.read_compact_nullable_array(|buffer| buffer.read_int32())?,
+                offline_replicas: buffer.read_compact_array(|buffer| buffer.read_int32())?,
+                tagged_fields: TaggedFields::read(buffer)?,
+            })
         }
     }
 }
 
+#[cfg(test)]
 mod tests {
-    use fetch::FetchTopicResponse;
+    use api_versions::{FinalizedFeatureKey, SupportedFeatureKey};
+    use fetch::{FetchTopicResponse, LeaderIdAndEpoch, NodeEndpoint, PartitionData};
+    use produce::{BatchIndexAndErrorMessage, PartitionProduceResponse, TopicProduceResponse};
 
     use super::*;
+    use crate::server::model::{ApiKeyVariant, TaggedFields, WireDeserialization};
+
+    fn api_versions_response() -> ApiVersions {
+        ApiVersions {
+            error_code: 0,
+            api_key_versions: vec![ApiKeyVariant::Fetch.into(), ApiKeyVariant::Versions.into()],
+            throttle_time_in_ms: 0,
+            supported_features: Some(vec![SupportedFeatureKey {
+                name: "metadata.version".to_string(),
+                min_version: 1,
+                max_version: 20,
+                tagged_fields: TaggedFields::default(),
+            }]),
+            finalized_features_epoch: None,
+            finalized_features: None,
+            zk_migration_ready: None,
+            tagged_fields: TaggedFields::default(),
+        }
+    }
 
     #[test]
-    fn test_api_version_to_wire_format() {
+    fn test_response_header_versions() {
+        let header = ResponseHeader {
+            correlation_id: 7,
+            tagged_fields: TaggedFields::default(),
+        };
         let mut buffer = vec![];
-        let api_versions_response = ApiVersions {
-            api_key_versions: vec![
-                super::model::ApiKeyVariant::Fetch,
-                super::model::ApiKeyVariant::Versions,
-            ],
+        header.write(&mut buffer, model::HeaderVersion(0));
+        assert_eq!(buffer, vec![0, 0, 0, 7]);
+
+        let mut buffer = vec![];
+        header.write(&mut buffer, model::HeaderVersion(1));
+        assert_eq!(buffer, vec![0, 0, 0, 7, 0]);
+    }
+
+    fn fetch_response() -> Fetch {
+        Fetch {
             throttle_time_in_ms: 0,
-        };
+            error_code: 0,
+            session_id: 0,
+            topics: vec![FetchTopicResponse {
+                topic: String::new(),
+                topic_id: 17,
+                partitions: vec![PartitionData {
+                    partition_index: 0,
+                    error_code: 100,
+                    high_watermark: 0,
+                    last_stable_offset: 0,
+                    log_start_offset: 0,
+                    diverging_epoch: None,
+                    current_leader: None,
+                    snapshot_id: None,
+                    aborted_transactions: None,
+                    preferred_read_replica: 0,
+                    records: Some(vec![]),
+                    tagged_fields: TaggedFields::default(),
+                }],
+                tagged_fields: TaggedFields::default(),
+            }],
+            node_endpoints: None,
+            tagged_fields: TaggedFields::default(),
+        }
+    }
+
+    #[test]
+    fn test_api_version_to_wire_format() {
+        let mut buffer = vec![];
+        api_versions_response().to_wire_format(&mut buffer, 4);
+
+        let mut expected = vec![
+            0, 0, 3, 0, 1, 0, 0, 0, 16, 0, 0, 18, 0, 0, 0, 4, 0, 0, 0, 0, 0,
+        ];
+        expected.extend_from_slice(&[1, 0, 23, 2, 17]); // SupportedFeatures tag
+        expected.extend_from_slice(b"metadata.version");
+        expected.extend_from_slice(&[0, 1, 0, 20, 0]);
+        assert_eq!(buffer, expected);
+    }
 
-        api_versions_response.to_wire_format(&mut buffer);
+    #[test]
+    fn test_api_version_v0_to_wire_format() {
+        let mut buffer = vec![];
+        api_versions_response().to_wire_format(&mut buffer, 0);
 
         assert_eq!(
             buffer,
-            vec![0, 0, 3, 0, 1, 0, 0, 0, 16, 0, 0, 18, 0, 1, 0, 4, 0, 0, 0, 0, 0, 0]
+            vec![0, 0, 0, 0, 0, 2, 0, 1, 0, 0, 0, 16, 0, 18, 0, 0, 0, 4]
         );
     }
 
+    #[test]
+    fn test_api_version_round_trip() {
+        let mut buffer = vec![];
+        api_versions_response().to_wire_format(&mut buffer, 4);
+
+        let decoded = ApiVersions::from_wire_format(&mut buffer.as_slice(), 4);
+        assert_eq!(decoded, Ok(api_versions_response()));
+    }
+
+    #[test]
+    fn test_api_version_round_trip_with_finalized_features() {
+        let response = ApiVersions {
+            finalized_features_epoch: Some(7),
+            finalized_features: Some(vec![FinalizedFeatureKey {
+                name: "metadata.version".to_string(),
+                max_version_level: 20,
+                min_version_level: 20,
+                tagged_fields: TaggedFields::default(),
+            }]),
+            zk_migration_ready: Some(false),
+            ..api_versions_response()
+        };
+        let mut buffer = vec![];
+        response.to_wire_format(&mut buffer, 3);
+
+        let decoded = ApiVersions::from_wire_format(&mut buffer.as_slice(), 3);
+        assert_eq!(decoded, Ok(response));
+    }
+
     #[test]
     fn test_fetch_response_to_wire_format() {
         let mut buffer = vec![];
-        let response = super::Response::Fetch(Fetch {
-            throttle_time_in_ms: 0,
-            session_id: 0,
-            topics: vec![FetchTopicResponse { topic_id: 17 }],
-        });
-        response.to_wire_format(&mut buffer);
+        let response = super::Response::Fetch(fetch_response());
+        response.to_wire_format(&mut buffer, 16);
 
         assert_eq!(
             buffer,
             vec![
-                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                17, 2, 0, 0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 17,
+                2, 0, 0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0
             ]
         );
     }
+
+    #[test]
+    fn test_fetch_response_by_topic_name_round_trip() {
+        for version in [0, 1, 4, 5, 7, 11, 12] {
+            let response = Fetch {
+                throttle_time_in_ms: if version >= 1 { 10 } else { 0 },
+                error_code: 0,
+                session_id: if version >= 7 { 3 } else { 0 },
+                topics: vec![FetchTopicResponse {
+                    topic: "orders".to_string(),
+                    topic_id: 0,
+                    partitions: vec![PartitionData {
+                        partition_index: 1,
+                        error_code: 0,
+                        high_watermark: 10,
+                        last_stable_offset: if version >= 4 { 10 } else { -1 },
+                        log_start_offset: if version >= 5 { 0 } else { -1 },
+                        diverging_epoch: None,
+                        current_leader: None,
+                        snapshot_id: None,
+                        aborted_transactions: None,
+                        preferred_read_replica: -1,
+                        records: Some(vec![1, 2, 3]),
+                        tagged_fields: TaggedFields::default(),
+                    }],
+                    tagged_fields: TaggedFields::default(),
+                }],
+                node_endpoints: None,
+                tagged_fields: TaggedFields::default(),
+            };
+
+            let mut buffer = vec![];
+            response.to_wire_format(&mut buffer, version);
+
+            let decoded = Fetch::from_wire_format(&mut buffer.as_slice(), version);
+            assert_eq!(decoded, Ok(response), "version {}", version);
+        }
+    }
+
+    #[test]
+    fn test_fetch_response_round_trip() {
+        let mut buffer = vec![];
+        fetch_response().to_wire_format(&mut buffer, 16);
+
+        let decoded = Fetch::from_wire_format(&mut buffer.as_slice(), 16);
+        assert_eq!(decoded, Ok(fetch_response()));
+    }
+
+    fn produce_response() -> Produce {
+        Produce {
+            responses: vec![TopicProduceResponse {
+                name: "foo".to_string(),
+                partition_responses: vec![PartitionProduceResponse {
+                    index: 0,
+                    error_code: 2,
+                    base_offset: -1,
+                    log_append_time_ms: -1,
+                    log_start_offset: 0,
+                    record_errors: vec![BatchIndexAndErrorMessage {
+                        batch_index: 0,
+                        batch_index_error_message: Some("invalid crc".to_string()),
+                        tagged_fields: TaggedFields::default(),
+                    }],
+                    error_message: Some("corrupt record batch".to_string()),
+                    current_leader: Some(LeaderIdAndEpoch {
+                        leader_id: 1,
+                        leader_epoch: 0,
+                        tagged_fields: TaggedFields::default(),
+                    }),
+                    tagged_fields: TaggedFields::default(),
+                }],
+                tagged_fields: TaggedFields::default(),
+            }],
+            throttle_time_in_ms: 0,
+            node_endpoints: Some(vec![NodeEndpoint {
+                node_id: 1,
+                host: "localhost".to_string(),
+                port: 9092,
+                rack: None,
+                tagged_fields: TaggedFields::default(),
+            }]),
+            tagged_fields: TaggedFields::default(),
+        }
+    }
+
+    #[test]
+    fn test_produce_response_round_trip() {
+        let mut buffer = vec![];
+        produce_response().to_wire_format(&mut buffer, 11);
+
+        let decoded = Produce::from_wire_format(&mut buffer.as_slice(), 11);
+        assert_eq!(decoded, Ok(produce_response()));
+    }
+
+    #[test]
+    fn test_produce_response_v3_to_wire_format() {
+        let mut buffer = vec![];
+        produce_response().to_wire_format(&mut buffer, 3);
+
+        let mut expected = vec![0, 0, 0, 1, 0, 3];
+        expected.extend_from_slice(b"foo");
+        expected.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 2]);
+        expected.extend_from_slice(&(-1i64).to_be_bytes());
+        expected.extend_from_slice(&(-1i64).to_be_bytes());
+        expected.extend_from_slice(&[0, 0, 0, 0]);
+        assert_eq!(buffer, expected);
+    }
+
+    fn metadata_response(version: i16) -> Metadata {
+        Metadata {
+            throttle_time_in_ms: 0,
+            brokers: vec![metadata::MetadataResponseBroker {
+                node_id: 1,
+                host: "localhost".to_string(),
+                port: 9092,
+                rack: None,
+                tagged_fields: TaggedFields::default(),
+            }],
+            cluster_id: (version >= 2).then(|| "cluster".to_string()),
+            controller_id: if version >= 1 { 1 } else { -1 },
+            topics: vec![metadata::MetadataResponseTopic {
+                error_code: 0,
+                name: Some("foo".to_string()),
+                topic_id: if version >= 10 { 17 } else { 0 },
+                is_internal: false,
+                partitions: vec![metadata::MetadataResponsePartition {
+                    error_code: 0,
+                    partition_index: 0,
+                    leader_id: 1,
+                    leader_epoch: if version >= 7 { 0 } else { -1 },
+                    replica_nodes: vec![1],
+                    isr_nodes: vec![1],
+                    offline_replicas: vec![],
+                    tagged_fields: TaggedFields::default(),
+                }],
+                topic_authorized_operations: if version >= 8 { 3576 } else { i32::MIN },
+                tagged_fields: TaggedFields::default(),
+            }],
+            cluster_authorized_operations: if (8..=10).contains(&version) {
+                8096
+            } else {
+                i32::MIN
+            },
+            tagged_fields: TaggedFields::default(),
+        }
+    }
+
+    #[test]
+    fn test_metadata_response_round_trip() {
+        for version in 0..=12 {
+            let mut buffer = vec![];
+            metadata_response(version).to_wire_format(&mut buffer, version);
+
+            let decoded = Metadata::from_wire_format(&mut buffer.as_slice(), version);
+            assert_eq!(
+                decoded,
+                Ok(metadata_response(version)),
+                "version {}",
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn test_metadata_response_v0_to_wire_format() {
+        let mut buffer = vec![];
+        metadata_response(0).to_wire_format(&mut buffer, 0);
+
+        let mut expected = vec![0, 0, 0, 1, 0, 0, 0, 1, 0, 9];
+        expected.extend_from_slice(b"localhost");
+        expected.extend_from_slice(&[0, 0, 35, 132, 0, 0, 0, 1, 0, 0, 0, 3]);
+        expected.extend_from_slice(b"foo");
+        expected.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        expected.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1]);
+        assert_eq!(buffer, expected);
+    }
+
+    fn describe_topic_partitions_response() -> DescribeTopicPartitions {
+        DescribeTopicPartitions {
+            throttle_time_in_ms: 0,
+            topics: vec![
+                describe_topic_partitions::DescribeTopicPartitionsResponseTopic {
+                    error_code: 0,
+                    name: Some("foo".to_string()),
+                    topic_id: 17,
+                    is_internal: false,
+                    partitions: vec![
+                        describe_topic_partitions::DescribeTopicPartitionsResponsePartition {
+                            error_code: 0,
+                            partition_index: 0,
+                            leader_id: 1,
+                            leader_epoch: 2,
+                            replica_nodes: vec![1, 2],
+                            isr_nodes: vec![1],
+                            eligible_leader_replicas: Some(vec![2]),
+                            last_known_elr: None,
+                            offline_replicas: vec![],
+                            tagged_fields: TaggedFields::default(),
+                        },
+                    ],
+                    topic_authorized_operations: 3576,
+                    tagged_fields: TaggedFields::default(),
+                },
+            ],
+            next_cursor: Some(describe_topic_partitions::Cursor {
+                topic_name: "foo".to_string(),
+                partition_index: 1,
+                tagged_fields: TaggedFields::default(),
+            }),
+            tagged_fields: TaggedFields::default(),
+        }
+    }
+
+    #[test]
+    fn test_describe_topic_partitions_response_to_wire_format() {
+        let mut buffer = vec![];
+        describe_topic_partitions_response().to_wire_format(&mut buffer, 0);
+
+        let mut expected = vec![0, 0, 0, 0, 2, 0, 0, 4];
+        expected.extend_from_slice(b"foo");
+        expected.extend_from_slice(&17u128.to_be_bytes());
+        expected.extend_from_slice(&[0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 2]);
+        expected.extend_from_slice(&[3, 0, 0, 0, 1, 0, 0, 0, 2, 2, 0, 0, 0, 1]);
+        expected.extend_from_slice(&[2, 0, 0, 0, 2, 0, 1, 0]);
+        expected.extend_from_slice(&[0, 0, 13, 248, 0]);
+        expected.extend_from_slice(&[1, 4]);
+        expected.extend_from_slice(b"foo");
+        expected.extend_from_slice(&[0, 0, 0, 1, 0, 0]);
+        assert_eq!(buffer, expected);
+
+        let decoded = DescribeTopicPartitions::from_wire_format(&mut buffer.as_slice(), 0);
+        assert_eq!(decoded, Ok(describe_topic_partitions_response()));
+    }
 }